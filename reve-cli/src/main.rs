@@ -1,5 +1,8 @@
 use reve_shared::*;
 
 fn main() {
-    prepare();
+    if let Err(e) = prepare() {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
 }
@@ -1,69 +1,131 @@
+use crate::jobs::JobRegistry;
+use crate::progress::WindowProgress;
 use crate::utils;
 use reve_shared::*;
-use std::io::Write;
-use std::process::Stdio;
-use tauri::api::process::{Command, CommandEvent};
-use tauri::Window;
-use std::io::BufRead;
+use std::path::Path;
+use std::sync::Arc;
+use tauri::{State, Window};
 
+/// Upscales `path` into `save_path` with the same pipeline as the CLI, on a blocking thread so
+/// `cancel_job`, `pause_job` and `resume_job` can be handled while it runs. The encoder settings
+/// and upscaler of `profile` replace those of the configuration.
+// the arguments are the fields the frontend sends
+#[allow(clippy::too_many_arguments)]
 #[tauri::command]
-pub fn upscale_video(
+pub async fn upscale_video(
     path: String,
     save_path: String,
     upscale_factor: u8,
     upscale_type: String,
     upscale_codec: String,
     window: Window,
+    jobs: State<'_, JobRegistry>,
     segment_size: u32,
+    upscaler: Option<String>,
+    profile: Option<String>,
 ) -> Result<String, String> {
+    let config = utils::job_configuration()?;
+    let (config, upscaler) = match profile {
+        Some(name) => {
+            let profile = find_profile(&name)?;
+            (
+                config.with_profile(&profile.settings),
+                upscaler.or(profile.settings.upscaler),
+            )
+        }
+        None => (config, upscaler),
+    };
+    let upscaler = upscaler.unwrap_or_else(|| String::from("realesrgan"));
     let upscale_information = format!(
-        "-> Video: {}\n-> Save path: {}\n-> Upscale factor: {}\n-> Upscale type: {}\n-> Upscale codec: {}\n-> Segment size: {}",
-        &path, &save_path, &upscale_factor, &upscale_type, &upscale_codec, &segment_size
+        "-> Video: {}\n-> Save path: {}\n-> Upscale factor: {}\n-> Upscale type: {}\n-> Upscale codec: {}\n-> Segment size: {}\n-> Upscaler: {}",
+        &path, &save_path, &upscale_factor, &upscale_type, &upscale_codec, &segment_size, &upscaler
     );
     println!("{}", &upscale_information);
     utils::write_log(&upscale_information);
 
-    // use Video::new to create a new Video object
-    let mut video = Video::new(&path, &save_path, segment_size, upscale_factor);
+    output_validation(&save_path)?;
+    let encoder = encoder_name(&upscale_codec);
+    if get_encoder_profile(encoder).is_none() {
+        return Err(format!("{} is not a supported codec", upscale_codec));
+    }
+    let format = Path::new(&save_path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("mp4");
+    let spec = JobSpec::new(&path)
+        .output(&save_path)
+        .model(&upscale_type)
+        .scale(upscale_factor)
+        .upscaler(&upscaler, "done")
+        .encoder(encoder)
+        .format(format)
+        .segment_size(segment_size);
+    let spec = config.apply(spec);
 
-    for segment in &video.segments {
-        // export the frames of the segment and cout the number of frames in output folder
-        let export_result = Video::export_segment(&video, segment.index as usize);
-        if export_result.is_err() {
-            utils::write_log(&format!("Failed to export segment {}.", segment.index));
-            return Err(export_result.err().unwrap().to_string());
-        } else {
-            utils::write_log(&format!("Exported segment {}.", segment.index));
-        }
+    let control = jobs.start(&path)?;
+    let pipeline = Pipeline::new(spec)
+        .progress(Arc::new(WindowProgress::new(window)))
+        .control(control);
+    let result = tauri::async_runtime::spawn_blocking(move || pipeline.run()).await;
+    jobs.finish(&path);
 
-        let upscale_result = Video::upscale_segment(&video, segment.index as usize);
-        if upscale_result.is_err() {
-            utils::write_log(&format!("Failed to upscale segment {}.", segment.index));
-            return Err(upscale_result.err().unwrap().to_string());
-        } else {
-            utils::write_log(&format!("Upscaled segment {}.", segment.index));
+    match result {
+        Ok(Ok(())) => {
+            utils::write_log(&format!("Upscaled {} to {}.", path, save_path));
+            Ok("Upscaling finished!".to_string())
         }
-
-        // read upscale_result and count lines that contain "done"
-        let mut upscale_done = 0;
-        for line in upscale_result.unwrap().lines() {
-            if line.unwrap().contains("done") {
-                upscale_done += 1;
-            }
+        Ok(Err(ReveError::Cancelled)) => {
+            utils::write_log(&format!("Cancelled upscale of {}.", path));
+            Err(ReveError::Cancelled.to_string())
+        }
+        Ok(Err(e)) => {
+            utils::write_log(&format!("Failed to upscale {}: {}", path, e));
+            Err(e.to_string())
         }
+        Err(e) => Err(e.to_string()),
+    }
+}
 
-        // write upscale_done to the log file
-        utils::write_log(&format!("Upscaled {} frames.", upscale_done));
+/// The built-in profiles and those of the profiles file shared with the CLI.
+#[tauri::command]
+pub fn list_profiles() -> Result<Vec<Profile>, String> {
+    Ok(Profiles::load().map_err(|e| e.to_string())?.list().to_vec())
+}
+
+pub(crate) fn find_profile(name: &str) -> Result<Profile, String> {
+    let profiles = Profiles::load().map_err(|e| e.to_string())?;
+    profiles
+        .get(name)
+        .cloned()
+        .ok_or_else(|| format!("unknown profile {}, valid: {}", name, profiles.names()))
+}
+
+/// Encoder profile name of a codec picked in the frontend, older configurations store the
+/// short names.
+pub(crate) fn encoder_name(codec: &str) -> &str {
+    match codec {
+        "x265" => "libx265",
+        "av1" => "libsvtav1",
+        codec => codec,
     }
+}
 
-    // print the number of segments
-    println!("Number of segments: {}", video.segments.len());
+/// Kills the processes of the upscale of `path`, its finished segments stay in the workspace.
+#[tauri::command]
+pub fn cancel_job(path: String, jobs: State<JobRegistry>) -> Result<(), String> {
+    jobs.get(&path)?.cancel();
+    Ok(())
+}
 
-    Ok("Upscaling finished!".to_string())
+/// Suspends the processes of the upscale of `path` and holds it before the next step.
+#[tauri::command]
+pub fn pause_job(path: String, jobs: State<JobRegistry>) -> Result<(), String> {
+    jobs.get(&path)?.pause();
+    Ok(())
 }
 
 #[tauri::command]
-// function to update the progress bar in the GUI
-pub fn update_progress_bar(progress: f64) {
-    println!("Progress: {}", progress);
-}
\ No newline at end of file
+pub fn resume_job(path: String, jobs: State<JobRegistry>) -> Result<(), String> {
+    jobs.get(&path)?.resume();
+    Ok(())
+}
@@ -1,12 +1,24 @@
-use std::{env::current_dir, error::Error, path::PathBuf};
+use std::{env::current_dir, error::Error, fmt, fs, io::ErrorKind, path::Path, path::PathBuf};
 
+use reve_shared::{
+    codec_validation, format_validation, max_resolution_validation, model_validation,
+    preset_validation, JobSpec, ReveConfig,
+};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 
 pub const LOG_FILE: &str = "reve-gui.log";
-const CONFIG_FILE: &str = "reve-gui-config.json";
+/// GUI only config file older versions kept in the working directory
+const LEGACY_CONFIG_FILE: &str = "reve-gui-config.json";
+
+/// Version of the config file layout, older files are migrated to it when loaded.
+pub const CONFIG_VERSION: u32 = 2;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct ConfigData {
+    #[serde(rename = "config-version")]
+    config_version: u32,
+
     #[serde(rename = "application-logs")]
     application_logs: bool,
 
@@ -22,33 +34,162 @@ pub struct ConfigData {
     #[serde(rename = "default-segment-size")]
     default_segment_size: u32,
 
-    //#[serde(rename = "default-output-directory")]
-    //default_output_directory: String,
+    /// None writes the upscaled video next to its input
+    #[serde(rename = "default-output-directory")]
+    default_output_directory: Option<String>,
+
+    #[serde(rename = "default-crf")]
+    default_crf: u8,
+
+    #[serde(rename = "default-preset")]
+    default_preset: String,
+
+    #[serde(rename = "default-encoder-params")]
+    default_encoder_params: String,
+
+    /// videos of a folder taller than this are skipped
+    #[serde(rename = "default-max-resolution")]
+    default_max_resolution: u32,
+
+    #[serde(rename = "default-output-container")]
+    default_output_container: String,
+
+    /// None uses the reve default, /dev/shm on Linux
+    #[serde(rename = "workspace-directory")]
+    workspace_directory: Option<String>,
+}
+
+/// A config value that can't be used, `field` is its key in the config file.
+#[derive(Serialize, Clone, Debug)]
+pub struct ConfigFieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigFieldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+/// The settings of the config file, with the values of it that can't be used.
+#[derive(Serialize, Clone)]
+pub struct LoadedConfig {
+    pub config: ConfigData,
+    pub errors: Vec<ConfigFieldError>,
 }
 
 impl ConfigData {
     /// Returns a default configuration.
     pub fn default() -> ConfigData {
         Self {
+            config_version: CONFIG_VERSION,
             application_logs: true,
             default_upscale_type: String::from("realesr-animevideov3"),
             default_upscale_factor: 2,
             default_upscale_codec: String::from("libx265"),
             default_segment_size: 1000,
+            default_output_directory: None,
+            default_crf: 15,
+            default_preset: String::from("slow"),
+            default_encoder_params: String::from("psy-rd=2:aq-strength=1:deblock=0,0:bframes=8"),
+            default_max_resolution: 480,
+            default_output_container: String::from("mp4"),
+            workspace_directory: None,
         }
     }
 
-    /// Validates the `ConfigData` struct.
-    fn validate_config(&self) -> Result<(), Box<dyn Error>> {
-        if [
-            String::from("realesr-animevideov3"),
-            String::from("realesr-realvideo"),
+    /// Validates every field of the `ConfigData` struct, returning one error per invalid field.
+    /// Whether the folders exist is left to [`ConfigData::check_directories`].
+    pub fn validate_config(&self) -> Result<(), Vec<ConfigFieldError>> {
+        let mut errors = Vec::new();
+        let mut check = |field: &str, result: Result<(), String>| {
+            if let Err(message) = result {
+                errors.push(ConfigFieldError {
+                    field: field.to_string(),
+                    message,
+                });
+            }
+        };
+
+        check(
+            "config-version",
+            match self.config_version {
+                CONFIG_VERSION => Ok(()),
+                version => Err(format!("unsupported config version {}", version)),
+            },
+        );
+        check(
+            "default-upscale-type",
+            model_validation(&self.default_upscale_type).map(|_| ()),
+        );
+        check(
+            "default-upscale-factor",
+            match self.default_upscale_factor {
+                2..=4 => Ok(()),
+                _ => Err(String::from("valid: 2/3/4")),
+            },
+        );
+        check(
+            "default-upscale-codec",
+            codec_validation(&self.default_upscale_codec).map(|_| ()),
+        );
+        check(
+            "default-crf",
+            match self.default_crf {
+                0..=51 => Ok(()),
+                _ => Err(String::from("valid: 0 to 51")),
+            },
+        );
+        check(
+            "default-preset",
+            preset_validation(&self.default_preset).map(|_| ()),
+        );
+        check(
+            "default-encoder-params",
+            encoder_params_validation(&self.default_encoder_params),
+        );
+        check(
+            "default-max-resolution",
+            match self.default_max_resolution {
+                0 => Err(String::from("must be a height in pixels")),
+                height => max_resolution_validation(&height.to_string()).map(|_| ()),
+            },
+        );
+        check(
+            "default-output-container",
+            format_validation(&self.default_output_container).map(|_| ()),
+        );
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Checks that the output and workspace folders exist. Kept out of `validate_config`, a folder
+    /// on a drive that isn't mounted yet must not make the whole config unusable, it is checked
+    /// when the settings are saved and when a job starts.
+    pub fn check_directories(&self) -> Result<(), Vec<ConfigFieldError>> {
+        let errors: Vec<ConfigFieldError> = [
+            ("default-output-directory", &self.default_output_directory),
+            ("workspace-directory", &self.workspace_directory),
         ]
-        .contains(&self.get_default_upscale_type())
-        {
+        .into_iter()
+        .filter_map(|(field, dir)| {
+            existing_dir(dir.as_deref())
+                .err()
+                .map(|message| ConfigFieldError {
+                    field: field.to_string(),
+                    message,
+                })
+        })
+        .collect();
+        if errors.is_empty() {
             Ok(())
         } else {
-            Err("Invalid default upscale type".into())
+            Err(errors)
         }
     }
 
@@ -61,8 +202,206 @@ impl ConfigData {
     pub fn get_default_upscale_type(&self) -> String {
         self.default_upscale_type.clone()
     }
+
+    /// Returns the value of the default-output-directory key in the `ConfigData`.
+    pub fn get_default_output_directory(&self) -> Option<String> {
+        self.default_output_directory.clone()
+    }
+
+    /// Returns the value of the default-output-container key in the `ConfigData`.
+    pub fn get_default_output_container(&self) -> String {
+        self.default_output_container.clone()
+    }
+
+    /// Returns the value of the default-max-resolution key in the `ConfigData`.
+    pub fn get_default_max_resolution(&self) -> u32 {
+        self.default_max_resolution
+    }
+
+    /// Sets the encoder options and workspace of the config on `spec`.
+    pub fn apply(&self, spec: JobSpec) -> JobSpec {
+        let spec = spec
+            .crf(self.default_crf)
+            .preset(&self.default_preset)
+            .encoder_params(&self.default_encoder_params);
+        match &self.workspace_directory {
+            Some(workdir) => spec.workdir(workdir),
+            None => spec,
+        }
+    }
+
+    /// The config with the values of `profile` in place of its own.
+    pub fn with_profile(&self, profile: &ReveConfig) -> ConfigData {
+        let shared = ReveConfig {
+            application_logs: Some(self.application_logs),
+            model: Some(self.default_upscale_type.clone()),
+            scale: Some(self.default_upscale_factor),
+            encoder: Some(self.default_upscale_codec.clone()),
+            segment_size: Some(self.default_segment_size),
+            output_directory: self.default_output_directory.clone(),
+            crf: Some(self.default_crf),
+            preset: Some(self.default_preset.clone()),
+            encoder_params: Some(self.default_encoder_params.clone()),
+            max_resolution: Some(self.default_max_resolution),
+            format: Some(self.default_output_container.clone()),
+            workdir: self.workspace_directory.clone(),
+            ..ReveConfig::default()
+        };
+        ConfigData::from(&profile.or(&shared))
+    }
+
+    /// Sets the GUI settings that differ from `loaded`, the settings read from `shared`, so the
+    /// defaults the GUI shows for missing keys aren't written and the other keys are kept.
+    fn store(&self, loaded: &ConfigData, shared: &mut ReveConfig) {
+        set_changed(
+            &self.application_logs,
+            &loaded.application_logs,
+            &mut shared.application_logs,
+        );
+        set_changed(
+            &self.default_upscale_type,
+            &loaded.default_upscale_type,
+            &mut shared.model,
+        );
+        set_changed(
+            &self.default_upscale_factor,
+            &loaded.default_upscale_factor,
+            &mut shared.scale,
+        );
+        set_changed(
+            &self.default_upscale_codec,
+            &loaded.default_upscale_codec,
+            &mut shared.encoder,
+        );
+        set_changed(
+            &self.default_segment_size,
+            &loaded.default_segment_size,
+            &mut shared.segment_size,
+        );
+        set_changed(&self.default_crf, &loaded.default_crf, &mut shared.crf);
+        set_changed(
+            &self.default_preset,
+            &loaded.default_preset,
+            &mut shared.preset,
+        );
+        set_changed(
+            &self.default_encoder_params,
+            &loaded.default_encoder_params,
+            &mut shared.encoder_params,
+        );
+        set_changed(
+            &self.default_max_resolution,
+            &loaded.default_max_resolution,
+            &mut shared.max_resolution,
+        );
+        set_changed(
+            &self.default_output_container,
+            &loaded.default_output_container,
+            &mut shared.format,
+        );
+        if self.default_output_directory != loaded.default_output_directory {
+            shared.output_directory = self.default_output_directory.clone();
+        }
+        if self.workspace_directory != loaded.workspace_directory {
+            shared.workdir = self.workspace_directory.clone();
+        }
+    }
+}
+
+impl From<&ReveConfig> for ConfigData {
+    /// The GUI settings of a shared config, a key it lacks gets the GUI default.
+    fn from(shared: &ReveConfig) -> ConfigData {
+        let default = ConfigData::default();
+        ConfigData {
+            config_version: CONFIG_VERSION,
+            application_logs: shared.application_logs.unwrap_or(default.application_logs),
+            default_upscale_type: shared.model.clone().unwrap_or(default.default_upscale_type),
+            default_upscale_factor: shared.scale.unwrap_or(default.default_upscale_factor),
+            default_upscale_codec: shared
+                .encoder
+                .clone()
+                .unwrap_or(default.default_upscale_codec),
+            default_segment_size: shared.segment_size.unwrap_or(default.default_segment_size),
+            default_output_directory: shared.output_directory.clone(),
+            default_crf: shared.crf.unwrap_or(default.default_crf),
+            default_preset: shared.preset.clone().unwrap_or(default.default_preset),
+            default_encoder_params: shared
+                .encoder_params
+                .clone()
+                .unwrap_or(default.default_encoder_params),
+            default_max_resolution: shared
+                .max_resolution
+                .unwrap_or(default.default_max_resolution),
+            default_output_container: shared
+                .format
+                .clone()
+                .unwrap_or(default.default_output_container),
+            workspace_directory: shared.workdir.clone(),
+        }
+    }
+}
+
+fn set_changed<T: PartialEq + Clone>(value: &T, loaded: &T, key: &mut Option<T>) {
+    if value != loaded {
+        *key = Some(value.clone());
+    }
 }
 
+fn existing_dir(dir: Option<&str>) -> Result<(), String> {
+    match dir {
+        Some(dir) if !Path::new(dir).is_dir() => Err(format!("{} is not a directory", dir)),
+        _ => Ok(()),
+    }
+}
+
+/// Encoder params are `key=value` pairs separated by `:`, like `-x265-params` takes them.
+fn encoder_params_validation(params: &str) -> Result<(), String> {
+    if params.is_empty() {
+        return Ok(());
+    }
+    for param in params.split(':') {
+        let key = param.split('=').next().unwrap_or("");
+        if key.is_empty() || param.chars().any(char::is_whitespace) {
+            return Err(format!("{} is not a key=value param", param));
+        }
+    }
+    Ok(())
+}
+
+/// Brings a config file written by an older version up to `CONFIG_VERSION`, keeping its values.
+fn migrate(mut content: Value) -> Result<Value, Box<dyn Error>> {
+    let object = content
+        .as_object_mut()
+        .ok_or("config file is not a JSON object")?;
+    let version = object
+        .get("config-version")
+        .and_then(Value::as_u64)
+        .unwrap_or(1);
+    if version > u64::from(CONFIG_VERSION) {
+        return Err(format!("config version {} is newer than this reve-gui", version).into());
+    }
+
+    if version < 2 {
+        // version 1 had no version key, stored short codec names and lacked the encoder,
+        // folder and workspace settings
+        if let Some(codec) = object.get("default-upscale-codec").and_then(Value::as_str) {
+            let codec = match codec {
+                "x265" => "libx265",
+                "av1" => "libsvtav1",
+                codec => codec,
+            };
+            object.insert(String::from("default-upscale-codec"), json!(codec));
+        }
+        let defaults = serde_json::to_value(ConfigData::default())?;
+        for (key, value) in defaults.as_object().ok_or("invalid default config")? {
+            object.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+    }
+    object.insert(String::from("config-version"), json!(CONFIG_VERSION));
+    Ok(content)
+}
+
+/// The GUI settings, stored in the config file shared with the CLI (see [`ReveConfig`]).
 pub struct Config {
     path: PathBuf,
     content: Option<ConfigData>,
@@ -71,44 +410,79 @@ pub struct Config {
 impl Config {
     /// Create a new config with the content as None or the content of `ConfigData` passed as argument.
     pub fn new(config: Option<ConfigData>) -> Self {
-        let path = current_dir()
-            .expect("Could not find config directory")
-            .join(CONFIG_FILE);
-
         Self {
-            path,
+            path: ReveConfig::path(),
             content: config,
         }
     }
 
-    /// Loads the config file and returns its content as a Option of `ConfigData`.
-    pub fn load(&mut self) -> Result<ConfigData, Box<dyn Error>> {
-        let content = std::fs::read_to_string(&self.path)?;
-        self.content = serde_json::from_str(&content)?;
-        match self
-            .content
-            .as_ref()
-            .ok_or("Failed to load config file")?
-            .validate_config()
-        {
-            Ok(_) => Ok(self.content.clone().ok_or("Failed to load config file")?),
-            Err(err) => Err(err),
+    pub fn exists(&self) -> bool {
+        self.path.exists() || legacy_path().is_ok_and(|path| path.exists())
+    }
+
+    /// Loads the config file and returns its content as a `ConfigData`, with an error for each
+    /// value that can't be used. Without a shared config file, the file of an older reve-gui is
+    /// migrated and written to the shared one.
+    ///
+    /// Only a file that isn't a config at all is an error, the file is never changed here since
+    /// the CLI reads it too.
+    pub fn load(&mut self) -> Result<LoadedConfig, Box<dyn Error>> {
+        if !self.path.exists() {
+            return self.load_legacy();
         }
+        let config = ConfigData::from(&self.read_shared()?);
+        self.content = Some(config.clone());
+        Ok(LoadedConfig {
+            errors: config.validate_config().err().unwrap_or_default(),
+            config,
+        })
     }
 
-    /// Write the config to the config file.
+    /// Reads the config file of an older version from the working directory. It is left there,
+    /// once the shared config exists it isn't read anymore. A file with invalid values is only
+    /// migrated once they are fixed, the CLI would refuse them.
+    fn load_legacy(&mut self) -> Result<LoadedConfig, Box<dyn Error>> {
+        let path = legacy_path()?;
+        let content: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let config: ConfigData = serde_json::from_value(migrate(content)?)?;
+        let errors = config.validate_config().err().unwrap_or_default();
+        self.content = Some(config.clone());
+        if errors.is_empty() {
+            self.save()?;
+        }
+        Ok(LoadedConfig { config, errors })
+    }
+
+    /// The shared config file, with values its own validation would refuse.
+    fn read_shared(&self) -> Result<ReveConfig, Box<dyn Error>> {
+        let json = match fs::read_to_string(&self.path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(ReveConfig::default()),
+            Err(e) => return Err(e.into()),
+        };
+        serde_json::from_str(&json)
+            .map_err(|e| format!("config file {}: {}", self.path.display(), e).into())
+    }
+
+    /// Writes the settings changed since the config file was read, the other keys of the file
+    /// stay as they are. A file that can't be read isn't replaced.
     pub fn save(&self) -> Result<(), Box<dyn Error>> {
-        let content = serde_json::to_string_pretty(&self.content)?;
-        std::fs::write(&self.path, content)?;
+        let mut shared = self.read_shared()?;
+        if let Some(content) = &self.content {
+            content.store(&ConfigData::from(&shared), &mut shared);
+        }
+        shared.save_to(&self.path)?;
         Ok(())
     }
 
     /// Create a new config with default values and returns this default value.
-    pub fn create_default_config_file(&self) -> Result<ConfigData, Box<dyn Error>> {
-        std::fs::write(
-            &self.path,
-            serde_json::to_string_pretty(&ConfigData::default())?,
-        )?;
+    pub fn create_default_config_file(&mut self) -> Result<ConfigData, Box<dyn Error>> {
+        self.content = Some(ConfigData::default());
+        self.save()?;
         Ok(ConfigData::default())
     }
 }
+
+fn legacy_path() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(current_dir()?.join(LEGACY_CONFIG_FILE))
+}
@@ -0,0 +1,35 @@
+use reve_shared::JobControl;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Running upscales by input path, so the frontend can cancel, pause and resume them.
+#[derive(Default)]
+pub struct JobRegistry {
+    jobs: Mutex<HashMap<String, JobControl>>,
+}
+
+impl JobRegistry {
+    /// Registers a job for `path`, failing if one is already running for it.
+    pub fn start(&self, path: &str) -> Result<JobControl, String> {
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.contains_key(path) {
+            return Err(format!("{} is already being upscaled", path));
+        }
+        let control = JobControl::new();
+        jobs.insert(path.to_string(), control.clone());
+        Ok(control)
+    }
+
+    pub fn finish(&self, path: &str) {
+        self.jobs.lock().unwrap().remove(path);
+    }
+
+    pub fn get(&self, path: &str) -> Result<JobControl, String> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(path)
+            .cloned()
+            .ok_or_else(|| format!("no running job for {}", path))
+    }
+}
@@ -5,10 +5,15 @@
 
 mod commands;
 mod configuration;
+mod jobs;
+mod progress;
+mod queue;
 mod utils;
 
 fn main() {
     tauri::Builder::default()
+        .manage(jobs::JobRegistry::default())
+        .manage(queue::QueueRunner::default())
         .invoke_handler(tauri::generate_handler![
             utils::get_version,
             //utils::replace_file_suffix,
@@ -16,6 +21,16 @@ fn main() {
             utils::write_configuration,
             utils::write_log,
             commands::upscale_video,
+            commands::cancel_job,
+            commands::pause_job,
+            commands::resume_job,
+            commands::list_profiles,
+            queue::queue_add,
+            queue::queue_list,
+            queue::queue_reorder,
+            queue::queue_remove,
+            queue::queue_start,
+            queue::queue_stop,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
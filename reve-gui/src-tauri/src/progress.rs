@@ -0,0 +1,67 @@
+use reve_shared::{ProgressEvent, ProgressSink, Stage};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::Window;
+
+/// Name of the event the frontend listens to.
+pub const PROGRESS_EVENT: &str = "upscale-progress";
+
+/// Frames events of a stage are sent at most this often, the last frame is always sent.
+const FRAMES_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Payload of an `upscale-progress` event, the pipeline event plus an ETA for frames events.
+#[derive(Serialize, Clone)]
+struct ProgressPayload {
+    #[serde(flatten)]
+    event: ProgressEvent,
+    /// seconds left in the stage at the current fps, only set on frames events
+    eta_seconds: Option<f64>,
+}
+
+/// Forwards pipeline progress to the frontend window.
+pub struct WindowProgress {
+    window: Window,
+    last_frames: Mutex<HashMap<(Stage, u32), Instant>>,
+}
+
+impl WindowProgress {
+    pub fn new(window: Window) -> Self {
+        Self {
+            window,
+            last_frames: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl ProgressSink for WindowProgress {
+    fn event(&self, event: ProgressEvent) {
+        let mut eta_seconds = None;
+        if let ProgressEvent::Frames {
+            stage,
+            segment,
+            done,
+            total,
+            fps,
+        } = &event
+        {
+            let mut last_frames = self.last_frames.lock().unwrap();
+            let now = Instant::now();
+            if done < total {
+                if let Some(last) = last_frames.get(&(*stage, *segment)) {
+                    if now.duration_since(*last) < FRAMES_INTERVAL {
+                        return;
+                    }
+                }
+            }
+            last_frames.insert((*stage, *segment), now);
+            if *fps > 0.0 {
+                eta_seconds = Some(total.saturating_sub(*done) as f64 / fps);
+            }
+        }
+        let _ = self
+            .window
+            .emit(PROGRESS_EVENT, ProgressPayload { event, eta_seconds });
+    }
+}
@@ -0,0 +1,275 @@
+use crate::commands::{encoder_name, find_profile};
+use crate::configuration::ConfigData;
+use crate::progress::WindowProgress;
+use crate::utils;
+use clap::Parser;
+use reve_shared::*;
+use rusqlite::Connection;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tauri::{State, Window};
+
+/// Name of the event sent with the whole job list whenever a job changes state.
+pub const QUEUE_EVENT: &str = "queue-updated";
+
+/// Runs the jobs queued in reve.db one after the other, in the GUI process.
+#[derive(Default)]
+pub struct QueueRunner {
+    state: Arc<Mutex<RunnerState>>,
+}
+
+#[derive(Default)]
+struct RunnerState {
+    running: bool,
+    /// set by `queue_stop`, the runner stops before the next job
+    stopping: bool,
+    /// id of the running job and its control
+    current: Option<(i64, JobControl)>,
+}
+
+fn queue_db() -> Result<Connection, String> {
+    open_db("reve.db").map_err(|e| e.to_string())
+}
+
+/// Queues files and folders with the options picked in the frontend and the encoder settings of
+/// `profile`, the videos of a folder are filtered by resolution like `reve -i <folder>` does.
+/// Returns the ids of the new jobs.
+// the arguments are the fields the frontend sends
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+pub fn queue_add(
+    paths: Vec<String>,
+    upscale_factor: u8,
+    upscale_type: String,
+    upscale_codec: String,
+    segment_size: u32,
+    priority: Option<i64>,
+    profile: Option<String>,
+    window: Window,
+) -> Result<Vec<i64>, String> {
+    let conn = queue_db()?;
+    let config = utils::job_configuration()?;
+    let profile = profile.map(|name| find_profile(&name)).transpose()?;
+    let config = match &profile {
+        Some(profile) => config.with_profile(&profile.settings),
+        None => config,
+    };
+    let upscaler = profile.and_then(|profile| profile.settings.upscaler);
+    let priority = priority.unwrap_or(0);
+    let mut ids = Vec::new();
+    for path in paths {
+        let input = absolute_path(&path);
+        let settings = queue_settings(
+            &input,
+            upscale_factor,
+            &upscale_type,
+            &upscale_codec,
+            segment_size,
+            &config,
+            upscaler.as_deref(),
+        )?;
+        let output = |file: &str| {
+            queue_output(
+                file,
+                upscale_factor,
+                &upscale_codec,
+                &settings.format,
+                &config,
+            )
+        };
+        if Path::new(&input).is_dir() {
+            let queued =
+                enqueue_new_files(&conn, walk_files(&input), &settings, priority, |file| {
+                    Some(output(file))
+                })
+                .map_err(|e| e.to_string())?;
+            ids.extend(queued.into_iter().map(|(id, _)| id));
+        } else {
+            let output = output(&input);
+            ids.push(
+                enqueue(&conn, &input, Some(&output), &settings, priority)
+                    .map_err(|e| e.to_string())?,
+            );
+        }
+    }
+    utils::write_log(&format!("Queued {} jobs.", ids.len()));
+    emit_jobs(&window, &conn);
+    Ok(ids)
+}
+
+/// Every job, queued and running first, then the finished ones.
+#[tauri::command]
+pub fn queue_list() -> Result<Vec<Job>, String> {
+    list_jobs(&queue_db()?).map_err(|e| e.to_string())
+}
+
+/// Changes the priority of a job, higher runs first.
+#[tauri::command]
+pub fn queue_reorder(id: i64, priority: i64, window: Window) -> Result<(), String> {
+    let conn = queue_db()?;
+    if !set_job_priority(&conn, id, priority).map_err(|e| e.to_string())? {
+        return Err(format!("no job with id {}", id));
+    }
+    emit_jobs(&window, &conn);
+    Ok(())
+}
+
+/// Removes a job that isn't running.
+#[tauri::command]
+pub fn queue_remove(id: i64, window: Window) -> Result<(), String> {
+    let conn = queue_db()?;
+    if !remove_job(&conn, id).map_err(|e| e.to_string())? {
+        return Err(format!("no job with id {} that isn't running", id));
+    }
+    emit_jobs(&window, &conn);
+    Ok(())
+}
+
+/// Starts running the queue on a background thread, until it is empty or stopped.
+#[tauri::command]
+pub fn queue_start(window: Window, runner: State<QueueRunner>) -> Result<(), String> {
+    let conn = queue_db()?;
+    {
+        let mut state = runner.state.lock().unwrap();
+        if state.running {
+            return Ok(());
+        }
+        state.running = true;
+        state.stopping = false;
+    }
+    // jobs left running when the app was closed resume from their workspace
+    requeue_interrupted(&conn).map_err(|e| e.to_string())?;
+    let state = runner.state.clone();
+    thread::spawn(move || run_queue_jobs(conn, &window, &state));
+    Ok(())
+}
+
+/// Stops the queue after the running job, or right away with `cancel_running`. A cancelled job
+/// goes back in the queue and resumes from its workspace on the next start.
+#[tauri::command]
+pub fn queue_stop(cancel_running: bool, runner: State<QueueRunner>) {
+    let mut state = runner.state.lock().unwrap();
+    state.stopping = true;
+    if cancel_running {
+        if let Some((_, control)) = &state.current {
+            control.cancel();
+        }
+    }
+}
+
+fn run_queue_jobs(mut conn: Connection, window: &Window, state: &Mutex<RunnerState>) {
+    let progress: Arc<dyn ProgressSink> = Arc::new(WindowProgress::new(window.clone()));
+    loop {
+        if state.lock().unwrap().stopping {
+            break;
+        }
+        let job = match dequeue_next(&mut conn) {
+            Ok(Some(job)) => job,
+            Ok(None) => break,
+            Err(e) => {
+                utils::write_log(&format!("Could not read the job queue: {}", e));
+                break;
+            }
+        };
+        let control = JobControl::new();
+        state.lock().unwrap().current = Some((job.id, control.clone()));
+        emit_jobs(window, &conn);
+
+        let result = Pipeline::new(JobSpec::from(&job.settings))
+            .progress(progress.clone())
+            .control(control)
+            .run();
+        state.lock().unwrap().current = None;
+
+        let result = match result {
+            Ok(()) => {
+                let _ = update_db_status(&conn, &job.input, "done");
+                mark_done(&conn, job.id)
+            }
+            Err(ReveError::Cancelled) => requeue_job(&conn, job.id).map(|_| ()),
+            Err(e) => mark_failed(&conn, job.id, &e.to_string()),
+        };
+        if let Err(e) = result {
+            utils::write_log(&format!("Could not update job {}: {}", job.id, e));
+        }
+        emit_jobs(window, &conn);
+    }
+    let mut state = state.lock().unwrap();
+    state.running = false;
+    state.stopping = false;
+}
+
+/// reve settings of a queued file or folder, through the same parser and checks as the command
+/// line. Videos are written in the output container of the config, mkv inputs stay mkv.
+fn queue_settings(
+    input: &str,
+    upscale_factor: u8,
+    upscale_type: &str,
+    upscale_codec: &str,
+    segment_size: u32,
+    config: &ConfigData,
+    upscaler: Option<&str>,
+) -> Result<Args, String> {
+    let encoder = encoder_name(upscale_codec);
+    // mkv inputs can only be written as mkv
+    let format = match Path::new(input).extension().and_then(|e| e.to_str()) {
+        Some("mkv") => String::from("mkv"),
+        _ => config.get_default_output_container(),
+    };
+    let spec = config.apply(JobSpec::new(input));
+    let mut cli_args = vec![
+        String::from("reve"),
+        format!("--inputpath={}", input),
+        format!("--scale={}", upscale_factor),
+        format!("--model={}", upscale_type),
+        format!("--encoder={}", encoder),
+        format!("--format={}", format),
+        match segment_size {
+            0 => String::from("--parts=auto"),
+            segment_size => format!("--parts={}", segment_size),
+        },
+        format!("--crf={}", spec.crf),
+        format!("--preset={}", spec.preset),
+        format!("--x265params={}", spec.encoder_params),
+        format!("--resolution={}", config.get_default_max_resolution()),
+    ];
+    if let Some(workdir) = &spec.workdir {
+        cli_args.push(format!("--workdir={}", workdir));
+    }
+    if let Some(upscaler) = upscaler {
+        cli_args.push(format!("--upscaler={}", upscaler));
+    }
+    Args::try_parse_from(cli_args).map_err(|e| e.to_string())
+}
+
+/// Where a queued video is upscaled to, named like the frontend names the videos it upscales:
+/// `<dir>/<name>-<factor>x.<codec>.<format>`, in the output directory of the config if it has
+/// one, otherwise next to the video.
+fn queue_output(
+    input: &str,
+    upscale_factor: u8,
+    upscale_codec: &str,
+    format: &str,
+    config: &ConfigData,
+) -> String {
+    let path = Path::new(input);
+    let dir = match config.get_default_output_directory() {
+        Some(dir) => PathBuf::from(dir),
+        None => path.parent().map(Path::to_path_buf).unwrap_or_default(),
+    };
+    let name = format!(
+        "{}-{}x.{}.{}",
+        path.file_stem().unwrap_or_default().to_string_lossy(),
+        upscale_factor,
+        upscale_codec,
+        format
+    );
+    dir.join(name).display().to_string()
+}
+
+fn emit_jobs(window: &Window, conn: &Connection) {
+    if let Ok(jobs) = list_jobs(conn) {
+        let _ = window.emit(QUEUE_EVENT, jobs);
+    }
+}
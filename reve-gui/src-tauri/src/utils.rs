@@ -1,29 +1,27 @@
-use std::{env::current_dir, fs::OpenOptions, io::Write, path::PathBuf};
+use std::{fs, fs::OpenOptions, io::Write, path::PathBuf};
 
-use crate::configuration::{self, ConfigData, LOG_FILE};
+use reve_shared::config_dir;
+
+use crate::configuration::{self, ConfigData, ConfigFieldError, LoadedConfig, LOG_FILE};
 
 pub struct Logger {
     path: PathBuf,
 }
 
 impl Logger {
-    /// Create a new logger.
+    /// Create a new logger, writing next to the config file.
     pub fn new() -> Self {
-        let path = current_dir()
-            .expect("Failed to locate cache directory")
-            .join(LOG_FILE);
+        let path = config_dir().join(LOG_FILE);
         Self { path }
     }
 
     /// Write a message to the log file. If the file does not exist, it will be created. If it does exist, it will be overwritten.
     pub fn log(&self, message: &str) {
-        let config = match load_configuration() {
-            Ok(config) => config,
-            Err(_) => ConfigData::default(),
-        };
+        let config = configuration();
         if !config.get_is_active_application_logs() {
             return;
         }
+        let _ = fs::create_dir_all(config_dir());
         let mut file = OpenOptions::new()
             .create(true)
             .append(true)
@@ -66,23 +64,57 @@ pub fn replace_file_suffix(path: String, upscale_factor: String) -> String {
         .to_string()
 } */
 
-/// Loads the configuration file and creates a default one if it does not exist or if it is invalid.
+/// Loads the configuration file, migrating it if it was written by an older version. A missing
+/// file is created with defaults. Invalid values are returned with an error for each, for the
+/// settings page to show, the file itself is left alone.
 #[tauri::command]
-pub fn load_configuration() -> Result<ConfigData, String> {
+pub fn load_configuration() -> Result<LoadedConfig, String> {
     let mut config = configuration::Config::new(None);
-    match config.load() {
-        Ok(config) => Ok(config),
-        Err(_) => Ok(config
+    if !config.exists() {
+        return config
             .create_default_config_file()
-            .map_err(|err| err.to_string())?),
+            .map(|config| LoadedConfig {
+                config,
+                errors: Vec::new(),
+            })
+            .map_err(|err| err.to_string());
     }
+    config.load().map_err(|err| err.to_string())
+}
+
+/// The settings a job starts with, an error if one of their folders is missing.
+pub fn job_configuration() -> Result<ConfigData, String> {
+    let config = configuration();
+    config.check_directories().map_err(|errors| {
+        let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        errors.join(", ")
+    })?;
+    Ok(config)
 }
 
-/// Validates the ConfigData values and writes the configuration file.
+/// The settings jobs run with, the defaults when the config file can't be read.
+pub fn configuration() -> ConfigData {
+    load_configuration()
+        .map(|loaded| loaded.config)
+        .unwrap_or_else(|_| ConfigData::default())
+}
+
+/// Validates the ConfigData values and writes the configuration file, returning an error for
+/// each invalid field.
 #[tauri::command]
-pub fn write_configuration(config: ConfigData) -> Result<(), String> {
+pub fn write_configuration(config: ConfigData) -> Result<(), Vec<ConfigFieldError>> {
+    let mut errors = config.validate_config().err().unwrap_or_default();
+    errors.extend(config.check_directories().err().unwrap_or_default());
+    if !errors.is_empty() {
+        return Err(errors);
+    }
     let config = configuration::Config::new(Some(config));
-    config.save().map_err(|err| err.to_string())
+    config.save().map_err(|err| {
+        vec![ConfigFieldError {
+            field: String::from("file"),
+            message: err.to_string(),
+        }]
+    })
 }
 
 /// Write to the log file.
@@ -0,0 +1,261 @@
+use crate::{
+    codec_validation, format_validation, max_resolution_validation, model_validation,
+    preset_validation, segment_size_validation, upscaler_validation, Args, ReveError,
+};
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::env;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+const CONFIG_FILE: &str = "config.json";
+
+/// Defaults shared by the CLI and the GUI, kept in `config.json` of [`config_dir`].
+///
+/// Every key is optional, a missing one keeps the built-in default. The CLI applies them to the
+/// options given neither as a flag nor in their `REVE_*` environment variable (`REVE_CRF`,
+/// `REVE_PRESET`, ...), the GUI uses them as its settings.
+///
+/// ```json
+/// { "crf": 18, "preset": "medium", "workdir": "/mnt/fast/reve" }
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReveConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upscaler: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoder: Option<String>,
+    /// output container, mp4/mkv/avi
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crf: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoder_params: Option<String>,
+    /// frames per segment, 0 (`"auto"` in the file) sizes them like `--parts auto`
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "segment_size_from",
+        serialize_with = "segment_size_to"
+    )]
+    pub segment_size: Option<u32>,
+    /// videos of a folder taller than this are skipped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_resolution: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workdir: Option<String>,
+    /// GUI only, None writes next to the input
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_directory: Option<String>,
+    /// GUI only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub application_logs: Option<bool>,
+}
+
+/// Folder of the reve config and GUI log: `$XDG_CONFIG_HOME/reve`, otherwise `~/.config/reve`
+/// on Linux, `~/Library/Application Support/reve` on macOS and `%APPDATA%\reve` on Windows.
+pub fn config_dir() -> PathBuf {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(platform_config_dir)
+        .unwrap_or_else(|| env::current_dir().unwrap_or_default());
+    base.join("reve")
+}
+
+#[cfg(target_os = "windows")]
+fn platform_config_dir() -> Option<PathBuf> {
+    env::var_os("APPDATA").map(PathBuf::from)
+}
+
+#[cfg(target_os = "macos")]
+fn platform_config_dir() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn platform_config_dir() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".config"))
+}
+
+impl ReveConfig {
+    pub fn path() -> PathBuf {
+        config_dir().join(CONFIG_FILE)
+    }
+
+    /// Reads the config file, an empty config if there is none.
+    pub fn load() -> Result<ReveConfig, ReveError> {
+        ReveConfig::load_from(&ReveConfig::path())
+    }
+
+    pub fn load_from(path: &Path) -> Result<ReveConfig, ReveError> {
+        let json = match fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(ReveConfig::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let config: ReveConfig = serde_json::from_str(&json).map_err(|e| {
+            ReveError::InvalidInput(format!("config file {}: {}", path.display(), e))
+        })?;
+        config.validate().map_err(|e| {
+            ReveError::InvalidInput(format!("config file {}: {}", path.display(), e))
+        })?;
+        Ok(config)
+    }
+
+    pub fn save(&self) -> Result<(), ReveError> {
+        self.save_to(&ReveConfig::path())
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<(), ReveError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_string_pretty(self).map_err(Error::from)?;
+        fs::write(path, json)?;
+        Ok(())
+    }
+
+    /// Checks the values the same way the matching command line options are checked, the
+    /// error names the first invalid key.
+    pub fn validate(&self) -> Result<(), String> {
+        fn check(key: &str, result: Result<String, String>) -> Result<(), String> {
+            result.map(|_| ()).map_err(|e| format!("{}: {}", key, e))
+        }
+        if let Some(model) = &self.model {
+            check("model", model_validation(model))?;
+        }
+        if let Some(scale) = self.scale {
+            if !(2..=4).contains(&scale) {
+                return Err(String::from("scale: valid: 2/3/4"));
+            }
+        }
+        if let Some(upscaler) = &self.upscaler {
+            check("upscaler", upscaler_validation(upscaler))?;
+        }
+        if let Some(encoder) = &self.encoder {
+            check("encoder", codec_validation(encoder))?;
+        }
+        if let Some(format) = &self.format {
+            check("format", format_validation(format))?;
+        }
+        if let Some(crf) = self.crf {
+            if crf > 51 {
+                return Err(String::from("crf: valid: 0 to 51"));
+            }
+        }
+        if let Some(preset) = &self.preset {
+            check("preset", preset_validation(preset))?;
+        }
+        if let Some(max_resolution) = self.max_resolution {
+            check(
+                "max-resolution",
+                max_resolution_validation(&max_resolution.to_string()),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// These settings, with the keys they leave unset taken from `base`.
+    pub fn or(&self, base: &ReveConfig) -> ReveConfig {
+        ReveConfig {
+            model: self.model.clone().or_else(|| base.model.clone()),
+            scale: self.scale.or(base.scale),
+            upscaler: self.upscaler.clone().or_else(|| base.upscaler.clone()),
+            encoder: self.encoder.clone().or_else(|| base.encoder.clone()),
+            format: self.format.clone().or_else(|| base.format.clone()),
+            crf: self.crf.or(base.crf),
+            preset: self.preset.clone().or_else(|| base.preset.clone()),
+            encoder_params: self
+                .encoder_params
+                .clone()
+                .or_else(|| base.encoder_params.clone()),
+            segment_size: self.segment_size.or(base.segment_size),
+            max_resolution: self.max_resolution.or(base.max_resolution),
+            workdir: self.workdir.clone().or_else(|| base.workdir.clone()),
+            output_directory: self
+                .output_directory
+                .clone()
+                .or_else(|| base.output_directory.clone()),
+            application_logs: self.application_logs.or(base.application_logs),
+        }
+    }
+
+    /// Sets the config values on the options of `args` that `matches` (the parse `args` came
+    /// from) left at their clap default.
+    pub fn apply(&self, args: &mut Args, matches: &ArgMatches) {
+        let default = |id: &str| matches.value_source(id) == Some(ValueSource::DefaultValue);
+        if let (Some(model), true) = (&self.model, default("model")) {
+            args.model = model.clone();
+        }
+        if let (Some(scale), true) = (self.scale, default("scale")) {
+            args.scale = scale;
+        }
+        if let (Some(upscaler), true) = (&self.upscaler, default("upscaler")) {
+            args.upscaler = upscaler.clone();
+        }
+        if let (Some(encoder), true) = (&self.encoder, default("codec")) {
+            args.codec = encoder.clone();
+        }
+        if let (Some(format), true) = (&self.format, default("format")) {
+            args.format = format.clone();
+        }
+        if let (Some(crf), true) = (self.crf, default("crf")) {
+            args.crf = crf;
+        }
+        if let (Some(preset), true) = (&self.preset, default("preset")) {
+            args.preset = preset.clone();
+        }
+        if let (Some(params), true) = (&self.encoder_params, default("x265params")) {
+            args.x265params = params.clone();
+        }
+        if let (Some(segment_size), true) = (self.segment_size, default("segmentsize")) {
+            args.segmentsize = segment_size;
+        }
+        if let (Some(max_resolution), true) = (self.max_resolution, default("resolution")) {
+            args.resolution = Some(max_resolution.to_string());
+        }
+        // --workdir has no default, it is only unset
+        if let (Some(workdir), None) = (&self.workdir, matches.value_source("workdir")) {
+            args.workdir = Some(workdir.clone());
+        }
+    }
+}
+
+/// `segment-size` is a number of frames or "auto", which is kept as 0 like `--parts auto`.
+fn segment_size_from<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum SegmentSize {
+        Frames(u32),
+        Auto(String),
+    }
+    match Option::<SegmentSize>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(SegmentSize::Frames(frames)) => Ok(Some(frames)),
+        Some(SegmentSize::Auto(auto)) => segment_size_validation(&auto)
+            .map(Some)
+            .map_err(de::Error::custom),
+    }
+}
+
+fn segment_size_to<S: Serializer>(
+    segment_size: &Option<u32>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match segment_size {
+        Some(0) => serializer.serialize_str("auto"),
+        Some(frames) => serializer.serialize_u32(*frames),
+        None => serializer.serialize_none(),
+    }
+}
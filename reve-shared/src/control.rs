@@ -0,0 +1,153 @@
+use crate::{error, ReveError};
+use std::collections::HashSet;
+use std::process::{Child, Command, ExitStatus};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+/// Cancels, pauses and resumes a running job from another thread.
+///
+/// The child processes a job starts (ffmpeg, the upscaler) are registered here while they run.
+/// Cancelling kills them and makes the pipeline stop with [`ReveError::Cancelled`], the segments
+/// already in the workspace are kept so the job resumes from there. Pausing suspends them
+/// (SIGSTOP, unix only) and holds the segment loop until the job is resumed. Clones control the
+/// same job.
+#[derive(Clone, Default)]
+pub struct JobControl {
+    shared: Arc<(Mutex<ControlState>, Condvar)>,
+}
+
+#[derive(Default)]
+struct ControlState {
+    cancelled: bool,
+    paused: bool,
+    /// pids of the running children
+    children: HashSet<u32>,
+}
+
+impl JobControl {
+    pub fn new() -> JobControl {
+        JobControl::default()
+    }
+
+    pub fn cancel(&self) {
+        let mut state = self.state();
+        state.cancelled = true;
+        state.paused = false;
+        for &pid in &state.children {
+            // a stopped process only handles SIGKILL once it is continued
+            continue_process(pid);
+            kill_process(pid);
+        }
+        self.shared.1.notify_all();
+    }
+
+    pub fn pause(&self) {
+        let mut state = self.state();
+        if state.cancelled || state.paused {
+            return;
+        }
+        state.paused = true;
+        for &pid in &state.children {
+            stop_process(pid);
+        }
+    }
+
+    pub fn resume(&self) {
+        let mut state = self.state();
+        if !state.paused {
+            return;
+        }
+        state.paused = false;
+        for &pid in &state.children {
+            continue_process(pid);
+        }
+        self.shared.1.notify_all();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state().cancelled
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state().paused
+    }
+
+    /// Blocks while the job is paused, fails once it is cancelled.
+    pub fn checkpoint(&self) -> Result<(), ReveError> {
+        let mut state = self.state();
+        while state.paused && !state.cancelled {
+            state = self.shared.1.wait(state).unwrap();
+        }
+        if state.cancelled {
+            Err(ReveError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// `error::spawn` for a child of the job, registered until it is waited on with `wait`.
+    pub(crate) fn spawn(&self, command: &mut Command) -> Result<Child, ReveError> {
+        // spawn under the lock so a cancel can't miss the new child
+        let mut state = self.state();
+        if state.cancelled {
+            return Err(ReveError::Cancelled);
+        }
+        let child = error::spawn(command)?;
+        if state.paused {
+            stop_process(child.id());
+        }
+        state.children.insert(child.id());
+        Ok(child)
+    }
+
+    /// Waits for a child started with `spawn`, a child of a cancelled job fails with Cancelled.
+    pub(crate) fn wait(&self, child: &mut Child) -> Result<ExitStatus, ReveError> {
+        let status = child.wait();
+        let mut state = self.state();
+        state.children.remove(&child.id());
+        if state.cancelled {
+            return Err(ReveError::Cancelled);
+        }
+        Ok(status?)
+    }
+
+    fn state(&self) -> MutexGuard<'_, ControlState> {
+        self.shared.0.lock().unwrap()
+    }
+}
+
+#[cfg(unix)]
+fn signal(pid: u32, signal: libc::c_int) {
+    unsafe {
+        libc::kill(pid as libc::pid_t, signal);
+    }
+}
+
+#[cfg(unix)]
+fn stop_process(pid: u32) {
+    signal(pid, libc::SIGSTOP);
+}
+
+#[cfg(unix)]
+fn continue_process(pid: u32) {
+    signal(pid, libc::SIGCONT);
+}
+
+#[cfg(unix)]
+fn kill_process(pid: u32) {
+    signal(pid, libc::SIGKILL);
+}
+
+// Windows has no SIGSTOP, a paused job there finishes the running child and waits before the
+// next one.
+#[cfg(not(unix))]
+fn stop_process(_pid: u32) {}
+
+#[cfg(not(unix))]
+fn continue_process(_pid: u32) {}
+
+#[cfg(not(unix))]
+fn kill_process(pid: u32) {
+    let _ = Command::new("taskkill")
+        .args(["/F", "/T", "/PID", &pid.to_string()])
+        .output();
+}
@@ -0,0 +1,112 @@
+/// How the `--crf` value is handed to the encoder.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RateControl {
+    /// `-crf <crf>`
+    Crf,
+    /// `-crf <crf> -b:v 0`, constant quality for encoders that otherwise target a bitrate
+    ConstantQuality,
+    /// `-qp <q>`, with the 0-51 crf scale stretched to the encoder's 0-`max` range
+    Qp { max: u16 },
+}
+
+/// Everything needed to encode upscaled frames with one ffmpeg video encoder.
+#[derive(Clone, Debug)]
+pub struct EncoderProfile {
+    /// name used by `--encoder` and in default output file names
+    pub name: String,
+    /// ffmpeg encoder passed to `-c:v`
+    pub codec: String,
+    pub rate_control: RateControl,
+    pub pixel_format: String,
+    /// whether the encoder understands the x264/x265 style `-preset` names
+    pub preset: bool,
+    /// option receiving the encoder parameters (`-x265-params`), if any
+    pub params_option: Option<String>,
+    pub extra_args: Vec<String>,
+}
+
+impl EncoderProfile {
+    fn new(name: &str, rate_control: RateControl, pixel_format: &str) -> EncoderProfile {
+        EncoderProfile {
+            name: name.to_string(),
+            codec: name.to_string(),
+            rate_control,
+            pixel_format: pixel_format.to_string(),
+            preset: false,
+            params_option: None,
+            extra_args: Vec::new(),
+        }
+    }
+
+    fn with_preset(mut self) -> EncoderProfile {
+        self.preset = true;
+        self
+    }
+
+    fn with_params_option(mut self, option: &str) -> EncoderProfile {
+        self.params_option = Some(option.to_string());
+        self
+    }
+
+    fn with_extra_args(mut self, args: &[&str]) -> EncoderProfile {
+        self.extra_args = args.iter().map(|a| a.to_string()).collect();
+        self
+    }
+
+    /// Returns the ffmpeg output arguments for this profile, from `-c:v` to the last encoder option.
+    pub fn args(&self, crf: u8, preset: &str, params: &str) -> Vec<String> {
+        let mut args = vec![String::from("-c:v"), self.codec.clone()];
+        args.extend(self.extra_args.iter().cloned());
+        match self.rate_control {
+            RateControl::Crf => args.extend([String::from("-crf"), crf.to_string()]),
+            RateControl::ConstantQuality => args.extend([
+                String::from("-crf"),
+                crf.to_string(),
+                String::from("-b:v"),
+                String::from("0"),
+            ]),
+            RateControl::Qp { max } => args.extend([
+                String::from("-qp"),
+                (u32::from(crf) * u32::from(max) / 51).to_string(),
+            ]),
+        }
+        args.extend([String::from("-pix_fmt"), self.pixel_format.clone()]);
+        if self.preset {
+            args.extend([String::from("-preset"), preset.to_string()]);
+        }
+        if let Some(option) = &self.params_option {
+            if !params.is_empty() {
+                args.extend([option.clone(), params.to_string()]);
+            }
+        }
+        args
+    }
+}
+
+/// All encoders reve knows how to drive.
+pub fn encoder_profiles() -> Vec<EncoderProfile> {
+    vec![
+        // 2022-05-23 17:47 27cffd1
+        // https://github.com/AnimMouse/ffmpeg-autobuild/releases/download/m-2022-05-23-17-47/ffmpeg-27cffd1-ff31946-win64-nonfree.7z
+        EncoderProfile::new("libx265", RateControl::Crf, "yuv420p10le")
+            .with_preset()
+            .with_params_option("-x265-params"),
+        // 2022-03-28 07:12 c2d1597
+        // https://github.com/AnimMouse/ffmpeg-autobuild/releases/download/m-2022-03-28-07-12/ffmpeg-c2d1597-651202b-win64-nonfree.7z
+        EncoderProfile::new("libsvt_hevc", RateControl::Qp { max: 51 }, "yuv420p10le")
+            .with_extra_args(&["-rc", "0", "-tune", "0"]),
+        EncoderProfile::new("libsvtav1", RateControl::Crf, "yuv420p10le"),
+        EncoderProfile::new("libx264", RateControl::Crf, "yuv420p").with_preset(),
+        EncoderProfile::new("libvpx-vp9", RateControl::ConstantQuality, "yuv420p10le")
+            .with_extra_args(&["-deadline", "good", "-cpu-used", "2", "-row-mt", "1"]),
+        EncoderProfile::new("librav1e", RateControl::Qp { max: 255 }, "yuv420p10le")
+            .with_extra_args(&["-speed", "6"]),
+        EncoderProfile::new("libaom-av1", RateControl::ConstantQuality, "yuv420p10le")
+            .with_extra_args(&["-cpu-used", "4", "-row-mt", "1"]),
+    ]
+}
+
+/// Looks up an encoder profile by its `--encoder` name.
+pub fn get_encoder_profile(name: &str) -> Option<EncoderProfile> {
+    encoder_profiles().into_iter().find(|p| p.name == name)
+}
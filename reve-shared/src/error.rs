@@ -0,0 +1,210 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::process::{Child, Command, ExitStatus, Output};
+
+/// Everything that can go wrong in reve-shared.
+///
+/// The library never exits or panics on these, the CLI prints them and exits, the GUI sends
+/// them back to the frontend.
+#[derive(Debug)]
+pub enum ReveError {
+    /// an external program (ffmpeg, ffprobe, the upscaler) could not be started
+    MissingTool {
+        tool: String,
+        source: io::Error,
+    },
+    /// ffprobe failed on `path` or reported something unusable
+    Probe {
+        path: String,
+        message: String,
+    },
+    /// ffmpeg exited with an error while doing `action`
+    Ffmpeg {
+        action: String,
+        stderr: String,
+    },
+    /// the upscaler exited with an error
+    Upscaler {
+        stderr: String,
+    },
+    Io(io::Error),
+    Db(rusqlite::Error),
+    InvalidInput(String),
+    /// the filesystem of `path` has `available` bytes free, the upscale needs about `needed`
+    NoSpace {
+        path: String,
+        needed: u64,
+        available: u64,
+        /// a smaller `--parts` that would fit, if any
+        parts: Option<u32>,
+    },
+    /// another reve process, `pid`, is upscaling in the workspace at `path`
+    WorkspaceBusy {
+        path: String,
+        pid: u32,
+    },
+    /// the job was cancelled through its `JobControl`
+    Cancelled,
+}
+
+impl fmt::Display for ReveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReveError::MissingTool { tool, source } => {
+                write!(f, "could not run {} ({}), is it installed?", tool, source)
+            }
+            ReveError::Probe { path, message } => {
+                write!(f, "could not probe {}: {}", path, message.trim())
+            }
+            ReveError::Ffmpeg { action, stderr } => {
+                write!(f, "ffmpeg failed to {}", action)?;
+                write_stderr(f, stderr)
+            }
+            ReveError::Upscaler { stderr } => {
+                write!(f, "upscaler failed")?;
+                write_stderr(f, stderr)
+            }
+            ReveError::Io(e) => write!(f, "{}", e),
+            ReveError::Db(e) => write!(f, "database error: {}", e),
+            ReveError::InvalidInput(message) => write!(f, "{}", message),
+            ReveError::NoSpace {
+                path,
+                needed,
+                available,
+                parts,
+            } => {
+                write!(
+                    f,
+                    "not enough space in {}: about {:.1} GB needed, {:.1} GB free",
+                    path,
+                    *needed as f64 / 1e9,
+                    *available as f64 / 1e9
+                )?;
+                match parts {
+                    Some(parts) => write!(f, ", use --parts {} or another --workdir", parts),
+                    None => write!(f, ", free some space or use another --workdir"),
+                }
+            }
+            ReveError::WorkspaceBusy { path, pid } => write!(
+                f,
+                "{} is used by reve process {}, wait for it to finish or use another --workdir",
+                path, pid
+            ),
+            ReveError::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+fn write_stderr(f: &mut fmt::Formatter, stderr: &str) -> fmt::Result {
+    if stderr.trim().is_empty() {
+        Ok(())
+    } else {
+        write!(f, ":\n{}", stderr.trim_end())
+    }
+}
+
+impl std::error::Error for ReveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ReveError::MissingTool { source, .. } => Some(source),
+            ReveError::Io(e) => Some(e),
+            ReveError::Db(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ReveError {
+    fn from(e: io::Error) -> ReveError {
+        ReveError::Io(e)
+    }
+}
+
+impl From<rusqlite::Error> for ReveError {
+    fn from(e: rusqlite::Error) -> ReveError {
+        ReveError::Db(e)
+    }
+}
+
+impl ReveError {
+    pub(crate) fn probe(path: &str, message: impl ToString) -> ReveError {
+        ReveError::Probe {
+            path: path.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    pub(crate) fn ffmpeg(action: &str, stderr: impl ToString) -> ReveError {
+        ReveError::Ffmpeg {
+            action: action.to_string(),
+            stderr: stderr.to_string(),
+        }
+    }
+}
+
+fn tool_error(command: &Command, e: io::Error) -> ReveError {
+    ReveError::MissingTool {
+        tool: command.get_program().to_string_lossy().to_string(),
+        source: e,
+    }
+}
+
+/// `command.spawn()`, a program that can't be started is a MissingTool.
+pub(crate) fn spawn(command: &mut Command) -> Result<Child, ReveError> {
+    command.spawn().map_err(|e| tool_error(command, e))
+}
+
+/// `command.output()`, a program that can't be started is a MissingTool.
+pub(crate) fn output(command: &mut Command) -> Result<Output, ReveError> {
+    command.output().map_err(|e| tool_error(command, e))
+}
+
+/// Runs an ffmpeg `command` to completion, failing with its stderr if it exits with an error.
+pub(crate) fn run_ffmpeg(command: &mut Command, action: &str) -> Result<Output, ReveError> {
+    let output = output(command)?;
+    if output.status.success() {
+        Ok(output)
+    } else {
+        Err(ReveError::ffmpeg(
+            action,
+            String::from_utf8_lossy(&output.stderr),
+        ))
+    }
+}
+
+/// Last lines of a child's stderr, kept while the output is read for progress so a failure
+/// can be reported with what the tool said.
+#[derive(Default)]
+pub(crate) struct StderrTail {
+    lines: VecDeque<String>,
+}
+
+impl StderrTail {
+    const LINES: usize = 20;
+
+    pub(crate) fn push(&mut self, line: &str) {
+        if self.lines.len() == Self::LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line.to_string());
+    }
+
+    /// Ok if `status` is a success, otherwise the error built by `error` from the kept lines.
+    pub(crate) fn check(
+        self,
+        status: ExitStatus,
+        error: impl FnOnce(String) -> ReveError,
+    ) -> Result<(), ReveError> {
+        if status.success() {
+            Ok(())
+        } else {
+            Err(error(self.into_string()))
+        }
+    }
+
+    /// The kept lines, oldest first.
+    pub(crate) fn into_string(self) -> String {
+        Vec::from(self.lines).join("\n")
+    }
+}
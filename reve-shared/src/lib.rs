@@ -1,7 +1,7 @@
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use clearscreen::clear;
 use colored::Colorize;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use indicatif::{ProgressBar, ProgressStyle};
 use path_clean::PathClean;
 use rayon::prelude::*;
 use rusqlite::{params, Connection, Result};
@@ -9,15 +9,15 @@ use serde::{Deserialize, Serialize};
 use serde_json::from_str;
 use serde_json::Value;
 use std::env;
+use std::ffi::OsString;
 use std::fs;
 use std::fs::metadata;
-use std::io::{BufRead, BufReader, Error, ErrorKind};
+use std::io::{BufRead, BufReader, Error};
 use std::path::Path;
-use std::process::exit;
 use std::process::Output;
 use std::process::{ChildStderr, Command, Stdio};
 use std::str::FromStr;
-use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
@@ -25,11 +25,46 @@ use std::time::Instant;
 use std::vec;
 use walkdir::WalkDir;
 
-#[derive(Serialize, Deserialize)]
-pub struct Segment {
-    pub index: u32,
-    pub size: u32,
-}
+mod config;
+mod control;
+mod encoder;
+mod error;
+mod library;
+mod migrations;
+mod pipe;
+mod pipeline;
+mod plan;
+mod probe;
+mod profile;
+mod progress;
+mod queue;
+mod segment;
+mod server;
+mod space;
+mod upscaler;
+mod watch;
+mod workspace;
+pub use config::*;
+pub use control::*;
+pub use encoder::*;
+pub use error::ReveError;
+use error::StderrTail;
+pub use library::*;
+pub use migrations::*;
+pub use pipe::*;
+pub use pipeline::*;
+pub use plan::*;
+pub use probe::*;
+pub use profile::*;
+use progress::FrameCounter;
+pub use progress::*;
+pub use queue::*;
+pub use segment::*;
+pub use server::*;
+pub use space::*;
+pub use upscaler::*;
+pub use watch::*;
+pub use workspace::*;
 
 #[derive(Serialize, Deserialize)]
 pub struct Video {
@@ -41,32 +76,37 @@ pub struct Video {
     pub segment_size: u32,
     pub segment_count: u32,
     pub upscale_ratio: u8,
+    pub upscaler: String,
+    #[serde(default = "default_upscaler_progress")]
+    pub upscaler_progress: String,
+    #[serde(default = "default_model")]
+    pub model: String,
+    pub workspace: Workspace,
 }
 
 impl Video {
-    pub fn new(path: &str, output_path: &str, segment_size: u32, upscale_ratio: u8) -> Video {
-        let frame_count = get_frame_count(&path.to_string());
-        let frame_rate = get_frame_rate(&path.to_string()).parse::<f32>().unwrap();
-
-        let parts_num = (frame_count as f32 / segment_size as f32).ceil() as i32;
-        let last_segment_size = get_last_segment_size(frame_count, segment_size);
-
-        let mut segments = Vec::new();
-        for i in 0..(parts_num - 1) {
-            let frame_number = segment_size;
-            segments.push(Segment {
-                index: i as u32,
-                size: frame_number as u32,
-            });
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        path: &str,
+        output_path: &str,
+        segment_size: u32,
+        upscale_ratio: u8,
+        upscaler: &str,
+        upscaler_progress: &str,
+        model: &str,
+        workspace: Workspace,
+    ) -> Result<Video, ReveError> {
+        let frame_count = get_frame_count(&path.to_string())?;
+        let frame_rate = parse_frame_rate(path, &get_frame_rate(&path.to_string())?)?;
+
+        let mut segments = plan_segments(frame_count, segment_size);
+        // without the index every segment is decoded from the start, slower but exact
+        if let Ok(index) = index_frames(path) {
+            index.locate(&mut segments);
         }
-        segments.push(Segment {
-            index: (parts_num - 1) as u32,
-            size: last_segment_size as u32,
-        });
-
         let segment_count = segments.len() as u32;
 
-        Video {
+        Ok(Video {
             path: path.to_string(),
             output_path: output_path.to_string(),
             segments,
@@ -75,124 +115,101 @@ impl Video {
             segment_size,
             segment_count,
             upscale_ratio,
-        }
+            upscaler: upscaler.to_string(),
+            upscaler_progress: upscaler_progress.to_string(),
+            model: model.to_string(),
+            workspace,
+        })
     }
 
-    pub fn export_segment(&self, index: usize) -> Result<BufReader<ChildStderr>, Error> {
-        let index_dir = format!("temp\\tmp_frames\\{}", index);
-        fs::create_dir(&index_dir).unwrap();
-
-        let output_path = format!("temp\\tmp_frames\\{}\\frame%08d.png", index);
-        let start_time = if index == 0 {
-            String::from("0")
-        } else {
-            ((index as u32 * self.segment_size - 1) as f32 / self.frame_rate).to_string()
-        };
-        let segments_index = if self.segments.len() == 1 { 0 } else { 1 };
-        let stderr = Command::new("ffmpeg")
-            .args([
-                "-v",
-                "verbose",
-                "-ss",
-                &start_time,
-                "-i",
-                &self.path.to_string(),
-                "-qscale:v",
-                "1",
-                "-qmin",
-                "1",
-                "-qmax",
-                "1",
-                "-vsync",
-                "0",
-                "-vframes",
-                &self.segments[segments_index].size.to_string(),
-                &output_path,
-            ])
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()?
-            .stderr
-            .ok_or_else(|| Error::new(ErrorKind::Other, "Could not capture standard output."))?;
-        Ok(BufReader::new(stderr))
+    /// Exports the frames of segment `index` to its tmp frames dir, reporting them to `progress`.
+    /// `control` can cancel or pause the ffmpeg doing it.
+    pub fn export_segment(
+        &self,
+        index: usize,
+        progress: &dyn ProgressSink,
+        control: &JobControl,
+    ) -> Result<(), ReveError> {
+        let index_dir = self.workspace.tmp_frames_dir(index as u32);
+        fs::create_dir(&index_dir)?;
+
+        let output_path = index_dir.join("frame%08d.png").display().to_string();
+        let segment = self.segments.get(index).ok_or_else(|| no_segment(index))?;
+        export_frames(
+            &self.path,
+            &output_path,
+            segment,
+            ExtractMode::Exact,
+            self.frame_rate,
+            None,
+            progress,
+            control,
+        )
     }
 
-    pub fn upscale_segment(&self, index: usize) -> Result<BufReader<ChildStderr>, Error> {
-        let input_path = format!("temp\\tmp_frames\\{}", index);
-        let output_path = format!("temp\\out_frames\\{}", index);
-        fs::create_dir(&output_path).expect("could not create directory");
-
-        let stderr = Command::new("realesrgan-ncnn-vulkan")
-            .args([
-                "-i",
-                &input_path,
-                "-o",
-                &output_path,
-                "-n",
-                "realesr-animevideov3-x2",
-                "-s",
-                &self.upscale_ratio.to_string(),
-                "-f",
-                "png",
-                "-v",
-            ])
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()?;
-
-        // get the output of the command
-        let error = stderr
-            .stderr
-            .ok_or_else(|| Error::new(ErrorKind::Other, "Could not capture standard output."))?;
-        let output = stderr
-            .stdout
-            .ok_or_else(|| Error::new(ErrorKind::Other, "Could not capture standard output."))?;
-
-        // read the output of the command
-        let _output = BufReader::new(output);
-
-        // for each line of the output that contains "done", print it
-        for line in BufReader::new(_output).lines() {
-            let line = line.unwrap();
-            if line.contains("done") {
-                println!("{}", line);
-            }
-        }
-        Ok(BufReader::new(error))
+    /// Upscales the exported frames of segment `index`, reporting them to `progress`.
+    pub fn upscale_segment(
+        &self,
+        index: usize,
+        progress: &dyn ProgressSink,
+        control: &JobControl,
+    ) -> Result<(), ReveError> {
+        let input_path = self
+            .workspace
+            .tmp_frames_dir(index as u32)
+            .display()
+            .to_string();
+        let output_path = self
+            .workspace
+            .out_frames_dir(index as u32)
+            .display()
+            .to_string();
+        fs::create_dir(&output_path)?;
+
+        let segment = self.segments.get(index).ok_or_else(|| no_segment(index))?;
+        let upscaler = get_upscaler(&self.upscaler, &self.upscaler_progress)?;
+        upscale_frames(
+            upscaler.as_ref(),
+            &input_path,
+            &output_path,
+            self.upscale_ratio,
+            &self.model,
+            segment,
+            progress,
+            control,
+        )
     }
 
     // TODO: args builder for custom commands
-    pub fn merge_segment(&self, args: Vec<&str>) -> Result<BufReader<ChildStderr>, Error> {
+    pub fn merge_segment(&self, args: Vec<&str>) -> Result<BufReader<ChildStderr>, ReveError> {
         let mut stderr = Command::new("ffmpeg");
         for arg in args {
             stderr.arg(arg);
         }
-        let stderr = stderr
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()?
+        let stderr = error::spawn(stderr.stdout(Stdio::piped()).stderr(Stdio::piped()))?
             .stderr
-            .ok_or_else(|| Error::new(ErrorKind::Other, "Could not capture standard output."))?;
+            .ok_or_else(no_pipe)?;
 
         Ok(BufReader::new(stderr))
     }
 
-    pub fn concatenate_segments(&self) {
-        let mut f_content = String::from("file 'video_parts\\0.mp4'");
+    pub fn concatenate_segments(&self) -> Result<(), ReveError> {
+        let parts_list = self.workspace.parts_list().display().to_string();
+        let mut f_content = format!("file '{}'", self.workspace.video_part_entry(0, "mp4"));
         for segment_index in 1..self.segment_count {
-            let video_part_path = format!("video_parts\\{}.mp4", segment_index);
+            let video_part_path = self.workspace.video_part_entry(segment_index, "mp4");
             f_content = format!("{}\nfile '{}'", f_content, video_part_path);
         }
-        fs::write("temp\\parts.txt", f_content).unwrap();
+        fs::write(&parts_list, f_content)?;
 
-        Command::new("ffmpeg")
-            .args([
+        error::run_ffmpeg(
+            Command::new("ffmpeg").args([
                 "-f",
                 "concat",
                 "-safe",
                 "0",
                 "-i",
-                "temp\\parts.txt",
+                &parts_list,
                 "-i",
                 &self.path,
                 "-map",
@@ -206,14 +223,23 @@ impl Video {
                 "-c",
                 "copy",
                 &self.output_path,
-            ])
-            .output()
-            .unwrap();
-        fs::remove_file("temp\\parts.txt").unwrap();
+            ]),
+            "concatenate segments",
+        )?;
+        fs::remove_file(&parts_list)?;
+        Ok(())
     }
 }
 
-#[derive(Parser, Serialize, Deserialize, Debug)]
+fn no_pipe() -> ReveError {
+    ReveError::Io(Error::other("Could not capture standard output."))
+}
+
+fn no_segment(index: usize) -> ReveError {
+    ReveError::InvalidInput(format!("there is no segment {}", index))
+}
+
+#[derive(Parser, Serialize, Deserialize, Debug, Clone)]
 #[clap(name = "Real-ESRGAN Video Enhance",
 author = "ONdraid <ondraid.png@gmail.com>",
 about = "Real-ESRGAN video upscaler with resumability",
@@ -221,40 +247,58 @@ long_about = None)]
 pub struct Args {
     /// input video path (mp4/mkv/...) or folder path (\\... or /... or C:\...)
     #[clap(short = 'i', long, value_parser = input_validation)]
-    pub inputpath: String,
+    pub inputpath: Option<String>,
 
     // maximum resolution (480 by default)
-    #[clap(short = 'r', long, value_parser = max_resolution_validation, default_value = "480")]
+    #[clap(short = 'r', long, env = "REVE_RESOLUTION", value_parser = max_resolution_validation, default_value = "480")]
     pub resolution: Option<String>,
 
     // output video extension format (mp4 by default)
-    #[clap(short = 'f', long, value_parser = format_validation, default_value = "mp4")]
+    #[clap(short = 'f', long, env = "REVE_FORMAT", value_parser = format_validation, default_value = "mp4")]
     pub format: String,
 
     // model name (realesr-animevideov3-x2 by default)
-    #[clap(short = 'm', long, value_parser = model_validation, default_value = "realesr-animevideov3")]
+    #[clap(short = 'm', long, env = "REVE_MODEL", value_parser = model_validation, default_value = "realesr-animevideov3")]
     pub model: String,
 
+    /// upscaler backend (realesrgan, realcugan, waifu2x) or a command template using
+    /// {input_dir} {output_dir} {scale} {model}
+    #[clap(long, env = "REVE_UPSCALER", value_parser = upscaler_validation, default_value = "realesrgan")]
+    #[serde(default = "default_upscaler")]
+    pub upscaler: String,
+
+    /// regex matching the upscaler's per-frame progress lines (command templates only)
+    #[clap(long = "upscaler-progress", value_parser, default_value = "done")]
+    #[serde(default = "default_upscaler_progress")]
+    pub upscaler_progress: String,
+
     /// upscale ratio (2, 3, 4)
-    #[clap(short = 's', long, value_parser = clap::value_parser!(u8).range(2..5), default_value_t = 2)]
+    #[clap(short = 's', long, env = "REVE_SCALE", value_parser = clap::value_parser!(u8).range(2..5), default_value_t = 2)]
     pub scale: u8,
 
-    /// segment size (in frames)
-    #[clap(short = 'P', long = "parts", value_parser, default_value_t = 1000)]
+    /// segment size (in frames), or auto for the largest that fits the free space of the workspace
+    #[clap(
+        short = 'P',
+        long = "parts",
+        env = "REVE_SEGMENT_SIZE",
+        value_parser = segment_size_validation,
+        default_value_t = 1000
+    )]
     pub segmentsize: u32,
 
     /// video constant rate factor (crf: 51-0)
-    #[clap(short = 'c', long = "crf", value_parser = clap::value_parser!(u8).range(0..52), default_value_t = 15)]
+    #[clap(short = 'c', long = "crf", env = "REVE_CRF", value_parser = clap::value_parser!(u8).range(0..52), default_value_t = 15)]
     pub crf: u8,
 
     /// video encoding preset
-    #[clap(short = 'p', long, value_parser = preset_validation, default_value = "slow")]
+    #[clap(short = 'p', long, env = "REVE_PRESET", value_parser = preset_validation, default_value = "slow")]
     pub preset: String,
 
-    /// codec encoding parameters (libsvt_hevc, libsvtav1, libx265)
+    /// video encoder (libx265, libsvt_hevc, libsvtav1, libx264, libvpx-vp9, librav1e, libaom-av1)
     #[clap(
         short = 'e',
         long = "encoder",
+        env = "REVE_ENCODER",
         value_parser = codec_validation,
         default_value = "libx265"
     )]
@@ -264,24 +308,216 @@ pub struct Args {
     #[clap(
         short = 'x',
         long,
+        env = "REVE_ENCODER_PARAMS",
         value_parser,
         default_value = "psy-rd=2:aq-strength=1:deblock=0,0:bframes=8"
     )]
     pub x265params: String,
 
+    /// named settings (anime-fast/anime-archive/live-action or one of profiles.json) used for
+    /// the options not given as flags
+    #[clap(long, env = "REVE_PROFILE", value_parser)]
+    #[serde(default)]
+    pub profile: Option<String>,
+
+    /// how segment frames are extracted: exact (by frame number) or seek (by timestamp)
+    #[clap(long, value_enum, default_value_t = ExtractMode::Exact)]
+    #[serde(default)]
+    pub extract: ExtractMode,
+
+    /// pipe frames between ffmpeg and the upscaler instead of exporting segments as PNG files
+    #[clap(long, action)]
+    #[serde(default)]
+    pub pipe: bool,
+
+    /// frames kept on disk at once in pipe mode when the upscaler can't stream
+    #[clap(long = "pipe-window", value_parser = clap::value_parser!(u32).range(1..), default_value_t = 16)]
+    #[serde(default = "default_pipe_window")]
+    pub pipe_window: u32,
+
+    /// folder for temporary frames and video parts (/dev/shm/reve, or ./temp without /dev/shm)
+    #[clap(long, env = "REVE_WORKDIR", value_parser)]
+    #[serde(default)]
+    pub workdir: Option<String>,
+
+    /// keep running and upscale videos as they are added to the input folder
+    #[clap(long, action)]
+    #[serde(default)]
+    pub watch: bool,
+
+    /// seconds between two looks at the watched folder
+    #[clap(long = "watch-interval", value_parser = clap::value_parser!(u64).range(1..), default_value_t = 10)]
+    #[serde(default = "default_watch_interval")]
+    pub watch_interval: u64,
+
+    /// print what would be upscaled, without running ffmpeg or the upscaler or writing to reve.db
+    #[clap(long = "dry-run", action)]
+    #[serde(default)]
+    pub dry_run: bool,
+
     // (Optional) output video path (file.mp4/mkv/...)
     #[clap(short = 'o', long, value_parser = output_validation)]
     pub outputpath: Option<String>,
+
+    #[clap(subcommand)]
+    #[serde(skip)]
+    pub command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Commands {
+    /// manage the job queue kept in reve.db
+    #[clap(subcommand)]
+    Queue(QueueCommand),
+    /// run queued jobs and accept new ones over HTTP on localhost
+    Serve {
+        #[clap(long, default_value_t = 8765)]
+        port: u16,
+    },
+    /// inspect and edit the library of upscaled folders kept in reve.db
+    #[clap(subcommand)]
+    Db(DbCommand),
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum DbCommand {
+    /// list the videos of the library
+    List {
+        #[clap(flatten)]
+        filter: LibraryFilter,
+    },
+    /// count the videos of the library by status and codec
+    Stats,
+    /// set the videos with a status back to another one, processing to pending by default
+    Reset {
+        #[clap(long, value_parser = status_validation, default_value = "processing")]
+        status: String,
+        #[clap(long, value_parser = status_validation, default_value = "pending")]
+        to: String,
+    },
+    /// remove a video, or every video of a folder, from the library
+    Forget { path: String },
+    /// write the videos of the library to stdout
+    Export {
+        #[clap(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+        #[clap(flatten)]
+        filter: LibraryFilter,
+    },
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum QueueCommand {
+    /// queue the input file, or every video in the input folder, with the given settings
+    /// (reve -i <INPUT> [OPTIONS] queue add)
+    Add {
+        /// jobs with a higher priority run first
+        #[clap(long, default_value_t = 0, allow_negative_numbers = true)]
+        priority: i64,
+    },
+    /// list queued, running and finished jobs
+    List,
+    /// change the priority of a job
+    Reorder {
+        id: i64,
+        #[clap(allow_negative_numbers = true)]
+        priority: i64,
+    },
+    /// remove a job that isn't running
+    Remove { id: i64 },
+    /// run queued jobs until the queue is empty
+    Run,
+}
+
+impl Args {
+    /// `Args::try_parse_from`, then the options left at their default (neither given as a flag
+    /// nor in their REVE_* environment variable) are taken from the `--profile`, if any, and
+    /// then from `config`.
+    pub fn try_parse_with_config<I, T>(
+        itr: I,
+        config: &ReveConfig,
+        profiles: &Profiles,
+    ) -> Result<Args, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let mut command = Args::command();
+        let matches = command.try_get_matches_from_mut(itr)?;
+        let mut args = Args::from_arg_matches(&matches)?;
+        let settings = match &args.profile {
+            Some(name) => match profiles.get(name) {
+                Some(profile) => profile.settings.or(config),
+                None => {
+                    return Err(command.error(
+                        clap::error::ErrorKind::InvalidValue,
+                        format!("unknown profile {}, valid: {}", name, profiles.names()),
+                    ))
+                }
+            },
+            None => config.clone(),
+        };
+        settings.apply(&mut args, &matches);
+        Ok(args)
+    }
+
+    /// The `-i` file or folder, empty for subcommands run without one.
+    pub fn input(&self) -> &str {
+        self.inputpath.as_deref().unwrap_or_default()
+    }
+
+    /// Command line arguments reproducing these settings for a single run, without the
+    /// subcommand, `--watch` and `--dry-run`.
+    pub fn to_cli_args(&self) -> Vec<String> {
+        let mut cli_args = vec![
+            format!("--inputpath={}", self.input()),
+            format!("--format={}", self.format),
+            format!("--model={}", self.model),
+            format!("--upscaler={}", self.upscaler),
+            format!("--upscaler-progress={}", self.upscaler_progress),
+            format!("--scale={}", self.scale),
+            match self.segmentsize {
+                0 => String::from("--parts=auto"),
+                segment_size => format!("--parts={}", segment_size),
+            },
+            format!("--crf={}", self.crf),
+            format!("--preset={}", self.preset),
+            format!("--encoder={}", self.codec),
+            format!("--x265params={}", self.x265params),
+            format!(
+                "--extract={}",
+                self.extract.to_possible_value().unwrap().get_name()
+            ),
+            format!("--pipe-window={}", self.pipe_window),
+        ];
+        if let Some(resolution) = &self.resolution {
+            cli_args.push(format!("--resolution={}", resolution));
+        }
+        if self.pipe {
+            cli_args.push(String::from("--pipe"));
+        }
+        if let Some(workdir) = &self.workdir {
+            cli_args.push(format!("--workdir={}", workdir));
+        }
+        if let Some(outputpath) = &self.outputpath {
+            cli_args.push(format!("--outputpath={}", outputpath));
+        }
+        cli_args
+    }
 }
 
 fn input_validation(s: &str) -> Result<String, String> {
     let p = Path::new(s);
 
     // if the path in p contains a double quote, remove it and everything after it
-    if p.to_str().unwrap().contains("\"") {
-        let mut s = p.to_str().unwrap().to_string();
-        s.truncate(s.find("\"").unwrap());
-        return Ok(s);
+    if let Some(quote) = s.find('"') {
+        return Ok(s[..quote].to_string());
     }
 
     if p.is_dir() {
@@ -292,9 +528,9 @@ fn input_validation(s: &str) -> Result<String, String> {
         return Err(String::from_str("input path not found").unwrap());
     }
 
-    match p.extension().unwrap().to_str().unwrap() {
-        "mp4" | "mkv" | "avi" => Ok(s.to_string()),
-        _ => Err(String::from_str("valid input formats: mp4/mkv/avi").unwrap()),
+    match p.extension().and_then(|e| e.to_str()) {
+        Some("mp4" | "mkv" | "avi") => Ok(s.to_string()),
+        _ => Err(String::from("valid input formats: mp4/mkv/avi")),
     }
 }
 
@@ -302,12 +538,11 @@ pub fn output_validation(s: &str) -> Result<String, String> {
     let p = Path::new(s);
 
     if p.exists() {
-        println!("{} already exists!", &s);
-        exit(1);
+        Err(format!("{} already exists!", &s))
     } else {
-        match p.extension().unwrap().to_str().unwrap() {
-            "mp4" | "mkv" | "avi" => Ok(s.to_string()),
-            _ => Err(String::from_str("valid input formats: mp4/mkv/avi").unwrap()),
+        match p.extension().and_then(|e| e.to_str()) {
+            Some("mp4" | "mkv" | "avi") => Ok(s.to_string()),
+            _ => Err(String::from("valid input formats: mp4/mkv/avi")),
         }
     }
 }
@@ -318,28 +553,63 @@ pub fn output_validation_dir(s: &str) -> Result<String, String> {
     if p.exists() {
         return Ok("already exists".to_string());
     } else {
-        match p.extension().unwrap().to_str().unwrap() {
-            "mp4" | "mkv" | "avi" => Ok(s.to_string()),
-            _ => Err(String::from_str("valid input formats: mp4/mkv/avi").unwrap()),
+        match p.extension().and_then(|e| e.to_str()) {
+            Some("mp4" | "mkv" | "avi") => Ok(s.to_string()),
+            _ => Err(String::from("valid input formats: mp4/mkv/avi")),
         }
     }
 }
 
-fn format_validation(s: &str) -> Result<String, String> {
+/// Checks a `--format` value.
+pub fn format_validation(s: &str) -> Result<String, String> {
     match s {
         "mp4" | "mkv" | "avi" => Ok(s.to_string()),
         _ => Err(String::from_str("valid output formats: mp4/mkv/avi").unwrap()),
     }
 }
 
-fn model_validation(s: &str) -> Result<String, String> {
-    match s {
-        "realesr-animevideov3" => Ok(s.to_string()),
-        _ => Err(String::from_str("valid: realesr-animevideov3").unwrap()),
+/// Checks a `--model` value.
+pub fn model_validation(s: &str) -> Result<String, String> {
+    // model names are backend specific, only make sure it can't escape the models folder
+    if !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+    {
+        Ok(s.to_string())
+    } else {
+        Err(String::from_str("valid: realesr-animevideov3 or another model name").unwrap())
     }
 }
 
-fn max_resolution_validation(s: &str) -> Result<String, String> {
+/// Checks a `--upscaler` value.
+pub fn upscaler_validation(s: &str) -> Result<String, String> {
+    get_upscaler(s, "done")
+        .map(|_| s.to_string())
+        .map_err(|e| e.to_string())
+}
+
+fn default_model() -> String {
+    String::from(REALESRGAN_MODEL)
+}
+
+fn default_upscaler() -> String {
+    String::from("realesrgan")
+}
+
+fn default_upscaler_progress() -> String {
+    String::from("done")
+}
+
+fn default_pipe_window() -> u32 {
+    16
+}
+
+fn default_watch_interval() -> u64 {
+    10
+}
+
+/// Checks a `--resolution` value.
+pub fn max_resolution_validation(s: &str) -> Result<String, String> {
     let validate = s.parse::<f64>().is_ok();
     match validate {
         true => Ok(s.to_string()),
@@ -347,7 +617,21 @@ fn max_resolution_validation(s: &str) -> Result<String, String> {
     }
 }
 
-fn preset_validation(s: &str) -> Result<String, String> {
+/// Parses a `--parts` value, `auto` is 0.
+pub fn segment_size_validation(s: &str) -> Result<u32, String> {
+    match s {
+        "auto" => Ok(0),
+        _ => match s.parse::<u32>() {
+            Ok(0) | Err(_) => Err(String::from(
+                "valid: a number of frames (at least 1) or auto",
+            )),
+            Ok(segment_size) => Ok(segment_size),
+        },
+    }
+}
+
+/// Checks a `--preset` value.
+pub fn preset_validation(s: &str) -> Result<String, String> {
     match s {
         "ultrafast" | "superfast" | "veryfast" | "faster" | "fast" | "medium" | "slow"
         | "slower" | "veryslow" => Ok(s.to_string()),
@@ -358,339 +642,189 @@ fn preset_validation(s: &str) -> Result<String, String> {
     }
 }
 
-fn codec_validation(s: &str) -> Result<String, String> {
-    match s {
-        "libx265" | "libsvt_hevc" | "libsvtav1" => Ok(s.to_string()),
-        _ => Err(String::from_str("valid: libx265/libsvt_hevc/libsvtav1").unwrap()),
+/// Checks a `--encoder` value.
+pub fn codec_validation(s: &str) -> Result<String, String> {
+    match get_encoder_profile(s) {
+        Some(_) => Ok(s.to_string()),
+        None => Err(format!(
+            "valid: {}",
+            encoder_profiles()
+                .iter()
+                .map(|p| p.name.as_str())
+                .collect::<Vec<&str>>()
+                .join("/")
+        )),
     }
 }
 
 pub fn get_last_segment_size(frame_count: u32, segment_size: u32) -> u32 {
-    let last_segment_size = (frame_count % segment_size) as u32;
+    let last_segment_size = frame_count % segment_size;
     if last_segment_size == 0 {
         segment_size
     } else {
-        last_segment_size - 1
+        last_segment_size
     }
 }
 
-pub fn rebuild_temp(keep_args: bool) {
-    let _ = fs::create_dir("temp");
-    if !keep_args {
-        println!("removing temp");
-        fs::remove_dir_all("temp").expect("could not remove temp. try deleting manually");
-
-        for dir in ["temp\\tmp_frames", "temp\\out_frames", "temp\\video_parts"] {
-            println!("creating {}", dir);
-            fs::create_dir_all(dir).unwrap();
-        }
-    } else {
-        for dir in ["temp\\tmp_frames", "temp\\out_frames"] {
-            println!("removing {}", dir);
-            fs::remove_dir_all(dir)
-                .unwrap_or_else(|_| panic!("could not remove {:?}. try deleting manually", dir));
-            println!("creating {}", dir);
-            fs::create_dir_all(dir).unwrap();
-        }
-        println!("removing parts.txt");
-        let _ = fs::remove_file("temp\\parts.txt");
-    }
+/// What `add_to_db` found in a folder.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AddSummary {
+    /// new entries of the library, also when they replace another video at the same path
+    pub added: u64,
+    /// already in the library at the same path
+    pub known: u64,
+    /// in the library under a path that is gone
+    pub moved: u64,
+    /// the same content as another file of the library
+    pub copies: u64,
+    /// of the files above, copies aside, the ones taller than the resolution limit
+    pub skipped: u64,
 }
 
+/// Records the videos of `files` in video_info by their content fingerprint and returns what
+/// it found with the files no taller than `res` to upscale. A file with the content of another
+/// one of the library isn't upscaled twice.
 pub fn add_to_db(
     files: Vec<String>,
     res: String,
     bar: ProgressBar,
-) -> Result<(Vec<AtomicI32>, Arc<Mutex<Vec<std::string::String>>>)> {
-    let count: AtomicI32 = AtomicI32::new(0);
-    let db_count;
-    let db_count_added: AtomicI32 = AtomicI32::new(0);
-    let db_count_skipped: AtomicI32 = AtomicI32::new(0);
-    let files_to_process: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
-    let conn = Connection::open("reve.db")?;
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS video_info (
-                    id INTEGER PRIMARY KEY,
-                    filename TEXT NOT NULL,
-                    filepath TEXT NOT NULL,
-                    width INTEGER NOT NULL,
-                    height INTEGER NOT NULL,
-                    duration REAL NOT NULL,
-                    pixel_format TEXT NOT NULL,
-                    display_aspect_ratio TEXT NOT NULL,
-                    sample_aspect_ratio TEXT NOT NULL,
-                    format TEXT NOT NULL,
-                    size BIGINT NOT NULL,
-                    folder_size BIGINT NOT NULL,
-                    bitrate BIGINT NOT NULL,
-                    codec TEXT NOT NULL,
-                    resolution TEXT NOT NULL,
-                    status TEXT NOT NULL,
-                    hash TEXT NOT NULL
-                  )",
-        params![],
-    )?;
-
-    let filenames_skip = files.clone();
-    let mut filenames = files;
-
-    // get all items in db
-    let mut stmt = conn.prepare("SELECT * FROM video_info")?;
-    let mut rows = stmt
-        .query_map(params![], |row| {
-            Ok((
-                row.get(1)?,
-                row.get(2)?,
-                row.get(3)?,
-                row.get(4)?,
-                row.get(5)?,
-                row.get(6)?,
-                row.get(7)?,
-                row.get(8)?,
-                row.get(9)?,
-                row.get(10)?,
-                row.get(11)?,
-                row.get(12)?,
-                row.get(13)?,
-                row.get(14)?,
-                row.get(15)?,
-                row.get(16)?,
-            ))
-        })
-        .unwrap();
-    let mut db_items: Vec<(
-        String,
-        String,
-        i32,
-        i32,
-        f64,
-        String,
-        String,
-        String,
-        String,
-        i64,
-        i64,
-        i64,
-        String,
-        String,
-        String,
-        String,
-    )> = Vec::new();
-    while let Some(row) = rows.next() {
-        let row = row.unwrap();
-        db_items.push(row);
-    }
-    // get all items from filenames that are not in db
-    let mut filenames_to_process: Vec<String> = Vec::new();
-    for filename in filenames {
-        let real_filename = Path::new(&filename).file_name().unwrap().to_str().unwrap();
-        let mut found = false;
-        for item in &db_items {
-            if item.0 == real_filename {
-                found = true;
-                break;
-            }
-        }
-        if !found {
-            filenames_to_process.push(filename);
-        }
-    }
+) -> Result<(AddSummary, Vec<String>), ReveError> {
+    let max_height = res
+        .parse::<i64>()
+        .map_err(|_| ReveError::InvalidInput(format!("invalid resolution {}", res)))?;
+    add_files_to_db(
+        open_db("reve.db")?,
+        files,
+        max_height,
+        bar,
+        get_ffprobe_output,
+    )
+}
 
-    // get all the items from filenames that are in db
-    let mut filenames_to_skip: Vec<String> = Vec::new();
-    for filename in filenames_skip {
-        let real_filename = Path::new(&filename).file_name().unwrap().to_str().unwrap();
-        let mut found = false;
-        for item in &db_items {
-            if item.0 == real_filename {
-                found = true;
-                break;
-            }
-        }
-        if found {
-            filenames_to_skip.push(filename);
-        }
-    }
-    db_count = AtomicI32::new(filenames_to_skip.len() as i32);
-
-    // print count for all items in filenames_to_process and return filenames with all items in db removed
-    println!("Found {} files not in database", filenames_to_process.len());
-    filenames = filenames_to_process.clone();
-
-    bar.set_length(filenames.len() as u64);
-    let conn = Arc::new(Mutex::new(Connection::open("reve.db")?));
-
-    filenames.par_iter().for_each(|filename| {
-        let real_filename = Path::new(filename).file_name().unwrap().to_str().unwrap();
-        let conn = conn.clone();
-        let conn = conn.lock().unwrap();
-        let mut stmt = conn.prepare("SELECT * FROM video_info WHERE filename=?1").unwrap();
-        let file_exists: bool = stmt.exists(params![real_filename]).unwrap();
-        if !file_exists {
-            let output = Command::new("ffprobe")
-                .args([
-                    "-i",
-                    filename,
-                    "-v",
-                    "error",
-                    "-select_streams",
-                    "v",
-                    "-show_entries",
-                    "stream",
-                    "-show_format",
-                    "-show_data_hash",
-                    "sha256",
-                    "-show_streams",
-                    "-of",
-                    "json"
-                ])
-                .output()
-                .expect("failed to execute process");
-            let json_value: Value = serde_json::from_str(&String::from_utf8_lossy(&output.stdout)).unwrap();
-            let json_str = json_value.to_string();
-            if &json_str.len() >= &1 {
-                let values: Value = json_value;
-                let _width = values["streams"][0]["width"].as_i64().unwrap_or(0);
-                let _height = values["streams"][0]["height"].as_i64().unwrap_or(0);
-                let filepath = values["format"]["filename"].as_str().unwrap();
-                let filename = Path::new(filepath).file_name().unwrap().to_str().unwrap();
-                let size = values["format"]["size"].as_str().unwrap_or("0");
-                let bitrate = values["format"]["bit_rate"].as_str().unwrap_or("0");
-                let duration = values["format"]["duration"].as_str().unwrap_or("0.0");
-                let format = values["format"]["format_name"].as_str().unwrap_or("NaN");
-                let width = values["streams"][0]["width"].as_i64().unwrap_or(0);
-                let height = values["streams"][0]["height"].as_i64().unwrap_or(0);
-                let codec = values["streams"][0]["codec_name"].as_str().unwrap_or("NaN");
-                let pix_fmt = values["streams"][0]["pix_fmt"].as_str().unwrap_or("NaN");
-                let checksum = values["streams"][0]["extradata_hash"].as_str().unwrap_or("NaN");
-                let dar = values["streams"][0]["display_aspect_ratio"].as_str().unwrap_or("NaN");
-                let sar = values["streams"][0]["sample_aspect_ratio"].as_str().unwrap_or("NaN");
-
-                // for each file in this folder and it's subfodlers, sum the size of the files
-                let mut folder_size = 0;
-                for entry in WalkDir::new(Path::new(filepath).parent().unwrap()) {
-                    let entry = entry.unwrap();
-                    let metadata = fs::metadata(entry.path());
-                    folder_size += metadata.unwrap().len() as i64;
+/// `add_to_db` into the library of `conn`, with `probe` reading the ffprobe output of a file.
+///
+/// New files are upscaled, files the library already knows only while their status is pending
+/// or processing, so a done file isn't upscaled again.
+pub fn add_files_to_db(
+    conn: Connection,
+    files: Vec<String>,
+    max_height: i64,
+    bar: ProgressBar,
+    probe: impl Fn(&str) -> Result<Value, ReveError> + Sync,
+) -> Result<(AddSummary, Vec<String>), ReveError> {
+    let added = AtomicU64::new(0);
+    let known = AtomicU64::new(0);
+    let moved = AtomicU64::new(0);
+    let copies = AtomicU64::new(0);
+    let skipped = AtomicU64::new(0);
+    let files_to_process: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    let conn = Mutex::new(conn);
+
+    bar.set_length(files.len() as u64);
+    files
+        .par_iter()
+        .try_for_each(|filename| -> Result<(), ReveError> {
+            let values = probe(filename)?;
+            let duration = values["format"]["duration"]
+                .as_str()
+                .and_then(|duration| duration.parse::<f64>().ok())
+                .unwrap_or(0.0);
+            let fingerprint = fingerprint_file(filename, duration)?;
+
+            // looked up and recorded under one lock, two copies of a new file can't both be added
+            let conn = conn.lock().unwrap();
+            let record = record_video(&conn, filename, &fingerprint, &values, max_height)?;
+            let unfinished = || -> Result<bool, ReveError> {
+                let status = get_db_status(&conn, filename)?;
+                Ok(matches!(status.as_deref(), Some("pending" | "processing")))
+            };
+            let upscale = match &record {
+                LibraryRecord::Added | LibraryRecord::Replaced => {
+                    added.fetch_add(1, Ordering::SeqCst);
+                    true
                 }
-                //println!("{}", folder_size);
-
-                if height <= res.parse::<i64>().unwrap() {
-                    conn.execute(
-                        "INSERT INTO video_info (filename, filepath, width, height, duration, pixel_format, display_aspect_ratio, sample_aspect_ratio, format, size, folder_size, bitrate, codec, resolution, status, hash) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
-                        params![filename, filepath, width, height, duration, pix_fmt, dar, sar, format, size, folder_size, bitrate, codec, res, "pending", checksum]
-                    ).unwrap();
-                    count.fetch_add(1, Ordering::SeqCst);
-                    db_count_added.fetch_add(1, Ordering::SeqCst);
-                } else {
-                    //db_count_skipped.fetch_add(1, Ordering::SeqCst);
-                    conn.execute(
-                        "INSERT INTO video_info (filename, filepath, width, height, duration, pixel_format, display_aspect_ratio, sample_aspect_ratio, format, size, folder_size, bitrate, codec, resolution, status, hash) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
-                        params![filename, filepath, width, height, duration, pix_fmt, dar, sar, format, size, folder_size, bitrate, codec, res, "skipped", checksum]
-                    ).unwrap();
-                    count.fetch_add(1, Ordering::SeqCst);
-                    db_count_added.fetch_add(1, Ordering::SeqCst);
+                LibraryRecord::Known => {
+                    known.fetch_add(1, Ordering::SeqCst);
+                    unfinished()?
                 }
-            }
-        }
+                LibraryRecord::Moved { from } => {
+                    println!("{} moved to {}", from, filename);
+                    moved.fetch_add(1, Ordering::SeqCst);
+                    unfinished()?
+                }
+                LibraryRecord::Copy { of } => {
+                    println!("{} is a copy of {}, skipping", filename, of);
+                    copies.fetch_add(1, Ordering::SeqCst);
+                    false
+                }
+            };
+            drop(conn);
 
-        // TODO check if all files in db then return only the ones that need to be processed
-        let height = get_ffprobe_output(filename).unwrap();
-        let height_value = height["streams"][0]["height"].as_i64().unwrap_or(0);
-        if height_value <= res.parse::<i64>().unwrap() {
-            files_to_process.lock().unwrap().push(filename.to_string());
-        }
+            let height = values["streams"][0]["height"].as_i64().unwrap_or(0);
+            if height > max_height && !matches!(record, LibraryRecord::Copy { .. }) {
+                skipped.fetch_add(1, Ordering::SeqCst);
+            } else if upscale {
+                files_to_process.lock().unwrap().push(filename.to_string());
+            }
 
-        bar.inc(1);
-    });
+            bar.inc(1);
+            Ok(())
+        })?;
 
-    // return all the counters
     Ok((
-        vec![count, db_count, db_count_added, db_count_skipped],
-        files_to_process,
+        AddSummary {
+            added: added.into_inner(),
+            known: known.into_inner(),
+            moved: moved.into_inner(),
+            copies: copies.into_inner(),
+            skipped: skipped.into_inner(),
+        },
+        files_to_process.into_inner().unwrap(),
     ))
 }
 
-pub fn update_db_status(
-    conn: &Connection,
-    filepath: &str,
-    status: &str,
-) -> Result<(), rusqlite::Error> {
+pub fn update_db_status(conn: &Connection, filepath: &str, status: &str) -> Result<(), ReveError> {
     let mut stmt = conn.prepare("UPDATE video_info SET status=?1 WHERE filepath=?2")?;
     stmt.execute(params![status, filepath])?;
     Ok(())
 }
 
-pub fn create_db_table(conn: &Connection) -> Result<(), rusqlite::Error> {
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS video_info (
-            id INTEGER PRIMARY KEY,
-            filename TEXT NOT NULL,
-            filepath TEXT NOT NULL,
-            width INTEGER NOT NULL,
-            height INTEGER NOT NULL,
-            duration TEXT NOT NULL,
-            pixel_format TEXT NOT NULL,
-            display_aspect_ratio TEXT NOT NULL,
-            sample_aspect_ratio TEXT NOT NULL,
-            format TEXT NOT NULL,
-            size TEXT NOT NULL,
-            folder_size INTEGER NOT NULL,
-            bitrate TEXT NOT NULL,
-            codec TEXT NOT NULL,
-            resolution TEXT NOT NULL,
-            status TEXT NOT NULL,
-            hash TEXT NOT NULL
-        )",
-        params![],
-    )?;
-    Ok(())
+/// Status of `filepath` in video_info, None if it isn't in the database.
+pub fn get_db_status(conn: &Connection, filepath: &str) -> Result<Option<String>, ReveError> {
+    let mut stmt = conn.prepare("SELECT status FROM video_info WHERE filepath=?1")?;
+    let mut rows = stmt.query(params![filepath])?;
+    match rows.next()? {
+        Some(row) => Ok(Some(row.get(0)?)),
+        None => Ok(None),
+    }
 }
 
-pub fn get_ffprobe_output(filename: &str) -> Result<Value, String> {
-    let output: Output = Command::new("ffprobe")
-        .args([
-            "-i",
-            filename,
-            "-v",
-            "error",
-            "-select_streams",
-            "v",
-            "-show_entries",
-            "stream",
-            "-show_format",
-            "-show_data_hash",
-            "sha256",
-            "-show_streams",
-            "-of",
-            "json",
-        ])
-        .output()
-        .map_err(|e| e.to_string())?;
+pub fn get_ffprobe_output(filename: &str) -> Result<Value, ReveError> {
+    let output: Output = error::output(Command::new("ffprobe").args([
+        "-i",
+        filename,
+        "-v",
+        "error",
+        "-select_streams",
+        "v",
+        "-show_entries",
+        "stream",
+        "-show_format",
+        "-show_data_hash",
+        "sha256",
+        "-show_streams",
+        "-of",
+        "json",
+    ]))?;
 
     if output.status.success() {
-        let output_str = String::from_utf8(output.stdout).map_err(|e| e.to_string())?;
-        let value: Value = from_str(&output_str).map_err(|e| e.to_string())?;
+        let output_str = String::from_utf8_lossy(&output.stdout);
+        let value: Value = from_str(&output_str).map_err(|e| ReveError::probe(filename, e))?;
         Ok(value)
     } else {
-        Err(String::from_utf8(output.stderr).unwrap_or_else(|e| e.to_string()))
-    }
-}
-
-#[cfg(target_os = "linux")]
-pub fn dev_shm_exists() -> Result<(), std::io::Error> {
-    let path = "/dev/shm";
-    let b: bool = Path::new(path).is_dir();
-
-    if b == true {
-        fs::create_dir_all("/dev/shm/tmp_frames")?;
-        fs::create_dir_all("/dev/shm/out_frames")?;
-        fs::create_dir_all("/dev/shm/video_parts")?;
-        Ok(())
-    } else {
-        Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "dev/shm does not exist!",
+        Err(ReveError::probe(
+            filename,
+            String::from_utf8_lossy(&output.stderr),
         ))
     }
 }
@@ -700,9 +834,9 @@ pub fn copy_streams_no_bin_data(
     copy_input_path: &String,
     output_path: &String,
     //ffmpeg_args: &String,
-) -> std::process::Output {
-    Command::new("ffmpeg")
-        .args([
+) -> Result<Output, ReveError> {
+    error::run_ffmpeg(
+        Command::new("ffmpeg").args([
             "-hide_banner",
             "-v",
             "error",
@@ -722,18 +856,18 @@ pub fn copy_streams_no_bin_data(
             "-c",
             "copy",
             output_path,
-        ])
-        .output()
-        .expect("failed to execute process")
+        ]),
+        "copy streams",
+    )
 }
 
 pub fn copy_streams(
     video_input_path: &String,
     copy_input_path: &String,
     output_path: &String,
-) -> std::process::Output {
-    Command::new("ffmpeg")
-        .args([
+) -> Result<Output, ReveError> {
+    error::run_ffmpeg(
+        Command::new("ffmpeg").args([
             "-hide_banner",
             "-v",
             "error",
@@ -751,9 +885,9 @@ pub fn copy_streams(
             "-c",
             "copy",
             output_path,
-        ])
-        .output()
-        .expect("failed to execute process")
+        ]),
+        "copy streams",
+    )
 }
 
 pub fn absolute_path(path: impl AsRef<Path>) -> String {
@@ -762,19 +896,25 @@ pub fn absolute_path(path: impl AsRef<Path>) -> String {
     let absolute_path = if path.is_absolute() {
         path.to_path_buf()
     } else {
-        env::current_dir()
-            .expect("could not get current path")
-            .join(path)
+        env::current_dir().unwrap_or_default().join(path)
     }
     .clean();
 
-    absolute_path.into_os_string().into_string().unwrap()
+    absolute_path.to_string_lossy().to_string()
 }
 
-pub fn walk_count(dir: &String) -> usize {
+/// File name of `path`, or `path` itself if it has none.
+fn file_name(path: &str) -> &str {
+    Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(path)
+}
+
+pub fn walk_count(dir: &str) -> usize {
     let mut count = 0;
     for e in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
-        if e.metadata().unwrap().is_file() {
+        if e.file_type().is_file() {
             let filepath = e.path().display();
             let str_filepath = filepath.to_string();
             //println!("{}", filepath);
@@ -789,12 +929,12 @@ pub fn walk_count(dir: &String) -> usize {
     return count;
 }
 
-pub fn walk_files(dir: &String) -> Vec<String> {
+pub fn walk_files(dir: &str) -> Vec<String> {
     let mut arr = vec![];
     let mut index = 0;
 
     for e in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
-        if e.metadata().unwrap().is_file() {
+        if e.file_type().is_file() {
             let filepath = e.path().display();
             let str_filepath = filepath.to_string();
             //println!("{}", filepath);
@@ -832,12 +972,16 @@ pub fn find_mimetype(filename: &String) -> String {
     return res.to_string();
 }
 
-pub fn check_ffprobe_output_i8(data: &str, res: &str) -> Result<i8, Error> {
+pub fn check_ffprobe_output_i8(data: &str, res: &str) -> Result<i8, ReveError> {
     let to_process;
-    let values: Value = serde_json::from_str(data)?;
+    let values: Value = serde_json::from_str(data).map_err(|e| ReveError::probe("video", e))?;
     let height = &values["streams"][0]["height"];
-    let u8_height = height.as_i64().unwrap();
-    let u8_res: i64 = res.parse().unwrap();
+    let u8_height = height
+        .as_i64()
+        .ok_or_else(|| ReveError::probe("video", "no video stream height"))?;
+    let u8_res: i64 = res
+        .parse()
+        .map_err(|_| ReveError::InvalidInput(format!("invalid resolution {}", res)))?;
 
     if u8_res >= u8_height {
         to_process = 1;
@@ -848,433 +992,301 @@ pub fn check_ffprobe_output_i8(data: &str, res: &str) -> Result<i8, Error> {
     return Ok(to_process);
 }
 
-pub fn get_frame_count(input_path: &String) -> u32 {
-    let output = Command::new("ffprobe")
-        .arg("-i")
-        .arg(input_path)
-        .arg("-v")
-        .arg("error")
-        .arg("-select_streams")
-        .arg("v")
-        .arg("-show_entries")
-        .arg("stream=nb_frames")
-        .arg("-of")
-        .arg("default=noprint_wrappers=1:nokey=1")
-        .output()
-        .expect("failed to execute process");
-    let r = String::from_utf8(output.stdout)
-        .unwrap()
-        .trim()
-        .parse::<u32>();
-    match r {
-        Err(_e) => 0,
-        _ => r.unwrap(),
+/// Where a frame count came from, the later ones are less exact.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FrameSource {
+    /// nb_frames of the video stream
+    Stream,
+    /// the NUMBER_OF_FRAMES-eng tag mkv muxers write
+    Tag,
+    /// duration at 25 fps, when neither is there
+    Duration,
+}
+
+impl std::fmt::Display for FrameSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            FrameSource::Stream => "nb_frames",
+            FrameSource::Tag => "NUMBER_OF_FRAMES tag",
+            FrameSource::Duration => "duration at 25 fps",
+        })
     }
 }
 
-pub fn get_frame_count_tag(input_path: &String) -> u32 {
-    let output = Command::new("ffprobe")
-        .arg("-i")
-        .arg(input_path)
-        .arg("-v")
-        .arg("error")
-        .arg("-select_streams")
-        .arg("v")
-        .arg("-show_entries")
-        .arg("stream_tags=NUMBER_OF_FRAMES-eng")
-        .arg("-of")
-        .arg("default=noprint_wrappers=1:nokey=1")
-        .output()
-        .expect("failed to execute process");
-    let r = String::from_utf8(output.stdout)
-        .unwrap()
-        .trim()
-        .parse::<u32>();
+/// Frame count of `input_path` from the stream, then the mkv tag, then the duration.
+pub fn count_frames(input_path: &String) -> Result<(u32, FrameSource), ReveError> {
+    let frames = get_frame_count(input_path)?;
+    if frames > 0 {
+        return Ok((frames, FrameSource::Stream));
+    }
+    let frames = get_frame_count_tag(input_path)?;
+    if frames > 0 {
+        return Ok((frames, FrameSource::Tag));
+    }
+    Ok((get_frame_count_duration(input_path)?, FrameSource::Duration))
+}
+
+pub fn get_frame_count(input_path: &String) -> Result<u32, ReveError> {
+    let r = ffprobe_entry(input_path, "v", "stream=nb_frames")?.parse::<u32>();
     match r {
-        Err(_e) => 0,
-        _ => r.unwrap(),
+        Err(_e) => Ok(0),
+        Ok(r) => Ok(r),
     }
 }
 
-pub fn get_frame_count_duration(input_path: &String) -> u32 {
-    let output = Command::new("ffprobe")
-        .arg("-i")
-        .arg(input_path)
-        .arg("-v")
-        .arg("error")
-        .arg("-select_streams")
-        .arg("v")
-        .arg("-show_entries")
-        .arg("format=duration")
-        .arg("-of")
-        .arg("default=noprint_wrappers=1:nokey=1")
-        .output()
-        .expect("failed to execute process");
-    let r = String::from_utf8(output.stdout)
-        .unwrap()
-        .trim()
-        .parse::<f32>();
+pub fn get_frame_count_tag(input_path: &String) -> Result<u32, ReveError> {
+    let r = ffprobe_entry(input_path, "v", "stream_tags=NUMBER_OF_FRAMES-eng")?.parse::<u32>();
     match r {
-        Err(_e) => 0,
-        _ => (r.unwrap() * 25.0) as u32,
+        Err(_e) => Ok(0),
+        Ok(r) => Ok(r),
     }
 }
 
-pub fn get_display_aspect_ratio(input_path: &String) -> String {
-    let output = Command::new("ffprobe")
-        .arg("-i")
-        .arg(input_path)
-        .arg("-v")
-        .arg("error")
-        .arg("-select_streams")
-        .arg("v")
-        .arg("-show_entries")
-        .arg("stream=display_aspect_ratio")
-        .arg("-of")
-        .arg("default=noprint_wrappers=1:nokey=1")
-        .output()
-        .expect("failed to execute process");
-    let r = String::from_utf8(output.stdout)
-        .unwrap()
-        .trim()
-        .parse::<String>();
+pub fn get_frame_count_duration(input_path: &String) -> Result<u32, ReveError> {
+    let r = ffprobe_entry(input_path, "v", "format=duration")?.parse::<f32>();
     match r {
-        Err(_e) => "0".to_owned(),
-        _ => r.unwrap(),
+        Err(_e) => Ok(0),
+        Ok(r) => Ok((r * 25.0) as u32),
     }
 }
 
-pub fn get_frame_rate(input_path: &String) -> String {
-    let output = Command::new("ffprobe")
-        .arg("-i")
-        .arg(input_path)
-        .arg("-v")
-        .arg("error")
-        .arg("-select_streams")
-        .arg("v")
-        .arg("-show_entries")
-        .arg("stream=avg_frame_rate")
-        .arg("-of")
-        .arg("default=noprint_wrappers=1:nokey=1")
-        .output()
-        .expect("failed to execute process");
-
-    let temp_output = output.clone();
-    let raw_framerate = String::from_utf8(temp_output.stdout)
-        .unwrap()
-        .trim()
-        .to_string();
-    let split_framerate = raw_framerate.split("/");
-    let vec_framerate: Vec<&str> = split_framerate.collect();
-    let frames: f32 = vec_framerate[0].parse().unwrap();
-    let seconds: f32 = vec_framerate[1].parse().unwrap();
-    return (frames / seconds).to_string();
-}
-
-pub fn get_bin_data(input_path: &String) -> String {
-    let output = Command::new("ffprobe")
-        .arg("-i")
-        .arg(input_path)
-        .arg("-v")
-        .arg("error")
-        .arg("-select_streams")
-        .arg("d")
-        .arg("-show_entries")
-        .arg("stream=index")
-        .arg("-of")
-        .arg("default=noprint_wrappers=1:nokey=1")
-        .output()
-        .expect("failed to execute process");
-
-    let temp_output = output.clone();
-    let bin_data = String::from_utf8(temp_output.stdout)
-        .unwrap()
-        .trim()
-        .to_string();
-    return bin_data;
+pub fn get_display_aspect_ratio(input_path: &String) -> Result<String, ReveError> {
+    let r = ffprobe_entry(input_path, "v", "stream=display_aspect_ratio")?;
+    match r.is_empty() {
+        true => Ok("0".to_owned()),
+        false => Ok(r),
+    }
 }
 
-pub fn export_frames(
-    input_path: &String,
-    output_path: &String,
-    start_time: &String,
-    frame_number: &u32,
-    progress_bar: ProgressBar,
-) -> Result<(), Error> {
-    let stderr = Command::new("ffmpeg")
-        .args([
-            "-v",
-            "verbose",
-            "-ss",
-            start_time,
-            "-i",
+pub fn get_frame_rate(input_path: &String) -> Result<String, ReveError> {
+    let raw_framerate = ffprobe_entry(input_path, "v", "stream=avg_frame_rate")?;
+    let mut split_framerate = raw_framerate.split('/');
+    let frames: Option<f32> = split_framerate.next().and_then(|f| f.parse().ok());
+    let seconds: Option<f32> = split_framerate.next().and_then(|s| s.parse().ok());
+    match (frames, seconds) {
+        (Some(frames), Some(seconds)) if frames > 0.0 && seconds > 0.0 => {
+            Ok((frames / seconds).to_string())
+        }
+        _ => Err(ReveError::probe(
             input_path,
-            "-qscale:v",
-            "1",
-            "-qmin",
-            "1",
-            "-qmax",
-            "1",
-            "-vsync",
-            "0",
-            "-vframes",
-            &frame_number.to_string(),
-            output_path,
-        ])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()?
-        .stderr
-        .ok_or_else(|| Error::new(ErrorKind::Other, "Could not capture standard output."))?;
-
-    let reader = BufReader::new(stderr);
-    let mut count: i32 = -1;
-
-    reader
-        .lines()
-        .filter_map(|line| line.ok())
-        .filter(|line| line.contains("AVIOContext"))
-        .for_each(|_| {
-            count += 1;
-            progress_bar.set_position(count as u64);
-        });
-
-    Ok(())
+            format!("unusable frame rate '{}'", raw_framerate),
+        )),
+    }
 }
 
-pub fn upscale_frames(
-    input_path: &String,
-    output_path: &String,
-    scale: &String,
-    model: &String,
-    progress_bar: ProgressBar,
-    total_progress_bar: ProgressBar,
-    mut frame_position: u64,
-) -> Result<u64, Error> {
-    let final_model = format!("{}-x{}", model, scale);
-    #[cfg(target_os = "linux")]
-    let stderr = Command::new("./realesrgan-ncnn-vulkan")
-        .args([
-            "-i",
-            input_path,
-            "-o",
-            output_path,
-            "-n",
-            &final_model,
-            "-s",
-            scale,
-            "-f",
-            "png",
-            "-v",
-        ])
-        .stderr(Stdio::piped())
-        .spawn()?
-        .stderr
-        .ok_or_else(|| Error::new(ErrorKind::Other, "Could not capture standard output."))?;
-
-    #[cfg(target_os = "windows")]
-    let stderr = Command::new("realesrgan-ncnn-vulkan")
-        .args([
-            "-i",
-            input_path,
-            "-o",
-            output_path,
-            "-n",
-            &final_model,
-            "-s",
-            scale,
-            "-f",
-            "png",
-            "-v",
-        ])
-        .stderr(Stdio::piped())
-        .spawn()?
-        .stderr
-        .ok_or_else(|| Error::new(ErrorKind::Other, "Could not capture standard output."))?;
+/// Parses a frame rate returned by get_frame_rate.
+fn parse_frame_rate(input_path: &str, frame_rate: &str) -> Result<f32, ReveError> {
+    frame_rate
+        .parse::<f32>()
+        .map_err(|_| ReveError::probe(input_path, format!("unusable frame rate '{}'", frame_rate)))
+}
 
-    let reader = BufReader::new(stderr);
-    let mut count = 0;
+pub fn get_video_size(input_path: &String) -> Result<(u32, u32), ReveError> {
+    let values = get_ffprobe_output(input_path)?;
+    let width = values["streams"][0]["width"].as_u64().unwrap_or(0);
+    let height = values["streams"][0]["height"].as_u64().unwrap_or(0);
+    Ok((width as u32, height as u32))
+}
 
-    total_progress_bar.set_position(frame_position);
-    //println!("{}", frame_position);
-
-    reader
-        .lines()
-        .filter_map(|line| line.ok())
-        .filter(|line| line.contains("done"))
-        .for_each(|_| {
-            count += 1;
-            frame_position += 1;
-            progress_bar.set_position(count);
-            total_progress_bar.set_position(frame_position);
-        });
+pub fn get_bin_data(input_path: &String) -> Result<String, ReveError> {
+    ffprobe_entry(input_path, "d", "stream=index")
+}
 
-    Ok(u64::from(total_progress_bar.position()))
+/// Value of `entries` for the `streams` of `input_path`, as printed by ffprobe.
+fn ffprobe_entry(input_path: &str, streams: &str, entries: &str) -> Result<String, ReveError> {
+    let output = error::output(Command::new("ffprobe").args([
+        "-i",
+        input_path,
+        "-v",
+        "error",
+        "-select_streams",
+        streams,
+        "-show_entries",
+        entries,
+        "-of",
+        "default=noprint_wrappers=1:nokey=1",
+    ]))?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
 }
 
-// 2022-05-23 17:47 27cffd1
-// https://github.com/AnimMouse/ffmpeg-autobuild/releases/download/m-2022-05-23-17-47/ffmpeg-27cffd1-ff31946-win64-nonfree.7z
-pub fn merge_frames(
+pub fn export_frames(
     input_path: &String,
     output_path: &String,
-    codec: &String,
-    frame_rate: &String,
-    crf: &String,
-    preset: &String,
-    x265_params: &String,
-    progress_bar: ProgressBar,
-) -> Result<(), Error> {
-    let stderr = Command::new("ffmpeg")
-        .args([
-            "-v",
-            "verbose",
-            "-f",
-            "image2",
-            "-framerate",
-            &format!("{}/1", frame_rate),
-            "-i",
-            input_path,
-            "-c:v",
-            codec,
-            "-pix_fmt",
-            "yuv420p10le",
-            "-crf",
-            crf,
-            "-preset",
-            preset,
-            "-x265-params",
-            x265_params,
-            output_path,
-        ])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()?
-        .stderr
-        .ok_or_else(|| Error::new(ErrorKind::Other, "Could not capture standard output."))?;
+    segment: &Segment,
+    mode: ExtractMode,
+    frame_rate: f32,
+    timestamps_path: Option<&String>,
+    progress: &dyn ProgressSink,
+    control: &JobControl,
+) -> Result<(), ReveError> {
+    let mut child = control.spawn(
+        Command::new("ffmpeg")
+            .args(["-v", "verbose"])
+            .args(segment.input_args(mode, input_path, frame_rate))
+            .args(["-qscale:v", "1", "-qmin", "1", "-qmax", "1"])
+            .args(segment.output_args(mode, timestamps_path.is_some()))
+            .arg(output_path)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped()),
+    )?;
+    let stderr = child.stderr.take().ok_or_else(no_pipe)?;
 
     let reader = BufReader::new(stderr);
-    let mut count = 0;
+    let mut counter = FrameCounter::new(progress, Stage::Export, segment);
+    // the first AVIOContext line is the input being opened
+    let mut input_opened = false;
+    let mut timestamps = String::new();
+    let mut tail = StderrTail::default();
+
+    reader.lines().map_while(Result::ok).for_each(|line| {
+        if line.contains("AVIOContext") {
+            if input_opened {
+                counter.tick();
+            }
+            input_opened = true;
+        } else if let Some(pts_time) = line.split("pts_time:").nth(1) {
+            // showinfo line of an extracted frame
+            let pts_time = pts_time.split_whitespace().next().unwrap_or("");
+            timestamps.push_str(pts_time);
+            timestamps.push('\n');
+        } else {
+            tail.push(&line);
+        }
+    });
+    tail.check(control.wait(&mut child)?, |stderr| {
+        ReveError::ffmpeg("export frames", stderr)
+    })?;
 
-    reader
-        .lines()
-        .filter_map(|line| line.ok())
-        .filter(|line| line.contains("AVIOContext"))
-        .for_each(|_| {
-            count += 1;
-            progress_bar.set_position(count);
-        });
+    if let Some(timestamps_path) = timestamps_path {
+        fs::write(timestamps_path, timestamps)?;
+    }
+    progress.event(ProgressEvent::SegmentExported {
+        segment: segment.index,
+        frames: counter.done(),
+    });
     Ok(())
 }
 
-// 2022-03-28 07:12 c2d1597
-// https://github.com/AnimMouse/ffmpeg-autobuild/releases/download/m-2022-03-28-07-12/ffmpeg-c2d1597-651202b-win64-nonfree.7z
-pub fn merge_frames_svt_hevc(
+pub fn upscale_frames(
+    upscaler: &dyn Upscaler,
     input_path: &String,
     output_path: &String,
-    codec: &String,
-    frame_rate: &String,
-    crf: &String,
-    progress_bar: ProgressBar,
-) -> Result<(), Error> {
-    let stderr = Command::new("ffmpeg")
-        .args([
-            "-v",
-            "verbose",
-            "-f",
-            "image2",
-            "-framerate",
-            &format!("{}/1", frame_rate),
-            "-i",
-            input_path,
-            "-c:v",
-            codec,
-            "-rc",
-            "0",
-            "-qp",
-            crf,
-            "-tune",
-            "0",
-            "-pix_fmt",
-            "yuv420p10le",
-            "-crf",
-            crf,
-            output_path,
-        ])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()?
-        .stderr
-        .ok_or_else(|| Error::new(ErrorKind::Other, "Could not capture standard output."))?;
+    scale: u8,
+    model: &String,
+    segment: &Segment,
+    progress: &dyn ProgressSink,
+    control: &JobControl,
+) -> Result<(), ReveError> {
+    let mut child = control.spawn(
+        upscaler
+            .command(input_path, output_path, scale, model)
+            .stderr(Stdio::piped()),
+    )?;
+    let stderr = child.stderr.take().ok_or_else(no_pipe)?;
 
     let reader = BufReader::new(stderr);
-    let mut count = 0;
-
-    reader
-        .lines()
-        .filter_map(|line| line.ok())
-        .filter(|line| line.contains("AVIOContext"))
-        .for_each(|_| {
-            count += 1;
-            progress_bar.set_position(count);
-        });
+    let mut counter = FrameCounter::new(progress, Stage::Upscale, segment);
+    let mut tail = StderrTail::default();
 
+    reader.lines().map_while(Result::ok).for_each(|line| {
+        if upscaler.is_progress_line(&line) {
+            counter.tick();
+        } else {
+            tail.push(&line);
+        }
+    });
+    tail.check(control.wait(&mut child)?, |stderr| ReveError::Upscaler {
+        stderr,
+    })?;
+
+    progress.event(ProgressEvent::FramesUpscaled {
+        segment: segment.index,
+        frames: counter.done(),
+        fps: counter.fps(),
+    });
     Ok(())
 }
 
-pub fn merge_frames_svt_av1(
-    input_path: &String,
-    output_path: &String,
-    codec: &String,
-    frame_rate: &String,
-    crf: &String,
-    progress_bar: ProgressBar,
-) -> Result<(), Error> {
-    let stderr = Command::new("ffmpeg")
-        .args([
-            "-v",
-            "verbose",
-            "-f",
-            "image2",
-            "-framerate",
-            &format!("{}/1", frame_rate),
-            "-i",
-            input_path,
-            "-c:v",
-            codec,
-            "-pix_fmt",
-            "yuv420p10le",
-            "-crf",
-            crf,
-            output_path,
-        ])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()?
-        .stderr
-        .ok_or_else(|| Error::new(ErrorKind::Other, "Could not capture standard output."))?;
-
-    let reader = BufReader::new(stderr);
-    let mut count = 0;
+/// The upscaled frames of one segment encoded into its video part.
+pub struct SegmentMerge<'a> {
+    /// frame%08d.png pattern of the upscaled frames
+    pub input_path: &'a str,
+    /// ffconcat list carrying the original frame durations, used instead of `frame_rate`
+    pub frames_list: Option<&'a str>,
+    pub output_path: &'a str,
+    pub frame_rate: &'a str,
+    pub profile: &'a EncoderProfile,
+    pub crf: u8,
+    pub preset: &'a str,
+    pub params: &'a str,
+    pub segment: &'a Segment,
+    pub control: &'a JobControl,
+}
 
-    reader
-        .lines()
-        .filter_map(|line| line.ok())
-        .filter(|line| line.contains("AVIOContext"))
-        .for_each(|_| {
-            count += 1;
-            progress_bar.set_position(count);
+impl SegmentMerge<'_> {
+    pub fn run(&self, progress: &dyn ProgressSink) -> Result<(), ReveError> {
+        let input_args = match self.frames_list {
+            Some(frames_list) => vec![
+                "-f",
+                "concat",
+                "-safe",
+                "0",
+                "-i",
+                frames_list,
+                "-vsync",
+                "vfr",
+            ],
+            None => vec![
+                "-f",
+                "image2",
+                "-framerate",
+                self.frame_rate,
+                "-i",
+                self.input_path,
+            ],
+        };
+        let mut child = self.control.spawn(
+            Command::new("ffmpeg")
+                .args(["-v", "verbose"])
+                .args(input_args)
+                .args(self.profile.args(self.crf, self.preset, self.params))
+                .arg(self.output_path)
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped()),
+        )?;
+        let stderr = child.stderr.take().ok_or_else(no_pipe)?;
+
+        let reader = BufReader::new(stderr);
+        let mut counter = FrameCounter::new(progress, Stage::Merge, self.segment);
+        let mut tail = StderrTail::default();
+
+        reader.lines().map_while(Result::ok).for_each(|line| {
+            if line.contains("AVIOContext") {
+                counter.tick();
+            } else {
+                tail.push(&line);
+            }
         });
+        tail.check(self.control.wait(&mut child)?, |stderr| {
+            ReveError::ffmpeg("merge frames", stderr)
+        })?;
 
-    Ok(())
+        progress.event(ProgressEvent::SegmentMerged {
+            segment: self.segment.index,
+            frames: counter.done(),
+        });
+        Ok(())
+    }
 }
 
 pub fn merge_video_parts_dar(
     input_path: &String,
     output_path: &String,
     dar: &String,
-) -> std::process::Output {
-    Command::new("ffmpeg")
-        .args([
+) -> Result<Output, ReveError> {
+    error::run_ffmpeg(
+        Command::new("ffmpeg").args([
             "-f",
             "concat",
             "-safe",
@@ -1286,14 +1298,14 @@ pub fn merge_video_parts_dar(
             "-c",
             "copy",
             output_path,
-        ])
-        .output()
-        .expect("failed to execute process")
+        ]),
+        "merge video parts",
+    )
 }
 
-pub fn merge_video_parts(input_path: &String, output_path: &String) -> std::process::Output {
-    Command::new("ffmpeg")
-        .args([
+pub fn merge_video_parts(input_path: &String, output_path: &String) -> Result<Output, ReveError> {
+    error::run_ffmpeg(
+        Command::new("ffmpeg").args([
             "-f",
             "concat",
             "-safe",
@@ -1303,43 +1315,94 @@ pub fn merge_video_parts(input_path: &String, output_path: &String) -> std::proc
             "-c",
             "copy",
             output_path,
-        ])
-        .output()
-        .expect("failed to execute process")
+        ]),
+        "merge video parts",
+    )
 }
 
-pub fn prepare() {
+/// Output path of `file` when upscaling a folder: `<name>.<encoder>.<format>` next to it, or the
+/// file name of `-o` in the current folder.
+pub fn batch_output_path(args: &Args, file: &str) -> String {
+    if let Some(outputpath) = &args.outputpath {
+        let filename = Path::new(outputpath).file_name().unwrap_or_default();
+        return absolute_path(filename);
+    }
+    let path = Path::new(file);
+    let directory = absolute_path(path.parent().unwrap_or(Path::new(".")));
+    let name = format!(
+        "{}.{}.{}",
+        path.file_stem().unwrap_or_default().to_string_lossy(),
+        args.codec,
+        args.format
+    );
+    Path::new(&directory).join(name).display().to_string()
+}
+
+/// Parses the command line, with the defaults of the config and profiles files, and runs it.
+pub fn prepare() -> Result<(), ReveError> {
+    let config = ReveConfig::load()?;
+    let profiles = Profiles::load()?;
+    let args = Args::try_parse_with_config(env::args_os(), &config, &profiles)
+        .unwrap_or_else(|e| e.exit());
+    run_args(args)
+}
+
+/// Runs parsed command line arguments: a subcommand, `--watch`, or the upscale of a file or
+/// of every video in a folder.
+pub fn run_args(mut args: Args) -> Result<(), ReveError> {
     let main_now = Instant::now();
 
-    let mut args;
-    args = Args::parse();
+    match &args.command {
+        Some(Commands::Queue(command)) => return queue_command(&args, command),
+        Some(Commands::Db(command)) => return db_command(command),
+        Some(Commands::Serve { port }) => {
+            let address = format!("127.0.0.1:{}", port);
+            let server = JobServer::bind(&address, "reve.db", env::current_exe()?)?;
+            server.write_token(&JobServer::token_path())?;
+            println!(
+                "listening on http://{}, token in {}",
+                address,
+                JobServer::token_path().display()
+            );
+            return server.run();
+        }
+        None => (),
+    }
+    if args.inputpath.is_none() {
+        return Err(ReveError::InvalidInput(String::from(
+            "set the file or folder to upscale with -i",
+        )));
+    }
 
-    let temp_args;
-    temp_args = Args::parse();
+    if args.dry_run {
+        return dry_run(&args);
+    }
 
-    #[cfg(target_os = "linux")]
-    match dev_shm_exists() {
-        Err(e) => {
-            println!("{:?}", e);
-            exit(1);
+    if args.watch {
+        if !Path::new(args.input()).is_dir() {
+            return Err(ReveError::InvalidInput(String::from(
+                "--watch needs a folder as input",
+            )));
         }
-        _ => (),
-    };
+        return watch(&args);
+    }
 
-    let mut output_path: String = "".to_string();
-    let mut done_output: String = "".to_string();
+    let progress: Arc<dyn ProgressSink> = Arc::new(TerminalProgress::new());
+    let info = |message: String| progress.event(ProgressEvent::Info { message });
     let mut current_file_count = 0;
-    let mut total_files: i32;
-    let resolution = temp_args.resolution.unwrap().parse::<u32>().unwrap();
-
-    let md = metadata(Path::new(&args.inputpath)).unwrap();
+    let mut total_files: u64;
+    let resolution = args
+        .resolution
+        .clone()
+        .unwrap_or_else(|| String::from("480"));
+    let resolution = resolution
+        .parse::<u32>()
+        .map_err(|_| ReveError::InvalidInput(format!("invalid resolution {}", resolution)))?;
+
+    let md = metadata(Path::new(args.input()))?;
     // Check if input is a directory, if yes, check how many video files are in it, and process the ones that are smaller than the given resolution
     if md.is_dir() {
-        let mut count;
-        let db_count;
-        let db_count_added;
-        let db_count_skipped;
-        let walk_count: u64 = walk_count(&args.inputpath) as u64;
+        let walk_count: u64 = walk_count(args.input()) as u64;
         let files_bar = ProgressBar::new(walk_count);
         let files_style = "[file][{elapsed_precise}] [{wide_bar:.green/white}] {percent}% {pos:>7}/{len:7} analyzed files       eta: {eta:<7}";
         files_bar.set_style(
@@ -1349,49 +1412,39 @@ pub fn prepare() {
                 .progress_chars("#>-"),
         );
 
-        let vector_files = walk_files(&args.inputpath);
+        let vector_files = walk_files(args.input());
         let mut vector_files_to_process_frames_count: Vec<u64> = Vec::new();
 
-        let result = add_to_db(
+        // what was found and the files to process
+        let (summary, mut vector_files_to_process) = add_to_db(
             vector_files.clone(),
             // if some args.resolution is given, use it, if not, use 0
             resolution.clone().to_string(),
             files_bar.clone(),
-        )
-        .unwrap();
-        // get the counters from the add_to_db function
-        let counters = result.0;
+        )?;
 
-        let to_process = result.1;
-        // get the vector of files to process
-        let mut vector_files_to_process = to_process.lock().unwrap().clone();
-
-        // count, db_count, db_count_added, db_count_skipped
-        count = format!("{:?}", counters[0]).parse::<i32>().unwrap();
-        db_count = format!("{:?}", counters[1]).parse::<u64>().unwrap();
-        db_count_added = format!("{:?}", counters[2]).parse::<u64>().unwrap();
-        db_count_skipped = format!("{:?}", counters[3]).parse::<u64>().unwrap();
+        let mut count = summary.added as i32;
+        let db_count = summary.known + summary.moved + summary.copies;
 
         if vector_files_to_process.len() == 0 {
             // get all the files from the database that contain input_path's folder parent in column filepath and status 'processing' in status column and add them to the vector_files_to_process
-            let conn = Connection::open("reve.db").unwrap();
-            let input = args.inputpath.clone();
-            let mut stmt = conn
-                .prepare("SELECT * FROM video_info WHERE status = 'processing' AND filepath LIKE ?")
-                .unwrap();
-            let mut rows = stmt.query(&[&format!("%{}%", input)]).unwrap();
-            while let Some(row) = rows.next().unwrap() {
-                vector_files_to_process.push(row.get(2).unwrap());
+            let conn = open_db("reve.db")?;
+            let input = args.input();
+            let mut stmt = conn.prepare(
+                "SELECT * FROM video_info WHERE status = 'processing' AND filepath LIKE ?",
+            )?;
+            let mut rows = stmt.query(&[&format!("%{}%", input)])?;
+            while let Some(row) = rows.next()? {
+                vector_files_to_process.push(row.get(2)?);
             }
             // get all the files from the database that contain input_path's folder parent in column filepath and status 'pending' in status column and add them to the vector_files_to_process
-            let conn = Connection::open("reve.db").unwrap();
-            let input = args.inputpath.clone();
+            let conn = open_db("reve.db")?;
+            let input = args.input();
             let mut stmt = conn
-                .prepare("SELECT * FROM video_info WHERE status = 'pending' AND filepath LIKE ?")
-                .unwrap();
-            let mut rows = stmt.query(&[&format!("%{}%", input)]).unwrap();
-            while let Some(row) = rows.next().unwrap() {
-                vector_files_to_process.push(row.get(2).unwrap());
+                .prepare("SELECT * FROM video_info WHERE status = 'pending' AND filepath LIKE ?")?;
+            let mut rows = stmt.query(&[&format!("%{}%", input)])?;
+            while let Some(row) = rows.next()? {
+                vector_files_to_process.push(row.get(2)?);
             }
         }
 
@@ -1410,16 +1463,16 @@ pub fn prepare() {
         );
 
         files_bar.finish_and_clear();
-        println!("Added {} files to the database ({} already present, {} skipped due to max resolution being {}p)", db_count_added, db_count, db_count_skipped, resolution);
-        println!(
+        info(format!("Added {} files to the database ({} already present, {} moved, {} copies, {} skipped due to max resolution being {}p)", summary.added, summary.known, summary.moved, summary.copies, summary.skipped, resolution));
+        info(format!(
             "Upscaling {} files (Due to max height resolution: {}p)",
             count, resolution
-        );
+        ));
 
         let total_frames = vector_files_to_process.clone();
         let mut current_frame_count: u64 = 0;
         for file in total_frames.clone() {
-            current_frame_count += u64::from(get_frame_count(&file));
+            current_frame_count += u64::from(get_frame_count(&file)?);
             vector_files_to_process_frames_count.push(current_frame_count);
             frame_count_bar.inc(1);
         }
@@ -1428,7 +1481,7 @@ pub fn prepare() {
             vector_files_to_process_frames_count.clear();
             if vector_files_to_process_frames_count.is_empty() {
                 for file in total_frames.clone() {
-                    current_frame_count += u64::from(get_frame_count_tag(&file));
+                    current_frame_count += u64::from(get_frame_count_tag(&file)?);
                     vector_files_to_process_frames_count.push(current_frame_count);
                 }
             }
@@ -1439,7 +1492,7 @@ pub fn prepare() {
             vector_files_to_process_frames_count.clear();
             if vector_files_to_process_frames_count.is_empty() {
                 for file in total_frames.clone() {
-                    current_frame_count += u64::from(get_frame_count_duration(&file));
+                    current_frame_count += u64::from(get_frame_count_duration(&file)?);
                     vector_files_to_process_frames_count.push(current_frame_count);
                 }
             }
@@ -1448,758 +1501,362 @@ pub fn prepare() {
         let total_frames_count = current_frame_count;
 
         for file in vector_files_to_process.clone() {
-            let dar = get_display_aspect_ratio(&file).to_string();
             current_file_count = current_file_count + 1;
-            total_files = vector_files_to_process.len() as i32;
-            args.inputpath = file.clone();
-            rebuild_temp(true);
-
-            if args.outputpath.is_none() {
-                let path = Path::new(&args.inputpath);
-                let filename_ext = &args.format;
-                let filename_no_ext = path.file_stem().unwrap().to_string_lossy();
-                let filename_codec = &args.codec;
-                let directory = absolute_path(path.parent().unwrap());
-                #[cfg(target_os = "windows")]
-                let directory_path = format!("{}{}", directory.trim_end_matches("."), "\\");
-                #[cfg(target_os = "linux")]
-                let directory_path = format!("{}{}", directory.trim_end_matches("."), "/");
-                output_path = format!(
-                    "{}{}.{}.{}",
-                    directory_path, filename_no_ext, filename_codec, filename_ext
-                );
-                done_output = format!("{}.{}.{}", filename_no_ext, filename_codec, filename_ext);
-                match output_validation_dir(&output_path) {
-                    Err(e) => {
-                        println!("{:?}", e);
-                        exit(1);
-                    }
-                    Ok(s) => {
-                        if s.contains("already exists") {
-                            println!("{} already exists, skipping", done_output);
-                            continue;
-                        }
-                    }
-                }
-            }
-            if args.outputpath.is_some() {
-                let str_outputpath = &args
-                    .outputpath
-                    .as_deref()
-                    .unwrap_or("default string")
-                    .to_owned();
-                let path = Path::new(&str_outputpath);
-                let filename = path.file_name().unwrap().to_string_lossy();
-
-                output_path = absolute_path(filename.to_string());
-                done_output = filename.to_string();
-                match output_validation_dir(&output_path) {
-                    Err(e) => {
-                        println!("{:?}", e);
-                        exit(1);
-                    }
-                    Ok(s) => {
-                        if s.contains("already exists") {
-                            println!("{} already exists, skipping", done_output);
-                            continue;
-                        }
-                    }
-                }
+            total_files = vector_files_to_process.len() as u64;
+            args.inputpath = Some(file.clone());
+
+            let output_path = batch_output_path(&args, &file);
+            let done_output = file_name(&output_path).to_string();
+            let s = output_validation_dir(&output_path).map_err(ReveError::InvalidInput)?;
+            if s.contains("already exists") {
+                info(format!("{} already exists, skipping", done_output));
+                continue;
             }
 
-            args.inputpath = absolute_path(file.clone());
+            args.inputpath = Some(absolute_path(file.clone()));
 
-            println!(
-                "Processing file {} of {} ({}):",
-                current_file_count, total_files, done_output
-            );
-            println!("Input path: {}", args.inputpath);
-            println!("Output path: {}", output_path);
-            println!("total_frames_count: {}", total_frames_count);
-            println!(
-                "vector_files_to_process_frames_count: {:?}",
-                vector_files_to_process_frames_count
-            );
-            //exit(1);
-
-            // update status in sqlite database 'reve.db' to processing for this file where filepaths match the current file
-            let conn = Connection::open("reve.db").unwrap();
-            conn.execute(
-                "UPDATE video_info SET status = 'processing' WHERE filepath = ?",
-                &[&args.inputpath],
-            )
-            .unwrap();
-
-            process(
-                &args,
-                dar.clone(),
-                current_file_count as i32,
-                total_files,
-                done_output.clone(),
-                output_path.clone(),
-                total_frames_count.clone(),
-                vector_files_to_process_frames_count.clone(),
-            );
-
-            // Validation
-            {
-                let in_extension = Path::new(&args.inputpath).extension().unwrap();
-                let out_extension = Path::new(&output_path).extension().unwrap();
-
-                if in_extension == "mkv" && out_extension != "mkv" {
-                    clear().unwrap();
-                    println!(
-                        "{} Invalid value {} for '{}': mkv file can only be exported as mkv file\n\nFor more information try {}",
-                        "error:".to_string().bright_red(),
-                        format!("\"{}\"", args.inputpath).yellow(),
-                        "--outputpath <OUTPUTPATH>".to_string().yellow(),
-                        "--help".to_string().green()
-                    );
-                    std::process::exit(1);
-                }
-            }
+            let frames_before = match current_file_count {
+                0 | 1 => 0,
+                n => vector_files_to_process_frames_count
+                    .get(n as usize - 2)
+                    .copied()
+                    .unwrap_or(0),
+            };
+            let mut spec = JobSpec::from(&args);
+            spec.output = Some(output_path.clone());
+            process_file(
+                spec,
+                Batch {
+                    file: current_file_count as u32,
+                    files: total_files as u32,
+                    frames_before,
+                    frames_total: total_frames_count,
+                },
+                progress.clone(),
+            )?;
         }
         let elapsed = main_now.elapsed();
         let seconds = elapsed.as_secs() % 60;
         let minutes = (elapsed.as_secs() / 60) % 60;
         let hours = (elapsed.as_secs() / 60) / 60;
-        println!(
+        info(format!(
             "done {} files in {}h:{}m:{}s",
             count, hours, minutes, seconds
-        );
+        ));
     }
 
-    #[cfg(target_os = "windows")]
-    let folder_args = "\\";
-    #[cfg(target_os = "linux")]
-    let folder_args = "/";
-
     if md.is_file() {
-        let dar = get_display_aspect_ratio(&args.inputpath).to_string();
-        current_file_count = 1;
-        let mut total_frames_count = u64::from(get_frame_count(&args.inputpath));
-        if total_frames_count == 0 {
-            total_frames_count = u64::from(get_frame_count_tag(&args.inputpath));
-        }
-        let directory = Path::new(&args.inputpath)
-            .parent()
-            .unwrap()
-            .to_str()
-            .unwrap();
-        if args.outputpath.is_none() {
-            let path = Path::new(&args.inputpath);
-            let filename_ext = &args.format;
-            let filename_no_ext = path.file_stem().unwrap().to_string_lossy();
-            let filename_codec = &args.codec;
-            output_path = format!(
-                "{}{}{}.{}.{}",
-                directory, folder_args, filename_no_ext, filename_codec, filename_ext
-            );
-            done_output = format!("{}.{}.{}", filename_no_ext, filename_codec, filename_ext);
-        }
-        if args.outputpath.is_some() {
-            let str_outputpath = &args
-                .outputpath
-                .as_deref()
-                .unwrap_or("default string")
-                .to_owned();
-            let path = Path::new(&str_outputpath);
-            let filename = path.file_name().unwrap().to_string_lossy();
-            output_path = path.to_str().unwrap().to_string();
-            done_output = filename.to_string();
-        }
-        match output_validation(&output_path) {
-            Err(e) => println!("{:?}", e),
-            _ => (),
-        }
-        clear().expect("failed to clear screen");
-        total_files = 1;
+        let mut spec = JobSpec::from(&args);
+        let output_path = spec.output_path();
+        output_validation(&output_path).map_err(ReveError::InvalidInput)?;
+        let _ = clear();
 
-        let temp_vector = vec![total_frames_count];
-
-        let ffprobe_output = Command::new("ffprobe")
-            .args([
-                "-i",
-                args.inputpath.as_str(),
-                "-v",
-                "error",
-                "-select_streams",
-                "v",
-                "-show_entries",
-                "stream",
-                "-show_format",
-                "-show_data_hash",
-                "sha256",
-                "-show_streams",
-                "-of",
-                "json",
-            ])
-            .output()
-            .unwrap();
+        let ffprobe_output = error::output(Command::new("ffprobe").args([
+            "-i",
+            args.input(),
+            "-v",
+            "error",
+            "-select_streams",
+            "v",
+            "-show_entries",
+            "stream",
+            "-show_format",
+            "-show_data_hash",
+            "sha256",
+            "-show_streams",
+            "-of",
+            "json",
+        ]))?;
         //.\ffprobe.exe -i '\\192.168.1.99\Data\Animes\Agent AIKa\Saison 2\Agent AIKa - S02E03 - Trial 3 Deep Blue Girl.mkv' -v error -select_streams v -show_entries stream -show_format -show_data_hash sha256 -show_streams -of json
-        let json_output = std::str::from_utf8(&ffprobe_output.stdout[..]).unwrap();
-        let height = check_ffprobe_output_i8(json_output, &resolution.to_string());
-        if height.unwrap() == 1 {
-            process(
-                &args,
-                dar,
-                current_file_count as i32,
-                total_files,
-                done_output,
-                output_path.clone(),
-                total_frames_count,
-                temp_vector,
-            );
+        let json_output = String::from_utf8_lossy(&ffprobe_output.stdout);
+        let height = check_ffprobe_output_i8(&json_output, &resolution.to_string())?;
+        if height == 1 {
+            spec.output = Some(output_path);
+            process_file(
+                spec,
+                Batch {
+                    file: 1,
+                    files: 1,
+                    ..Default::default()
+                },
+                progress,
+            )?;
         } else {
-            println!(
-                "{} is bigger than {}p",
-                args.inputpath,
-                resolution.to_string()
-            );
-            println!("Set argument -r to a higher value");
-            exit(1);
+            return Err(ReveError::InvalidInput(format!(
+                "{} is bigger than {}p, set argument -r to a higher value",
+                args.input(),
+                resolution
+            )));
         }
+    }
+    Ok(())
+}
 
-        // Validation
-        {
-            let in_extension = Path::new(&args.inputpath).extension().unwrap();
-            let out_extension = Path::new(&output_path).extension().unwrap();
+/// Runs the pipeline on a file of the library and keeps its status in reve.db up to date.
+fn process_file(
+    spec: JobSpec,
+    batch: Batch,
+    progress: Arc<dyn ProgressSink>,
+) -> Result<(), ReveError> {
+    let conn = open_db("reve.db")?;
+    // a file left processing by an earlier run goes back to pending
+    conn.execute(
+        "UPDATE video_info SET status = 'pending' WHERE status = 'processing' AND filepath != ?1",
+        params![spec.input],
+    )?;
+    update_db_status(&conn, &spec.input, "processing")?;
 
-            if in_extension == "mkv" && out_extension != "mkv" {
-                clear().unwrap();
-                println!(
-                    "{} Invalid value {} for '{}': mkv file can only be exported as mkv file\n\nFor more information try {}",
-                    "error:".to_string().bright_red(),
-                    format!("\"{}\"", args.inputpath).yellow(),
-                    "--outputpath <OUTPUTPATH>".to_string().yellow(),
-                    "--help".to_string().green()
-                );
-                std::process::exit(1);
-            }
-        }
-    }
+    let pipeline = Pipeline::new(spec).batch(batch).progress(progress.clone());
+    pipeline.run()?;
+
+    let message = match update_db_status(&conn, &pipeline.spec().input, "done") {
+        Ok(_) => String::from("updated database"),
+        Err(e) => format!("failed to update database: {}", e),
+    };
+    progress.event(ProgressEvent::Info { message });
+    Ok(())
 }
 
-pub fn process(
-    args: &Args,
-    dar: String,
-    current_file_count: i32,
-    total_files: i32,
-    done_output: String,
-    output_path: String,
-    total_frames_count: u64,
-    vector_files_to_process_frames_count: Vec<u64>,
-) {
-    let work_now = Instant::now();
-
-    /*     // print all arguments given to function work
-    if args.verbose {
-        println!("Arguments given to function work:");
-        println!("dar: {}", dar);
-        println!("current_file_count: {}", current_file_count);
-        println!("total_files: {}", total_files);
-        println!("done_output: {}", done_output);
-        println!("output_path: {}", output_path);
-        println!("total_frames_count: {}", total_frames_count);
-        println!(
-            "vector_files_to_process_frames_count: {:?}",
-            vector_files_to_process_frames_count
-        );
-    }
-    exit(1); */
-
-    #[cfg(target_os = "windows")]
-    let args_path = Path::new("temp\\args.temp");
-    #[cfg(target_os = "windows")]
-    let video_parts_path = "temp\\video_parts\\";
-    #[cfg(target_os = "windows")]
-    let temp_video_path = format!("temp\\temp.{}", &args.format);
-    #[cfg(target_os = "windows")]
-    let txt_list_path = "temp\\parts.txt";
-
-    #[cfg(target_os = "linux")]
-    let args_path = Path::new("/dev/shm/args.temp");
-    #[cfg(target_os = "linux")]
-    let video_parts_path = "/dev/shm/video_parts/";
-    #[cfg(target_os = "linux")]
-    let temp_video_path = format!("/dev/shm/temp.{}", &args.format);
-    #[cfg(target_os = "linux")]
-    let txt_list_path = "/dev/shm/parts.txt";
-
-    if Path::new(&args_path).exists() {
-        //Check if previous file is used, if yes, continue upscale without asking
-        let old_args_json = fs::read_to_string(&args_path).expect("Unable to read file");
-        let old_args: Args = serde_json::from_str(&old_args_json).unwrap();
-        let previous_file = Path::new(&old_args.inputpath);
-        let md = fs::metadata(&args.inputpath).unwrap();
-
-        // Check if same file is used as previous upscale and if yes, resume
-        if args.inputpath == previous_file.to_string_lossy()
-            && args.model == old_args.model
-            && args.scale == old_args.scale
-        {
-            if md.is_file() {
+/// Runs a `reve db` subcommand against the video_info table of reve.db.
+pub fn db_command(command: &DbCommand) -> Result<(), ReveError> {
+    let conn = open_db("reve.db")?;
+
+    match command {
+        DbCommand::List { filter } => {
+            let videos = list_videos(&conn, filter)?;
+            for video in &videos {
+                let status = match video.status.as_str() {
+                    "done" => video.status.green(),
+                    "processing" => video.status.yellow(),
+                    "skipped" => video.status.dimmed(),
+                    _ => video.status.normal(),
+                };
                 println!(
-                    "Same file! '{}' Resuming...",
-                    previous_file.file_name().unwrap().to_str().unwrap()
+                    "{:>5}  {:<10}  {:>4}x{:<4}  {:<6}  {:>8.1} min  {}",
+                    video.id,
+                    status,
+                    video.width,
+                    video.height,
+                    video.codec,
+                    video.duration / 60.0,
+                    video.filepath
                 );
-                // Resume upscale
-                rebuild_temp(true);
-                clear().expect("failed to clear screen");
-                println!("{}", "resuming upscale".to_string().green());
             }
-        } else {
-            // Remove and start new
-            rebuild_temp(false);
-            match fs::remove_file(&txt_list_path) {
-                Ok(()) => "ok",
-                Err(_e) if _e.kind() == ErrorKind::NotFound => "not found",
-                Err(_e) => "other",
-            };
-            match fs::remove_file(&temp_video_path) {
-                Ok(()) => "ok",
-                Err(_e) if _e.kind() == ErrorKind::NotFound => "not found",
-                Err(_e) => "other",
-            };
-
-            let serialized_args = serde_json::to_string(&args).unwrap();
-
-            let md = metadata(&args.inputpath).unwrap();
-            if md.is_file() {
-                fs::write(&args_path, serialized_args).expect("Unable to write file");
-            }
-            clear().expect("failed to clear screen");
+            println!("{} videos", videos.len());
+        }
+        DbCommand::Stats => {
+            let stats = library_stats(&conn)?;
             println!(
-                "{}",
-                "deleted all temporary files, parsing console input"
-                    .to_string()
-                    .green()
+                "{} videos, {:.1} GB, {:.1} hours",
+                stats.videos,
+                stats.size as f64 / 1e9,
+                stats.duration / 3600.0
             );
+            println!("by status:");
+            for (status, count) in &stats.by_status {
+                println!("  {:<10} {}", status, count);
+            }
+            println!("by codec:");
+            for (codec, count) in &stats.by_codec {
+                println!("  {:<10} {}", codec, count);
+            }
         }
-    } else {
-        // Remove and start new
-        rebuild_temp(false);
-        match fs::remove_file(&txt_list_path) {
-            Ok(()) => "ok",
-            Err(_e) if _e.kind() == ErrorKind::NotFound => "not found",
-            Err(_e) => "other",
-        };
-        match fs::remove_file(&temp_video_path) {
-            Ok(()) => "ok",
-            Err(_e) if _e.kind() == ErrorKind::NotFound => "not found",
-            Err(_e) => "other",
-        };
-
-        let serialized_args = serde_json::to_string(&args).unwrap();
-
-        let md = metadata(&args.inputpath).unwrap();
-        if md.is_file() {
-            fs::write(&args_path, serialized_args).expect("Unable to write file");
+        DbCommand::Reset { status, to } => {
+            let count = reset_db_status(&conn, status, to)?;
+            println!("{} videos {} -> {}", count, status, to);
+        }
+        DbCommand::Forget { path } => {
+            let videos = forget_videos(&conn, path)?;
+            if videos.is_empty() {
+                return Err(ReveError::InvalidInput(format!(
+                    "no video of the library at {}",
+                    path
+                )));
+            }
+            for video in &videos {
+                println!("forgot {}", video.filepath);
+            }
+        }
+        DbCommand::Export { format, filter } => {
+            let videos = list_videos(&conn, filter)?;
+            match format {
+                ExportFormat::Csv => print!("{}", videos_to_csv(&videos)),
+                ExportFormat::Json => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&videos).map_err(Error::from)?
+                ),
+            }
         }
-        clear().expect("failed to clear screen");
-        println!(
-            "{}",
-            "deleted all temporary files, parsing console input"
-                .to_string()
-                .green()
-        );
-    }
-
-    // check if files are marked as processing in database that is not the current file and set status as 'pending'
-    let conn = Connection::open("reve.db").unwrap();
-    create_db_table(&conn);
-    conn.execute(
-        "UPDATE video_info SET status = 'pending' WHERE status = 'processing' AND filepath != ?1",
-        params![args.inputpath],
-    )
-    .unwrap();
-
-    let mut frame_position;
-    let filename = Path::new(&args.inputpath)
-        .file_name()
-        .unwrap()
-        .to_str()
-        .unwrap();
-
-    let mut total_frame_count = get_frame_count(&args.inputpath);
-
-    if total_frame_count == 0 {
-        total_frame_count = get_frame_count_tag(&args.inputpath);
-    }
-
-    if total_frame_count == 0 {
-        total_frame_count = get_frame_count_duration(&args.inputpath);
     }
+    Ok(())
+}
 
-    let original_frame_rate = get_frame_rate(&args.inputpath);
-
-    // Calculate steps
-    let parts_num = (total_frame_count as f32 / args.segmentsize as f32).ceil() as i32;
-    let last_part_size = (total_frame_count % args.segmentsize) as u32;
-    let last_part_size = if last_part_size == 0 {
-        args.segmentsize
-    } else {
-        last_part_size
-    };
-
-    let _codec = args.codec.clone();
-    clear().expect("failed to clear screen");
-    println!(
-        "{}",
-        format!(
-            "{}/{}, {}, total segments: {}, last segment size: {}, codec: {} (ctrl+c to exit)",
-            current_file_count,
-            total_files,
-            filename.green(),
-            parts_num,
-            last_part_size,
-            _codec
-        )
-        .yellow()
-    );
+/// Runs a `reve queue` subcommand against reve.db.
+pub fn queue_command(args: &Args, command: &QueueCommand) -> Result<(), ReveError> {
+    let mut conn = open_db("reve.db")?;
 
-    {
-        let mut unprocessed_indexes = Vec::new();
-        for i in 0..parts_num {
-            #[cfg(target_os = "linux")]
-            let n = format!("{}/{}.{}", video_parts_path, i, &args.format);
-            #[cfg(target_os = "windows")]
-            let n = format!("{}\\{}.{}", video_parts_path, i, &args.format);
-            let p = Path::new(&n);
-            let frame_number = if i + 1 == parts_num {
-                last_part_size
-            } else {
-                args.segmentsize
+    match command {
+        QueueCommand::Add { priority } => {
+            let Some(input) = &args.inputpath else {
+                return Err(ReveError::InvalidInput(String::from(
+                    "set the file or folder to queue with -i",
+                )));
             };
-            if !p.exists() {
-                unprocessed_indexes.push(Segment {
-                    index: i as u32,
-                    size: frame_number as u32,
-                });
-            } else {
-                let mut c = get_frame_count(&p.display().to_string());
-                if c == 0 {
-                    c = get_frame_count_tag(&p.display().to_string());
-                }
-                if c != frame_number {
-                    fs::remove_file(p).expect("could not remove invalid part, maybe in use?");
-                    println!("removed invalid segment file [{}] with {} frame size", i, c);
-                    unprocessed_indexes.push(Segment {
-                        index: i as u32,
-                        size: frame_number as u32,
-                    });
+            let input = absolute_path(input);
+            if Path::new(&input).is_dir() {
+                let files = walk_files(&input);
+                for file in &files {
+                    let id = enqueue(&conn, file, None, args, *priority)?;
+                    println!("queued job {}: {}", id, file);
                 }
+                println!("queued {} files", files.len());
+            } else {
+                let output = args.outputpath.as_deref().map(absolute_path);
+                let id = enqueue(&conn, &input, output.as_deref(), args, *priority)?;
+                println!("queued job {}: {}", id, input);
             }
         }
-
-        let count;
-        if current_file_count == 1 {
-            count = total_frames_count;
-        } else {
-            count = total_frames_count
-                - vector_files_to_process_frames_count[(current_file_count - 2) as usize];
+        QueueCommand::List => {
+            let jobs = list_jobs(&conn)?;
+            if jobs.is_empty() {
+                println!("queue is empty");
+            }
+            for job in jobs {
+                let state = match job.state {
+                    JobState::Queued => job.state.to_string().normal(),
+                    JobState::Running => job.state.to_string().yellow(),
+                    JobState::Done => job.state.to_string().green(),
+                    JobState::Failed => job.state.to_string().bright_red(),
+                    JobState::Cancelled => job.state.to_string().dimmed(),
+                };
+                println!(
+                    "{:>5}  {:<9}  priority {:<4}  attempts {:<2}  {} -> {}",
+                    job.id,
+                    state,
+                    job.priority,
+                    job.attempts,
+                    job.input,
+                    job.output.as_deref().unwrap_or("(next to input)")
+                );
+                if let Some(error) = job.error {
+                    println!("       {}", error.bright_red());
+                }
+            }
         }
-        frame_position = (total_frames_count - count as u64)
-            + (parts_num as usize - unprocessed_indexes.len()) as u64 * args.segmentsize as u64;
-
-        let mut export_handle = thread::spawn(move || {});
-        let mut merge_handle = thread::spawn(move || {});
-        let total_frames_style = "[fram][{elapsed_precise}] [{wide_bar:.green/white}] {pos:>7}/{len:7} total frames             eta: {eta:<7}";
-        let info_style = "[info][{elapsed_precise}] [{wide_bar:.green/white}] {pos:>7}/{len:7} processed segments       eta: {eta:<7}";
-        let expo_style = "[expo][{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos:>7}/{len:7} exporting segment        {per_sec:<12}";
-        let upsc_style = "[upsc][{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos:>7}/{len:7} upscaling segment        {per_sec:<12}";
-        let merg_style = "[merg][{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos:>7}/{len:7} merging segment          {per_sec:<12}";
-        let _alt_style = "[]{elapsed}] {wide_bar:.cyan/blue} {spinner} {percent}% {human_len:>7}/{human_len:7} {per_sec} {eta}";
-
-        let m = MultiProgress::new();
-        let pb = m.add(ProgressBar::new(parts_num as u64));
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template(info_style)
-                .unwrap()
-                .progress_chars("#>-"),
-        );
-        let mut last_pb = pb.clone();
-
-        //let progress_bar = m.insert_after(&last_pb, ProgressBar::new(total_files as u64));
-        let progress_bar_frames =
-            m.insert_after(&last_pb, ProgressBar::new(total_frames_count as u64));
-        progress_bar_frames.set_style(
-            ProgressStyle::default_bar()
-                .template(total_frames_style)
-                .unwrap()
-                .progress_chars("#>-"),
-        );
-        progress_bar_frames.set_position(frame_position as u64);
-
-        last_pb = progress_bar_frames.clone();
-
-        // Initial export
-        if !unprocessed_indexes.is_empty() {
-            let index = unprocessed_indexes[0].index;
-            let _inpt = &args.inputpath.clone();
-            #[cfg(target_os = "linux")]
-            let _outpt = format!("/dev/shm/tmp_frames/{}/frame%08d.png", index);
-            #[cfg(target_os = "windows")]
-            let _outpt = format!("temp\\tmp_frames\\{}\\frame%08d.png", index);
-            let _start_time = if index == 0 {
-                String::from("0")
-            } else {
-                ((index * args.segmentsize - 1) as f32
-                    / original_frame_rate.parse::<f32>().unwrap())
-                .to_string()
-            };
-            #[cfg(target_os = "linux")]
-            let _index_dir = format!("/dev/shm/tmp_frames/{}", index);
-            #[cfg(target_os = "windows")]
-            let _index_dir = format!("temp\\tmp_frames\\{}", index);
-            let _frame_number = unprocessed_indexes[0].size;
-
-            let progress_bar = m.insert_after(&last_pb, ProgressBar::new(_frame_number as u64));
-            progress_bar.set_style(
-                ProgressStyle::default_bar()
-                    .template(expo_style)
-                    .unwrap()
-                    .progress_chars("#>-"),
-            );
-            last_pb = progress_bar.clone();
-
-            fs::create_dir(&_index_dir).expect("could not create directory");
-
-            // TODO LINUX: /dev/shm to export the frames
-            // https://github.com/PauMAVA/cargo-ramdisk
-            // Windows doesn't really have something native like a ramdisk sadly
-            export_frames(
-                &args.inputpath,
-                &_outpt,
-                &_start_time,
-                &(_frame_number as u32),
-                progress_bar,
-            )
-            .unwrap();
-            m.clear().unwrap();
+        QueueCommand::Reorder { id, priority } => {
+            if !set_job_priority(&conn, *id, *priority)? {
+                return Err(ReveError::InvalidInput(format!("no job with id {}", id)));
+            }
+            println!("job {} now has priority {}", id, priority);
         }
-
-        for _ in 0..unprocessed_indexes.len() {
-            let segment = &unprocessed_indexes[0];
-            export_handle.join().unwrap();
-            if unprocessed_indexes.len() != 1 {
-                let index = unprocessed_indexes[1].index;
-                let _inpt = args.inputpath.clone();
-                #[cfg(target_os = "linux")]
-                let _outpt = format!("/dev/shm/tmp_frames/{}/frame%08d.png", index);
-                #[cfg(target_os = "windows")]
-                let _outpt = format!("temp\\tmp_frames\\{}\\frame%08d.png", index);
-                let _start_time = ((index * args.segmentsize - 1) as f32
-                    / original_frame_rate.parse::<f32>().unwrap())
-                .to_string();
-                #[cfg(target_os = "linux")]
-                let _index_dir = format!("/dev/shm/tmp_frames/{}", index);
-                #[cfg(target_os = "windows")]
-                let _index_dir = format!("temp\\tmp_frames\\{}", index);
-                let _frame_number = unprocessed_indexes[1].size;
-
-                let progress_bar = m.insert_after(&last_pb, ProgressBar::new(_frame_number as u64));
-                progress_bar.set_style(
-                    ProgressStyle::default_bar()
-                        .template(expo_style)
-                        .unwrap()
-                        .progress_chars("#>-"),
-                );
-                last_pb = progress_bar.clone();
-
-                export_handle = thread::spawn(move || {
-                    fs::create_dir(&_index_dir).expect("could not create directory");
-                    export_frames(
-                        &_inpt,
-                        &_outpt,
-                        &_start_time,
-                        &(_frame_number as u32),
-                        progress_bar,
-                    )
-                    .unwrap();
-                });
-            } else {
-                export_handle = thread::spawn(move || {});
+        QueueCommand::Remove { id } => {
+            if !remove_job(&conn, *id)? {
+                return Err(ReveError::InvalidInput(format!(
+                    "no job with id {} that isn't running",
+                    id
+                )));
             }
-
-            #[cfg(target_os = "linux")]
-            let inpt_dir = format!("/dev/shm/tmp_frames/{}", segment.index);
-            #[cfg(target_os = "linux")]
-            let outpt_dir = format!("/dev/shm/out_frames/{}", segment.index);
-            #[cfg(target_os = "windows")]
-            let inpt_dir = format!("temp\\tmp_frames\\{}", segment.index);
-            #[cfg(target_os = "windows")]
-            let outpt_dir = format!("temp\\out_frames\\{}", segment.index);
-
-            fs::create_dir(&outpt_dir).expect("could not create directory");
-
-            let frame_number = unprocessed_indexes[0].size;
-
-            let progress_bar = m.insert_after(&last_pb, ProgressBar::new(frame_number as u64));
-            progress_bar.set_style(
-                ProgressStyle::default_bar()
-                    .template(upsc_style)
-                    .unwrap()
-                    .progress_chars("#>-"),
-            );
-            last_pb = progress_bar.clone();
-
-            frame_position = upscale_frames(
-                &inpt_dir,
-                &outpt_dir,
-                &args.scale.to_string(),
-                &args.model,
-                progress_bar,
-                progress_bar_frames.clone(),
-                frame_position,
-            )
-            .expect("could not upscale frames");
-
-            merge_handle.join().unwrap();
-
-            let _codec = args.codec.clone();
-            #[cfg(target_os = "linux")]
-            let _inpt = format!("/dev/shm/out_frames/{}/frame%08d.png", segment.index);
-            #[cfg(target_os = "linux")]
-            let _outpt = format!("/dev/shm/video_parts/{}.{}", segment.index, &args.format);
-            #[cfg(target_os = "windows")]
-            let _inpt = format!("temp\\out_frames\\{}\\frame%08d.png", segment.index);
-            #[cfg(target_os = "windows")]
-            let _outpt = format!("temp\\video_parts\\{}.{}", segment.index, &args.format);
-            let _frmrt = original_frame_rate.clone();
-            let _crf = args.crf.clone().to_string();
-            let _preset = args.preset.clone();
-            let _x265_params = args.x265params.clone();
-            let _extension = args.format.clone();
-
-            let progress_bar = m.insert_after(&last_pb, ProgressBar::new(frame_number as u64));
-            progress_bar.set_style(
-                ProgressStyle::default_bar()
-                    .template(merg_style)
-                    .unwrap()
-                    .progress_chars("#>-"),
-            );
-            last_pb = progress_bar.clone();
-
-            merge_handle = thread::spawn(move || {
-                // 2022-03-28 07:12 c2d1597
-                // https://github.com/AnimMouse/ffmpeg-autobuild/releases/download/m-2022-03-28-07-12/ffmpeg-c2d1597-651202b-win64-nonfree.7z
-                fs::remove_dir_all(&inpt_dir).unwrap();
-                if &_codec == "libsvt_hevc" {
-                    merge_frames_svt_hevc(&_inpt, &_outpt, &_codec, &_frmrt, &_crf, progress_bar)
-                        .unwrap();
-                    fs::remove_dir_all(&outpt_dir).unwrap();
-                } else if &_codec == "libsvtav1" {
-                    merge_frames_svt_av1(&_inpt, &_outpt, &_codec, &_frmrt, &_crf, progress_bar)
-                        .unwrap();
-                    fs::remove_dir_all(&outpt_dir).unwrap();
-                } else if &_codec == "libx265" {
-                    merge_frames(
-                        &_inpt,
-                        &_outpt,
-                        &_codec,
-                        &_frmrt,
-                        &_crf,
-                        &_preset,
-                        &_x265_params,
-                        progress_bar,
-                    )
-                    .unwrap();
-                    fs::remove_dir_all(&outpt_dir).unwrap();
-                }
-            });
-
-            unprocessed_indexes.remove(0);
-            pb.set_position((parts_num - unprocessed_indexes.len() as i32 - 1) as u64);
+            println!("removed job {}", id);
         }
-        merge_handle.join().unwrap();
-        m.clear().unwrap();
+        QueueCommand::Run => run_queue(&mut conn)?,
     }
+    Ok(())
+}
 
-    // Merge video parts
-    let choosen_extension = &args.format;
-    #[cfg(target_os = "linux")]
-    let mut f_content = format!("file 'video_parts/0.{}'", choosen_extension);
-    #[cfg(target_os = "windows")]
-    let mut f_content = format!("file 'video_parts\\0.{}'", choosen_extension);
-
-    for part_number in 1..parts_num {
-        #[cfg(target_os = "linux")]
-        let video_part_path = format!("video_parts/{}.{}", part_number, choosen_extension);
-        #[cfg(target_os = "windows")]
-        let video_part_path = format!("video_parts\\{}.{}", part_number, choosen_extension);
-        f_content = format!("{}\nfile '{}'", f_content, video_part_path);
+/// Runs queued jobs until the queue is empty.
+pub fn run_queue(conn: &mut Connection) -> Result<(), ReveError> {
+    let requeued = requeue_interrupted(conn)?;
+    if requeued > 0 {
+        println!("resuming {} interrupted jobs", requeued);
+    }
+    while let Some(job) = dequeue_next(conn)? {
+        run_job(conn, &job)?;
     }
+    println!("queue is empty");
+    Ok(())
+}
 
-    fs::write(txt_list_path, f_content).expect("Unable to write file");
+/// Runs a dequeued job and marks how it ended. Every job runs in its own reve process, so a
+/// failing job can't stop the queue.
+fn run_job(conn: &Connection, job: &Job) -> Result<(), ReveError> {
+    println!("running job {}: {}", job.id, job.input);
+    let status = Command::new(env::current_exe()?)
+        .args(job.settings.to_cli_args())
+        .status();
+    match status {
+        Ok(status) if status.success() => mark_done(conn, job.id),
+        Ok(status) => mark_failed(conn, job.id, &format!("reve exited with {}", status)),
+        Err(e) => mark_failed(conn, job.id, &e.to_string()),
+    }
+}
 
-    println!("merging video segments");
-    {
-        let mut count = 0;
-        let p = Path::new(&temp_video_path);
-        loop {
-            thread::sleep(Duration::from_secs(1));
-            if count == 5 {
-                panic!("could not merge segments")
-            } else if p.exists() {
-                if fs::File::open(p).unwrap().metadata().unwrap().len() == 0 {
-                    count += 1;
-                } else {
-                    break;
-                }
-            } else {
-                if dar == "0" || dar == "N/A" {
-                    merge_video_parts(&txt_list_path.to_string(), &temp_video_path.to_string());
-                } else {
-                    merge_video_parts_dar(
-                        &txt_list_path.to_string(),
-                        &temp_video_path.to_string(),
-                        &dar,
-                    );
+/// `--watch`: queues videos added to the input folder once they are completely written and
+/// runs them, until the process is stopped.
+///
+/// New files go through the same resolution filter as `reve -i <folder>`, files already done
+/// in video_info or already queued are left alone. Only the jobs the watcher queued run, the
+/// rest of the queue is left to `reve queue run`, and the upscales it writes into the folder
+/// aren't queued again.
+pub fn watch(args: &Args) -> Result<(), ReveError> {
+    let mut conn = open_db("reve.db")?;
+
+    let mut watcher =
+        FolderWatcher::new(args.input()).ignore(&format!(".{}.{}", args.codec, args.format));
+    println!(
+        "watching {} for new videos every {}s",
+        args.input(),
+        args.watch_interval
+    );
+    loop {
+        let ready = watcher.poll();
+        if !ready.is_empty() {
+            let mut ids = Vec::new();
+            // a file ffprobe can't read is skipped, the folder keeps being watched
+            match enqueue_new_files(&conn, ready, args, 0, |_| None) {
+                Ok(queued) => {
+                    for (id, file) in queued {
+                        println!("queued job {}: {}", id, file);
+                        ids.push(id);
+                    }
                 }
-                count += 1;
+                Err(e) => println!("{}", e),
+            }
+            while let Some(job) = dequeue_next_of(&mut conn, &ids)? {
+                run_job(&conn, &job)?;
             }
+            println!("watching {} for new videos", args.input());
         }
+        thread::sleep(Duration::from_secs(args.watch_interval));
     }
+}
 
-    //Check if there is invalid bin data in the input file
-    let bin_data = get_bin_data(&args.inputpath);
-    if bin_data != "" {
-        println!("invalid data at index: {}, skipping this one", bin_data);
-        println!("copying streams");
-        copy_streams_no_bin_data(&temp_video_path.to_string(), &args.inputpath, &output_path);
-    } else {
-        println!("copying streams");
-        copy_streams(&temp_video_path.to_string(), &args.inputpath, &output_path);
-    }
-
-    //Check if file has been copied successfully to output path, if so, update database
-    let p = Path::new(&output_path);
-    if p.exists() {
-        if fs::File::open(p).unwrap().metadata().unwrap().len() == 0 {
-            panic!("failed to copy streams");
-        }
-        // update sqlite database "reve.db" entry with status "done" using update_db_status function;
-        let conn = Connection::open("reve.db").unwrap();
-        let db_status = update_db_status(&conn, &args.inputpath, "done");
-        match db_status {
-            Ok(_) => println!("updated database"),
-            Err(e) => println!("failed to update database: {}", e),
+/// Queues the videos of `files` that pass the `--resolution` filter of `settings`, the way
+/// `reve -i <folder>` picks them, each one upscaled to `output(file)` or next to it when that
+/// is None. Files already done in video_info or already queued are left alone. Returns the id
+/// and path of every new job.
+pub fn enqueue_new_files(
+    conn: &Connection,
+    files: Vec<String>,
+    settings: &Args,
+    priority: i64,
+    output: impl Fn(&str) -> Option<String>,
+) -> Result<Vec<(i64, String)>, ReveError> {
+    let resolution = settings
+        .resolution
+        .clone()
+        .unwrap_or_else(|| String::from("480"));
+    let (_, to_process) = add_to_db(files, resolution, ProgressBar::hidden())?;
+
+    let mut queued = Vec::new();
+    for file in to_process {
+        if get_db_status(conn, &file)?.as_deref() == Some("done") || job_exists(conn, &file)? {
+            continue;
         }
-    } else {
-        panic!("failed to copy streams");
+        let output = output(&file);
+        queued.push((
+            enqueue(conn, &file, output.as_deref(), settings, priority)?,
+            file,
+        ));
     }
-
-    clear().expect("failed to clear screen");
-    let elapsed = work_now.elapsed();
-    let seconds = elapsed.as_secs() % 60;
-    let minutes = (elapsed.as_secs() / 60) % 60;
-    let hours = (elapsed.as_secs() / 60) / 60;
-
-    let ancestors = Path::new(&args.inputpath).file_name().unwrap();
-    println!(
-        "done {:?} to {:?} in {}h:{}m:{}s",
-        ancestors, done_output, hours, minutes, seconds
-    );
+    Ok(queued)
 }
@@ -0,0 +1,357 @@
+use crate::{absolute_path, file_name, ReveError};
+use fnv::FnvHasher;
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Bytes hashed at the start, the middle and the end of a file.
+const SAMPLE_SIZE: u64 = 1 << 20;
+
+/// Identity of a video in video_info: a hash of its first, middle and last MiB, its size and
+/// its duration in milliseconds, `<hash>-<size>-<duration>`. It only reads 3 MiB of the file and
+/// stays the same when the file is renamed or moved.
+pub fn fingerprint_file<P: AsRef<Path>>(path: P, duration: f64) -> Result<String, ReveError> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let offsets = if size <= 3 * SAMPLE_SIZE {
+        // the samples cover the whole file
+        vec![0, SAMPLE_SIZE, 2 * SAMPLE_SIZE]
+    } else {
+        vec![0, size / 2 - SAMPLE_SIZE / 2, size - SAMPLE_SIZE]
+    };
+
+    let mut hasher = FnvHasher::default();
+    let mut sample = Vec::with_capacity(SAMPLE_SIZE as usize);
+    for offset in offsets {
+        file.seek(SeekFrom::Start(offset))?;
+        sample.clear();
+        (&mut file).take(SAMPLE_SIZE).read_to_end(&mut sample)?;
+        hasher.write(&sample);
+    }
+    Ok(format!(
+        "{:016x}-{}-{}",
+        hasher.finish(),
+        size,
+        (duration * 1000.0).round() as i64
+    ))
+}
+
+/// What `record_video` did with a scanned file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LibraryRecord {
+    /// not in the library before
+    Added,
+    /// not in the library before, the entry of the other video that was at this path is
+    /// replaced by a new one
+    Replaced,
+    /// already in the library at this path
+    Known,
+    /// in the library under another path that is gone, the entry now has this path
+    Moved { from: String },
+    /// the same content as the entry at another path that still exists, nothing recorded
+    Copy { of: String },
+}
+
+/// Records the video at `filepath` in video_info under its `fingerprint`, with the stream
+/// details of its ffprobe output. A new video taller than `max_height` is added as skipped.
+///
+/// Entries recorded before fingerprints existed are matched by path and get one.
+pub fn record_video(
+    conn: &Connection,
+    filepath: &str,
+    fingerprint: &str,
+    probe: &Value,
+    max_height: i64,
+) -> Result<LibraryRecord, ReveError> {
+    let known: Option<(i64, String)> = conn
+        .query_row(
+            "SELECT id, filepath FROM video_info WHERE fingerprint = ?1",
+            params![fingerprint],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let filename = file_name(filepath);
+    if let Some((id, known_path)) = known {
+        if known_path == filepath {
+            return Ok(LibraryRecord::Known);
+        }
+        if Path::new(&known_path).exists() {
+            return Ok(LibraryRecord::Copy { of: known_path });
+        }
+        conn.execute(
+            "UPDATE video_info SET filepath = ?1, filename = ?2 WHERE id = ?3",
+            params![filepath, filename, id],
+        )?;
+        return Ok(LibraryRecord::Moved { from: known_path });
+    }
+
+    let updated = conn.execute(
+        "UPDATE video_info SET fingerprint = ?1 WHERE filepath = ?2 AND fingerprint IS NULL",
+        params![fingerprint, filepath],
+    )?;
+    if updated > 0 {
+        return Ok(LibraryRecord::Known);
+    }
+    // another video took the place of the one recorded at this path
+    let replaced = conn.execute(
+        "DELETE FROM video_info WHERE filepath = ?1",
+        params![filepath],
+    )?;
+
+    let format = &probe["format"];
+    let stream = &probe["streams"][0];
+    let height = stream["height"].as_i64().unwrap_or(0);
+    let status = if height <= max_height {
+        "pending"
+    } else {
+        "skipped"
+    };
+    let folder_size = folder_size(filepath);
+    conn.execute(
+        "INSERT INTO video_info (filename, filepath, width, height, duration, pixel_format, display_aspect_ratio, sample_aspect_ratio, format, size, folder_size, bitrate, codec, resolution, status, hash, fingerprint) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
+        params![
+            filename,
+            filepath,
+            stream["width"].as_i64().unwrap_or(0),
+            height,
+            format["duration"].as_str().unwrap_or("0.0"),
+            stream["pix_fmt"].as_str().unwrap_or("NaN"),
+            stream["display_aspect_ratio"].as_str().unwrap_or("NaN"),
+            stream["sample_aspect_ratio"].as_str().unwrap_or("NaN"),
+            format["format_name"].as_str().unwrap_or("NaN"),
+            format["size"].as_str().unwrap_or("0"),
+            folder_size,
+            format["bit_rate"].as_str().unwrap_or("0"),
+            stream["codec_name"].as_str().unwrap_or("NaN"),
+            max_height.to_string(),
+            status,
+            stream["extradata_hash"].as_str().unwrap_or("NaN"),
+            fingerprint
+        ],
+    )?;
+    Ok(if replaced > 0 {
+        LibraryRecord::Replaced
+    } else {
+        LibraryRecord::Added
+    })
+}
+
+/// Size of the files in the folder of `filepath` and its subfolders.
+fn folder_size(filepath: &str) -> i64 {
+    let folder = Path::new(filepath).parent().unwrap_or(Path::new("."));
+    walkdir::WalkDir::new(folder)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len() as i64)
+        .sum()
+}
+
+/// A video of the library, as `reve db` shows it.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct LibraryVideo {
+    pub id: i64,
+    pub filepath: String,
+    pub width: i64,
+    pub height: i64,
+    /// seconds
+    pub duration: f64,
+    pub codec: String,
+    /// bytes
+    pub size: i64,
+    /// bits per second
+    pub bitrate: i64,
+    pub status: String,
+    pub fingerprint: Option<String>,
+}
+
+/// Which videos `reve db list` and `reve db export` show, every video by default.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct LibraryFilter {
+    /// only videos with this status
+    #[clap(long, value_parser = status_validation)]
+    pub status: Option<String>,
+    /// only videos in this folder or its subfolders
+    #[clap(long)]
+    pub folder: Option<String>,
+    /// only videos with this codec (h264, hevc, ...)
+    #[clap(long)]
+    pub codec: Option<String>,
+    /// only videos at most this tall
+    #[clap(long)]
+    pub resolution: Option<i64>,
+}
+
+impl LibraryFilter {
+    fn matches(&self, video: &LibraryVideo) -> bool {
+        self.status.as_ref().is_none_or(|s| *s == video.status)
+            && self.codec.as_ref().is_none_or(|c| *c == video.codec)
+            && self.resolution.is_none_or(|r| video.height <= r)
+            && self.folder.as_ref().is_none_or(|folder| {
+                Path::new(&absolute_path(&video.filepath)).starts_with(absolute_path(folder))
+            })
+    }
+}
+
+/// Checks a video_info status.
+pub fn status_validation(s: &str) -> Result<String, String> {
+    match s {
+        "pending" | "processing" | "done" | "skipped" => Ok(s.to_string()),
+        _ => Err(String::from("valid: pending/processing/done/skipped")),
+    }
+}
+
+/// The videos of video_info that `filter` matches, by path.
+pub fn list_videos(
+    conn: &Connection,
+    filter: &LibraryFilter,
+) -> Result<Vec<LibraryVideo>, ReveError> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM video_info ORDER BY filepath",
+        VIDEO_COLUMNS
+    ))?;
+    let rows = stmt.query_map(params![], video_from_row)?;
+    let mut videos = Vec::new();
+    for video in rows {
+        let video = video?;
+        if filter.matches(&video) {
+            videos.push(video);
+        }
+    }
+    Ok(videos)
+}
+
+/// The video of the library with `fingerprint`, or recorded at `filepath` before fingerprints
+/// existed. Only reads, for `--dry-run`.
+pub fn find_video(
+    conn: &Connection,
+    filepath: &str,
+    fingerprint: &str,
+) -> Result<Option<LibraryVideo>, ReveError> {
+    Ok(conn
+        .query_row(
+            &format!(
+                "SELECT {} FROM video_info
+                 WHERE fingerprint = ?1 OR (filepath = ?2 AND fingerprint IS NULL)
+                 ORDER BY fingerprint IS NULL",
+                VIDEO_COLUMNS
+            ),
+            params![fingerprint, filepath],
+            video_from_row,
+        )
+        .optional()?)
+}
+
+const VIDEO_COLUMNS: &str =
+    "id, filepath, width, height, duration, codec, size, bitrate, status, fingerprint";
+
+fn video_from_row(row: &rusqlite::Row) -> rusqlite::Result<LibraryVideo> {
+    Ok(LibraryVideo {
+        id: row.get(0)?,
+        filepath: row.get(1)?,
+        width: row.get(2)?,
+        height: row.get(3)?,
+        duration: number(row.get_ref(4)?),
+        codec: row.get(5)?,
+        size: number(row.get_ref(6)?) as i64,
+        bitrate: number(row.get_ref(7)?) as i64,
+        status: row.get(8)?,
+        fingerprint: row.get(9)?,
+    })
+}
+
+/// A number column, 0 for the "N/A" ffprobe writes when it doesn't know.
+fn number(value: ValueRef) -> f64 {
+    match value {
+        ValueRef::Integer(i) => i as f64,
+        ValueRef::Real(f) => f,
+        ValueRef::Text(text) => std::str::from_utf8(text)
+            .ok()
+            .and_then(|text| text.parse().ok())
+            .unwrap_or(0.0),
+        _ => 0.0,
+    }
+}
+
+/// Totals of the library for `reve db stats`.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct LibraryStats {
+    pub videos: usize,
+    /// bytes
+    pub size: i64,
+    /// seconds
+    pub duration: f64,
+    pub by_status: BTreeMap<String, usize>,
+    pub by_codec: BTreeMap<String, usize>,
+}
+
+pub fn library_stats(conn: &Connection) -> Result<LibraryStats, ReveError> {
+    let mut stats = LibraryStats::default();
+    for video in list_videos(conn, &LibraryFilter::default())? {
+        stats.videos += 1;
+        stats.size += video.size;
+        stats.duration += video.duration;
+        *stats.by_status.entry(video.status).or_default() += 1;
+        *stats.by_codec.entry(video.codec).or_default() += 1;
+    }
+    Ok(stats)
+}
+
+/// Sets the videos with status `from` back to `to`, like the `processing` ones a crash left
+/// behind. Returns how many changed.
+pub fn reset_db_status(conn: &Connection, from: &str, to: &str) -> Result<usize, ReveError> {
+    Ok(conn.execute(
+        "UPDATE video_info SET status = ?1 WHERE status = ?2",
+        params![to, from],
+    )?)
+}
+
+/// Removes the video at `path`, or every video under the folder `path`, from the library. They
+/// are added again, as pending, the next time their folder is upscaled. Returns the removed
+/// videos.
+pub fn forget_videos(conn: &Connection, path: &str) -> Result<Vec<LibraryVideo>, ReveError> {
+    let filter = LibraryFilter {
+        folder: Some(path.to_string()),
+        ..LibraryFilter::default()
+    };
+    let videos = list_videos(conn, &filter)?;
+    for video in &videos {
+        conn.execute("DELETE FROM video_info WHERE id = ?1", params![video.id])?;
+    }
+    Ok(videos)
+}
+
+/// The videos as CSV, with a header row.
+pub fn videos_to_csv(videos: &[LibraryVideo]) -> String {
+    fn field(value: &str) -> String {
+        if value.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        }
+    }
+    let mut csv =
+        String::from("id,filepath,width,height,duration,codec,size,bitrate,status,fingerprint\n");
+    for video in videos {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{},{}\n",
+            video.id,
+            field(&video.filepath),
+            video.width,
+            video.height,
+            video.duration,
+            field(&video.codec),
+            video.size,
+            video.bitrate,
+            field(&video.status),
+            field(video.fingerprint.as_deref().unwrap_or(""))
+        ));
+    }
+    csv
+}
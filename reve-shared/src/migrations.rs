@@ -0,0 +1,136 @@
+use crate::ReveError;
+use rusqlite::{params, Connection, Transaction, TransactionBehavior};
+use std::path::Path;
+use std::time::Duration;
+
+/// The schema of reve.db, as the changes that built it. Migration `n` takes a database from
+/// `PRAGMA user_version` n to n + 1, a new column or table is a new migration appended here,
+/// the ones already released are never edited.
+const MIGRATIONS: &[fn(&Connection) -> rusqlite::Result<()>] =
+    &[video_info, jobs, video_fingerprint, job_owner];
+
+/// `user_version` of a database with every migration applied.
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Opens reve.db, or another database at `path`, and brings its schema up to date.
+pub fn open_db<P: AsRef<Path>>(path: P) -> Result<Connection, ReveError> {
+    let conn = Connection::open(path)?;
+    // a watcher, the server and the GUI can write to the same database
+    conn.busy_timeout(Duration::from_secs(5))?;
+    migrate_db(&conn)?;
+    Ok(conn)
+}
+
+/// Applies the migrations a database hasn't had yet, each in its own transaction.
+pub fn migrate_db(conn: &Connection) -> Result<(), ReveError> {
+    let version: u32 = conn.query_row("PRAGMA user_version", params![], |row| row.get(0))?;
+    if version == SCHEMA_VERSION {
+        return Ok(());
+    }
+    loop {
+        // the version is read under the write lock, another reve may be migrating as well
+        let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+        let version: u32 = tx.query_row("PRAGMA user_version", params![], |row| row.get(0))?;
+        if version > SCHEMA_VERSION {
+            return Err(ReveError::InvalidInput(format!(
+                "reve.db schema version {} is newer than this reve ({})",
+                version, SCHEMA_VERSION
+            )));
+        }
+        if version == SCHEMA_VERSION {
+            return Ok(());
+        }
+        MIGRATIONS[version as usize](&tx)?;
+        // PRAGMA doesn't take parameters
+        tx.execute_batch(&format!("PRAGMA user_version = {}", version + 1))?;
+        tx.commit()?;
+    }
+}
+
+/// 1: video_info, the library of `reve -i <folder>`.
+///
+/// Before versions were tracked it was created in two places that disagreed on the type of
+/// duration, size and bitrate. A table with the TEXT ones is rebuilt with numeric columns.
+fn video_info(conn: &Connection) -> rusqlite::Result<()> {
+    let text_columns: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM pragma_table_info('video_info')
+         WHERE name IN ('duration', 'size', 'bitrate') AND type = 'TEXT'",
+        params![],
+        |row| row.get(0),
+    )?;
+    if text_columns > 0 {
+        conn.execute(
+            "ALTER TABLE video_info RENAME TO video_info_text",
+            params![],
+        )?;
+    }
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS video_info (
+            id INTEGER PRIMARY KEY,
+            filename TEXT NOT NULL,
+            filepath TEXT NOT NULL,
+            width INTEGER NOT NULL,
+            height INTEGER NOT NULL,
+            duration REAL NOT NULL,
+            pixel_format TEXT NOT NULL,
+            display_aspect_ratio TEXT NOT NULL,
+            sample_aspect_ratio TEXT NOT NULL,
+            format TEXT NOT NULL,
+            size BIGINT NOT NULL,
+            folder_size BIGINT NOT NULL,
+            bitrate BIGINT NOT NULL,
+            codec TEXT NOT NULL,
+            resolution TEXT NOT NULL,
+            status TEXT NOT NULL,
+            hash TEXT NOT NULL
+        )",
+        params![],
+    )?;
+    if text_columns > 0 {
+        conn.execute_batch(
+            "INSERT INTO video_info
+             SELECT id, filename, filepath, width, height, CAST(duration AS REAL), pixel_format,
+                    display_aspect_ratio, sample_aspect_ratio, format, CAST(size AS INTEGER),
+                    folder_size, CAST(bitrate AS INTEGER), codec, resolution, status, hash
+             FROM video_info_text;
+             DROP TABLE video_info_text;",
+        )?;
+    }
+    Ok(())
+}
+
+/// 2: jobs, the queue of `reve queue`, `reve serve` and the GUI.
+fn jobs(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS jobs (
+            id INTEGER PRIMARY KEY,
+            input TEXT NOT NULL,
+            output TEXT,
+            settings TEXT NOT NULL,
+            priority INTEGER NOT NULL DEFAULT 0,
+            state TEXT NOT NULL DEFAULT 'queued',
+            attempts INTEGER NOT NULL DEFAULT 0,
+            error TEXT,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL
+        )",
+        params![],
+    )?;
+    Ok(())
+}
+
+/// 3: video_info entries are identified by the fingerprint of their content, the filepath of
+/// an entry changes when its file moves. Older entries get theirs when they are scanned again.
+fn video_fingerprint(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE video_info ADD COLUMN fingerprint TEXT;
+         CREATE UNIQUE INDEX video_info_fingerprint ON video_info (fingerprint);",
+    )
+}
+
+/// 4: running jobs remember the pid of the reve running them, so a runner only requeues the
+/// jobs of runners that are gone.
+fn job_owner(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute("ALTER TABLE jobs ADD COLUMN owner INTEGER", params![])?;
+    Ok(())
+}
@@ -0,0 +1,363 @@
+use crate::error::StderrTail;
+use crate::progress::FrameCounter;
+use crate::{
+    EncoderProfile, ExtractMode, JobControl, ProgressEvent, ProgressSink, ReveError, Segment,
+    Stage, Upscaler,
+};
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::Path;
+use std::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command, ExitStatus, Stdio};
+use std::thread::{self, JoinHandle};
+
+/// One segment upscaled without writing the whole segment as PNG files.
+///
+/// ffmpeg decodes the segment to rgb24 rawvideo on a pipe, the upscaler turns it into upscaled
+/// frames and a second ffmpeg encodes them from stdin. Streaming backends are fed straight from
+/// the decoder; folder backends get `window` frames at a time through `window_dir`.
+pub struct PipeSegment<'a> {
+    pub input_path: &'a str,
+    pub output_path: &'a str,
+    pub segment: &'a Segment,
+    pub extract: ExtractMode,
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate: &'a str,
+    pub scale: u8,
+    pub model: &'a str,
+    pub profile: &'a EncoderProfile,
+    pub crf: u8,
+    pub preset: &'a str,
+    pub params: &'a str,
+    pub window_dir: &'a str,
+    pub window: usize,
+    /// the decoder, upscaler and encoder are started through it
+    pub control: &'a JobControl,
+}
+
+impl PipeSegment<'_> {
+    /// Reports upscale progress per encoded frame, the segment counts as upscaled and merged
+    /// once the encoder is done.
+    pub fn run(
+        &self,
+        upscaler: &dyn Upscaler,
+        progress: &dyn ProgressSink,
+    ) -> Result<(), ReveError> {
+        let mut decoder = self.decoder()?;
+        let mut encoder = self.encoder()?;
+        let decoded = decoder.stdout.take().ok_or_else(no_pipe)?;
+        let encoder_stdin = encoder.stdin.take().ok_or_else(no_pipe)?;
+        let decoder_stderr = drain(decoder.stderr.take());
+        let encoder_stderr = drain(encoder.stderr.take());
+
+        let mut counter = FrameCounter::new(progress, Stage::Upscale, self.segment);
+        let result = match upscaler.stream_command(self.width, self.height, self.scale, self.model)
+        {
+            Some(command) => self.run_streaming(command, decoded, encoder_stdin, &mut counter),
+            None => self.run_window(upscaler, decoded, encoder_stdin, &mut counter),
+        };
+        // a decoder that failed on its own is why the frames stopped, not the truncated frame
+        let decoder_failed =
+            result.is_err() && matches!(decoder.try_wait(), Ok(Some(status)) if !status.success());
+        if result.is_err() {
+            let _ = decoder.kill();
+            let _ = encoder.kill();
+        }
+        let decoder_status = self.control.wait(&mut decoder);
+        let encoder_status = self.control.wait(&mut encoder);
+        let decoder_stderr = decoder_stderr.join().unwrap_or_default();
+        let encoder_stderr = encoder_stderr.join().unwrap_or_default();
+        let decoder_status = decoder_status?;
+        let encoder_status = encoder_status?;
+        if decoder_failed {
+            return Err(ReveError::ffmpeg("decode segment", decoder_stderr));
+        }
+        result?;
+
+        if !decoder_status.success() {
+            return Err(ReveError::ffmpeg("decode segment", decoder_stderr));
+        }
+        if !encoder_status.success() {
+            return Err(ReveError::ffmpeg("encode segment", encoder_stderr));
+        }
+        progress.event(ProgressEvent::FramesUpscaled {
+            segment: self.segment.index,
+            frames: counter.done(),
+            fps: counter.fps(),
+        });
+        progress.event(ProgressEvent::SegmentMerged {
+            segment: self.segment.index,
+            frames: counter.done(),
+        });
+        Ok(())
+    }
+
+    fn frame_size(&self) -> usize {
+        self.width as usize * self.height as usize * 3
+    }
+
+    fn upscaled_frame_size(&self) -> usize {
+        self.frame_size() * self.scale as usize * self.scale as usize
+    }
+
+    fn decoder(&self) -> Result<Child, ReveError> {
+        let frame_rate = self.frame_rate.parse::<f32>().unwrap_or(0.0);
+        self.control.spawn(
+            Command::new("ffmpeg")
+                .args(["-v", "error"])
+                .args(
+                    self.segment
+                        .input_args(self.extract, self.input_path, frame_rate),
+                )
+                .args(self.segment.output_args(self.extract, false))
+                .args(["-f", "rawvideo", "-pix_fmt", "rgb24", "-"])
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped()),
+        )
+    }
+
+    fn encoder(&self) -> Result<Child, ReveError> {
+        self.control.spawn(
+            Command::new("ffmpeg")
+                .args([
+                    "-v",
+                    "error",
+                    "-y",
+                    "-f",
+                    "rawvideo",
+                    "-pix_fmt",
+                    "rgb24",
+                    "-s",
+                    &format!(
+                        "{}x{}",
+                        self.width * self.scale as u32,
+                        self.height * self.scale as u32
+                    ),
+                    "-framerate",
+                    self.frame_rate,
+                    "-i",
+                    "-",
+                ])
+                .args(self.profile.args(self.crf, self.preset, self.params))
+                .arg(self.output_path)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped()),
+        )
+    }
+
+    /// Runs the upscaler between the decoder and the encoder. Whatever goes wrong, the upscaler
+    /// is gone and the thread feeding it is joined when this returns.
+    fn run_streaming(
+        &self,
+        mut command: Command,
+        mut decoded: ChildStdout,
+        encoder_stdin: ChildStdin,
+        counter: &mut FrameCounter,
+    ) -> Result<(), ReveError> {
+        let mut upscaler = self.control.spawn(
+            command
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped()),
+        )?;
+        let upscaler_stderr = drain(upscaler.stderr.take());
+        let (Some(mut upscaler_stdin), Some(mut upscaled)) =
+            (upscaler.stdin.take(), upscaler.stdout.take())
+        else {
+            let _ = upscaler.kill();
+            let _ = self.control.wait(&mut upscaler);
+            return Err(no_pipe().into());
+        };
+
+        // feed the upscaler from another thread so both pipes keep moving
+        let feeder = thread::spawn(move || std::io::copy(&mut decoded, &mut upscaler_stdin));
+
+        let encoded = self.encode(&mut upscaled, encoder_stdin, counter);
+        // an upscaler that failed on its own is why the frames stopped
+        let upscaler_failed = encoded.is_err()
+            && matches!(upscaler.try_wait(), Ok(Some(status)) if !status.success());
+        if encoded.is_err() {
+            // the feeder's writes fail once the upscaler is gone
+            let _ = upscaler.kill();
+        }
+        drop(upscaled);
+        let fed = feeder
+            .join()
+            .map_err(|_| Error::other("upscaler feeder panicked"));
+        let status = self.control.wait(&mut upscaler);
+        let stderr = upscaler_stderr.join().unwrap_or_default();
+
+        let exit_failed = |status: &ExitStatus| match encoded {
+            Ok(()) => !status.success(),
+            // killed above, unless it exited with an error of its own first
+            Err(_) => status.code().is_some_and(|code| code != 0),
+        };
+        if upscaler_failed || matches!(&status, Ok(status) if exit_failed(status)) {
+            return Err(ReveError::Upscaler { stderr });
+        }
+        encoded?;
+        status?;
+        fed??;
+        Ok(())
+    }
+
+    /// Writes the upscaled frames of `upscaled` to the encoder until the upscaler closes it.
+    fn encode(
+        &self,
+        upscaled: &mut ChildStdout,
+        encoder_stdin: ChildStdin,
+        counter: &mut FrameCounter,
+    ) -> Result<(), ReveError> {
+        let mut encoder_stdin = BufWriter::new(encoder_stdin);
+        let mut frame = vec![0u8; self.upscaled_frame_size()];
+        while read_frame(upscaled, &mut frame)? {
+            encoder_stdin.write_all(&frame)?;
+            counter.tick();
+        }
+        encoder_stdin.flush()?;
+        Ok(())
+    }
+
+    fn run_window(
+        &self,
+        upscaler: &dyn Upscaler,
+        mut decoded: ChildStdout,
+        encoder_stdin: ChildStdin,
+        counter: &mut FrameCounter,
+    ) -> Result<(), ReveError> {
+        let input_dir = Path::new(self.window_dir).join("in");
+        let output_dir = Path::new(self.window_dir).join("out");
+        let input_dir_str = input_dir.to_string_lossy().to_string();
+        let output_dir_str = output_dir.to_string_lossy().to_string();
+
+        let mut encoder_stdin = BufWriter::new(encoder_stdin);
+        let mut frame = vec![0u8; self.frame_size()];
+        let mut done = false;
+        while !done {
+            // at most `window` frames of the segment are on disk at any time
+            let _ = fs::remove_dir_all(self.window_dir);
+            fs::create_dir_all(&input_dir)?;
+            fs::create_dir_all(&output_dir)?;
+
+            let mut frames = 0;
+            while frames < self.window {
+                if !read_frame(&mut decoded, &mut frame)? {
+                    done = true;
+                    break;
+                }
+                frames += 1;
+                write_png(
+                    &input_dir.join(format!("frame{:08}.png", frames)),
+                    self.width,
+                    self.height,
+                    &frame,
+                )?;
+            }
+            if frames == 0 {
+                break;
+            }
+
+            let mut child = self.control.spawn(
+                upscaler
+                    .command(&input_dir_str, &output_dir_str, self.scale, self.model)
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::piped()),
+            )?;
+            let stderr = child.stderr.take().ok_or_else(no_pipe)?;
+            let mut tail = StderrTail::default();
+            BufReader::new(stderr)
+                .lines()
+                .map_while(Result::ok)
+                .for_each(|line| tail.push(&line));
+            tail.check(self.control.wait(&mut child)?, |stderr| {
+                ReveError::Upscaler { stderr }
+            })?;
+
+            for index in 1..=frames {
+                let upscaled = read_png(&output_dir.join(format!("frame{:08}.png", index)))?;
+                if upscaled.len() != self.upscaled_frame_size() {
+                    return Err(ReveError::Upscaler {
+                        stderr: String::from("upscaled frame has an unexpected size"),
+                    });
+                }
+                encoder_stdin.write_all(&upscaled)?;
+                counter.tick();
+            }
+        }
+        encoder_stdin.flush()?;
+        let _ = fs::remove_dir_all(self.window_dir);
+        Ok(())
+    }
+}
+
+fn no_pipe() -> Error {
+    Error::other("Could not capture standard output.")
+}
+
+/// Reads a child's stderr to the end on another thread so it can't fill up and block the child.
+fn drain(stderr: Option<ChildStderr>) -> JoinHandle<String> {
+    thread::spawn(move || {
+        let mut output = String::new();
+        if let Some(mut stderr) = stderr {
+            let _ = stderr.read_to_string(&mut output);
+        }
+        output
+    })
+}
+
+/// Fills `frame` from `reader`, returning false on a clean end of stream. A stream ending
+/// inside a frame is an `UnexpectedEof` error.
+pub fn read_frame(reader: &mut impl Read, frame: &mut [u8]) -> Result<bool, Error> {
+    let mut filled = 0;
+    while filled < frame.len() {
+        match reader.read(&mut frame[filled..])? {
+            0 if filled == 0 => return Ok(false),
+            0 => return Err(Error::new(ErrorKind::UnexpectedEof, "truncated frame")),
+            n => filled += n,
+        }
+    }
+    Ok(true)
+}
+
+fn write_png(path: &Path, width: u32, height: u32, rgb: &[u8]) -> Result<(), Error> {
+    let file = BufWriter::new(fs::File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_compression(png::Compression::Fast);
+    let mut writer = encoder.write_header().map_err(Error::other)?;
+    writer.write_image_data(rgb).map_err(Error::other)
+}
+
+/// Reads a PNG written by the upscaler back as rgb24, whatever its color type and depth.
+pub fn read_png(path: &Path) -> Result<Vec<u8>, Error> {
+    let mut decoder = png::Decoder::new(fs::File::open(path)?);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder
+        .read_info()
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut buffer)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    buffer.truncate(info.buffer_size());
+
+    match info.color_type {
+        png::ColorType::Rgb => Ok(buffer),
+        png::ColorType::Rgba => Ok(buffer
+            .chunks_exact(4)
+            .flat_map(|p| [p[0], p[1], p[2]])
+            .collect()),
+        png::ColorType::Grayscale => Ok(buffer.iter().flat_map(|&g| [g, g, g]).collect()),
+        png::ColorType::GrayscaleAlpha => Ok(buffer
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0]])
+            .collect()),
+        png::ColorType::Indexed => Err(Error::new(
+            ErrorKind::InvalidData,
+            "indexed png output is not supported",
+        )),
+    }
+}
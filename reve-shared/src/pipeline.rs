@@ -0,0 +1,705 @@
+use crate::{
+    auto_segment_size, check_space, copy_streams, copy_streams_no_bin_data, count_frames,
+    export_frames, file_name, get_bin_data, get_display_aspect_ratio, get_encoder_profile,
+    get_frame_count, get_frame_count_tag, get_frame_rate, get_upscaler, get_video_size,
+    index_frames, merge_video_parts, merge_video_parts_dar, parse_frame_rate, plan_segments,
+    probe_media, read_timestamps, upscale_frames, write_ffconcat, Args, ExtractMode, JobControl,
+    NoProgress, PipeSegment, ProgressEvent, ProgressSink, ReveError, SegmentMerge, SpaceEstimate,
+    Workspace,
+};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Error;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Everything needed to upscale one video, as plain values.
+///
+/// ```no_run
+/// # use reve_shared::*;
+/// let spec = JobSpec::new("/videos/episode 01.mkv")
+///     .output("/videos/episode 01.x265.mkv")
+///     .scale(2)
+///     .encoder("libx265")
+///     .crf(15)
+///     .segment_size(1000);
+/// Pipeline::new(spec).run()?;
+/// # Ok::<(), ReveError>(())
+/// ```
+///
+/// The command line builds one from `Args` with `JobSpec::from(&args)`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct JobSpec {
+    pub input: String,
+    /// None writes next to the input, named after the encoder and format
+    pub output: Option<String>,
+    pub model: String,
+    pub scale: u8,
+    /// upscaler backend or command template, see `get_upscaler`
+    pub upscaler: String,
+    pub upscaler_progress: String,
+    pub encoder: String,
+    /// container of the output, used when `output` isn't set
+    pub format: String,
+    pub crf: u8,
+    pub preset: String,
+    pub encoder_params: String,
+    /// frames per segment, 0 picks the largest that fits the workspace (`--parts auto`)
+    pub segment_size: u32,
+    pub extract: ExtractMode,
+    pub pipe: bool,
+    pub pipe_window: u32,
+    pub workdir: Option<String>,
+}
+
+impl JobSpec {
+    /// Upscales `input` with the same defaults as the command line.
+    pub fn new(input: &str) -> JobSpec {
+        JobSpec {
+            input: input.to_string(),
+            output: None,
+            model: String::from("realesr-animevideov3"),
+            scale: 2,
+            upscaler: String::from("realesrgan"),
+            upscaler_progress: String::from("done"),
+            encoder: String::from("libx265"),
+            format: String::from("mp4"),
+            crf: 15,
+            preset: String::from("slow"),
+            encoder_params: String::from("psy-rd=2:aq-strength=1:deblock=0,0:bframes=8"),
+            segment_size: 1000,
+            extract: ExtractMode::Exact,
+            pipe: false,
+            pipe_window: 16,
+            workdir: None,
+        }
+    }
+
+    pub fn output(mut self, output: &str) -> JobSpec {
+        self.output = Some(output.to_string());
+        self
+    }
+
+    pub fn model(mut self, model: &str) -> JobSpec {
+        self.model = model.to_string();
+        self
+    }
+
+    pub fn scale(mut self, scale: u8) -> JobSpec {
+        self.scale = scale;
+        self
+    }
+
+    pub fn upscaler(mut self, upscaler: &str, progress: &str) -> JobSpec {
+        self.upscaler = upscaler.to_string();
+        self.upscaler_progress = progress.to_string();
+        self
+    }
+
+    pub fn encoder(mut self, encoder: &str) -> JobSpec {
+        self.encoder = encoder.to_string();
+        self
+    }
+
+    pub fn format(mut self, format: &str) -> JobSpec {
+        self.format = format.to_string();
+        self
+    }
+
+    pub fn crf(mut self, crf: u8) -> JobSpec {
+        self.crf = crf;
+        self
+    }
+
+    pub fn preset(mut self, preset: &str) -> JobSpec {
+        self.preset = preset.to_string();
+        self
+    }
+
+    pub fn encoder_params(mut self, params: &str) -> JobSpec {
+        self.encoder_params = params.to_string();
+        self
+    }
+
+    pub fn segment_size(mut self, segment_size: u32) -> JobSpec {
+        self.segment_size = segment_size;
+        self
+    }
+
+    pub fn extract(mut self, extract: ExtractMode) -> JobSpec {
+        self.extract = extract;
+        self
+    }
+
+    /// Streams frames through pipes instead of PNG folders, keeping `window` frames on disk
+    /// for upscalers that can't stream.
+    pub fn pipe(mut self, pipe: bool, window: u32) -> JobSpec {
+        self.pipe = pipe;
+        self.pipe_window = window;
+        self
+    }
+
+    pub fn workdir(mut self, workdir: &str) -> JobSpec {
+        self.workdir = Some(workdir.to_string());
+        self
+    }
+
+    /// True if the workspace of `previous` can be resumed by this spec: the same file, model
+    /// and scale, cut into the same segments. `--parts auto` takes the segment size of
+    /// `previous`.
+    pub fn resumes(&self, previous: &JobSpec) -> bool {
+        previous.input == self.input
+            && previous.model == self.model
+            && previous.scale == self.scale
+            && previous.extract == self.extract
+            && (self.segment_size == 0 || previous.segment_size == self.segment_size)
+    }
+
+    /// `output`, or `<input folder>/<input name>.<encoder>.<format>`.
+    pub fn output_path(&self) -> String {
+        if let Some(output) = &self.output {
+            return output.clone();
+        }
+        let path = Path::new(&self.input);
+        let name = format!(
+            "{}.{}.{}",
+            path.file_stem().unwrap_or_default().to_string_lossy(),
+            self.encoder,
+            self.format
+        );
+        path.with_file_name(name).display().to_string()
+    }
+}
+
+impl From<&Args> for JobSpec {
+    fn from(args: &Args) -> JobSpec {
+        JobSpec {
+            input: args.input().to_string(),
+            output: args.outputpath.clone(),
+            model: args.model.clone(),
+            scale: args.scale,
+            upscaler: args.upscaler.clone(),
+            upscaler_progress: args.upscaler_progress.clone(),
+            encoder: args.codec.clone(),
+            format: args.format.clone(),
+            crf: args.crf,
+            preset: args.preset.clone(),
+            encoder_params: args.x265params.clone(),
+            segment_size: args.segmentsize,
+            extract: args.extract,
+            pipe: args.pipe,
+            pipe_window: args.pipe_window,
+            workdir: args.workdir.clone(),
+        }
+    }
+}
+
+/// Where a file is in a batch, for the progress display.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Batch {
+    /// 1 based number of the file
+    pub file: u32,
+    pub files: u32,
+    /// frames of the files before this one
+    pub frames_before: u64,
+    /// frames of the whole batch, 0 for just this file
+    pub frames_total: u64,
+}
+
+/// Runs a JobSpec: export, upscale and merge of every segment, then the stream copy into the
+/// output. An interrupted run resumes from the segments left in the workspace.
+pub struct Pipeline {
+    spec: JobSpec,
+    workspace: Workspace,
+    batch: Batch,
+    progress: Arc<dyn ProgressSink>,
+    control: JobControl,
+}
+
+impl Pipeline {
+    pub fn new(spec: JobSpec) -> Pipeline {
+        Pipeline {
+            workspace: Workspace::resolve(spec.workdir.as_deref()),
+            spec,
+            batch: Batch {
+                file: 1,
+                files: 1,
+                ..Default::default()
+            },
+            progress: Arc::new(NoProgress),
+            control: JobControl::new(),
+        }
+    }
+
+    pub fn batch(mut self, batch: Batch) -> Pipeline {
+        self.batch = batch;
+        self
+    }
+
+    /// Where progress events go, nowhere by default.
+    pub fn progress(mut self, progress: Arc<dyn ProgressSink>) -> Pipeline {
+        self.progress = progress;
+        self
+    }
+
+    /// Lets another thread cancel, pause or resume the run, keep a clone of `control` for that.
+    pub fn control(mut self, control: JobControl) -> Pipeline {
+        self.control = control;
+        self
+    }
+
+    pub fn spec(&self) -> &JobSpec {
+        &self.spec
+    }
+
+    pub fn workspace(&self) -> &Workspace {
+        &self.workspace
+    }
+
+    /// Runs the upscale, an error is also sent to the progress sink before it is returned.
+    pub fn run(&self) -> Result<(), ReveError> {
+        let result = self.run_file();
+        if let Err(e) = &result {
+            self.progress.event(ProgressEvent::Error {
+                message: e.to_string(),
+            });
+        }
+        result
+    }
+
+    fn info(&self, message: impl ToString) {
+        self.progress.event(ProgressEvent::Info {
+            message: message.to_string(),
+        });
+    }
+
+    fn run_file(&self) -> Result<(), ReveError> {
+        let work_now = Instant::now();
+        let spec = &self.spec;
+        let workspace = &self.workspace;
+        let _lock = workspace.lock()?;
+        let output_path = spec.output_path();
+
+        let mkv = Some(OsStr::new("mkv"));
+        if Path::new(&spec.input).extension() == mkv && Path::new(&output_path).extension() != mkv {
+            return Err(ReveError::InvalidInput(format!(
+                "{}: mkv file can only be exported as mkv file",
+                output_path
+            )));
+        }
+
+        let args_path = workspace.args_file();
+        let previous: Option<JobSpec> = fs::read_to_string(&args_path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok());
+        let resume = matches!(&previous, Some(previous) if spec.resumes(previous));
+
+        let (total_frame_count, _) = count_frames(&spec.input)?;
+        let (width, height) = get_video_size(&spec.input)?;
+        let source_size = fs::metadata(&spec.input)?.len();
+        let mut spec = spec.clone();
+        if spec.segment_size == 0 {
+            spec.segment_size = match &previous {
+                // the parts in the workspace were cut with the size picked back then
+                Some(previous) if resume => previous.segment_size,
+                _ => {
+                    let estimate =
+                        SpaceEstimate::new(&spec, width, height, total_frame_count, source_size);
+                    auto_segment_size(
+                        workspace,
+                        &output_path,
+                        &estimate,
+                        total_frame_count,
+                        spec.pipe,
+                    )?
+                }
+            };
+            self.info(format!("segments of {} frames", spec.segment_size));
+        }
+        let spec = &spec;
+        let estimate = SpaceEstimate::new(spec, width, height, total_frame_count, source_size);
+        check_space(workspace, &output_path, &estimate, spec.pipe)?;
+
+        workspace.create()?;
+        let dar = get_display_aspect_ratio(&spec.input)?;
+        let temp_video_path = workspace.temp_video(&spec.format).display().to_string();
+        let txt_list_path = workspace.parts_list().display().to_string();
+
+        if resume {
+            workspace.rebuild(true)?;
+            self.info(format!("resuming upscale of {}", file_name(&spec.input)));
+        } else {
+            // Remove and start new, args.temp keeps the segment size picked by --parts auto
+            workspace.rebuild(false)?;
+            let _ = fs::remove_file(&txt_list_path);
+            let _ = fs::remove_file(&temp_video_path);
+            fs::write(
+                &args_path,
+                serde_json::to_string(spec).map_err(Error::from)?,
+            )?;
+        }
+
+        let original_frame_rate = get_frame_rate(&spec.input)?;
+        let upscaler = get_upscaler(&spec.upscaler, &spec.upscaler_progress)?;
+        if !spec.pipe && !upscaler.supports_folders() {
+            return Err(ReveError::InvalidInput(format!(
+                "{} can only stream frames, use --pipe",
+                spec.upscaler
+            )));
+        }
+
+        let frame_rate = parse_frame_rate(&spec.input, &original_frame_rate)?;
+
+        // variable frame rate sources keep the timestamps of every exported frame
+        let media = probe_media(&spec.input).ok();
+        let vfr = media.as_ref().is_some_and(|media| media.vfr);
+        if vfr && spec.pipe {
+            self.info(format!(
+                "variable frame rate source, --pipe re-times it at {} fps",
+                frame_rate
+            ));
+        }
+
+        // exact extraction seeks with the frame index, and the last frame of each segment of a
+        // variable frame rate source lasts until the first frame of the next one
+        let seek = spec.extract == ExtractMode::Exact && total_frame_count > spec.segment_size;
+        let index = if seek || (vfr && !spec.pipe) {
+            match index_frames(&spec.input) {
+                Ok(index) => Some(index),
+                Err(e) => {
+                    self.info(format!(
+                        "could not index the frames, segments are decoded from the start: {}",
+                        e
+                    ));
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        // the index has every frame the decoder outputs, nb_frames or a tag can be off
+        let frame_count = index
+            .as_ref()
+            .map_or(total_frame_count, |index| index.pts.len() as u32);
+        let total_frames_count = match self.batch.frames_total {
+            0 => u64::from(frame_count),
+            frames_total => frames_total,
+        };
+
+        // Calculate steps
+        let mut segments = plan_segments(frame_count, spec.segment_size);
+        let parts_num = segments.len() as i32;
+        let last_part_size = segments.last().map(|s| s.size).unwrap_or(0);
+        if spec.extract == ExtractMode::Exact && segments.len() > 1 {
+            if let Some(index) = &index {
+                index.locate(&mut segments);
+            }
+        }
+        let end = media.map_or(0.0, |media| media.end());
+
+        {
+            let mut unprocessed_indexes = Vec::new();
+            for segment in segments {
+                let n = workspace.video_part(segment.index, &spec.format);
+                let p = n.as_path();
+                if !p.exists() {
+                    unprocessed_indexes.push(segment);
+                } else {
+                    let mut c = get_frame_count(&p.display().to_string())?;
+                    if c == 0 {
+                        c = get_frame_count_tag(&p.display().to_string())?;
+                    }
+                    if c != segment.size {
+                        fs::remove_file(p)?;
+                        self.info(format!(
+                            "removed invalid segment file [{}] with {} frame size",
+                            segment.index, c
+                        ));
+                        unprocessed_indexes.push(segment);
+                    }
+                }
+            }
+
+            let segments_done = parts_num as usize - unprocessed_indexes.len();
+            self.progress.event(ProgressEvent::FileStarted {
+                input: spec.input.clone(),
+                output: output_path.clone(),
+                encoder: spec.encoder.clone(),
+                file: self.batch.file,
+                files: self.batch.files,
+                segments: parts_num as u32,
+                segments_done: segments_done as u32,
+                last_segment_size: last_part_size,
+                frames_total: total_frames_count,
+                frames_done: self.batch.frames_before
+                    + segments_done as u64 * spec.segment_size as u64,
+            });
+
+            let profile = get_encoder_profile(&spec.encoder).ok_or_else(|| {
+                ReveError::InvalidInput(format!("no encoder profile for {}", spec.encoder))
+            })?;
+            if spec.pipe {
+                let window_dir = workspace.window_dir().display().to_string();
+
+                for segment in &unprocessed_indexes {
+                    self.control.checkpoint()?;
+                    let part = workspace
+                        .video_part(segment.index, &spec.format)
+                        .display()
+                        .to_string();
+
+                    PipeSegment {
+                        input_path: &spec.input,
+                        output_path: &part,
+                        segment,
+                        extract: spec.extract,
+                        width,
+                        height,
+                        frame_rate: &original_frame_rate,
+                        scale: spec.scale,
+                        model: &spec.model,
+                        profile: &profile,
+                        crf: spec.crf,
+                        preset: &spec.preset,
+                        params: &spec.encoder_params,
+                        window_dir: &window_dir,
+                        window: spec.pipe_window as usize,
+                        control: &self.control,
+                    }
+                    .run(upscaler.as_ref(), &*self.progress)?;
+                }
+                // every segment is in video_parts, nothing left for the PNG pipeline below
+                unprocessed_indexes.clear();
+            }
+
+            // Initial export
+            if !unprocessed_indexes.is_empty() {
+                let index = unprocessed_indexes[0].index;
+                let frames_dir = workspace.tmp_frames_dir(index);
+                let frames = frames_dir.join("frame%08d.png").display().to_string();
+                fs::create_dir(&frames_dir)?;
+                let timestamps = workspace.timestamps(index).display().to_string();
+
+                // the workspace defaults to /dev/shm on Linux, Windows doesn't really have
+                // something native like a ramdisk sadly (pass --workdir to use one)
+                export_frames(
+                    &spec.input,
+                    &frames,
+                    &unprocessed_indexes[0],
+                    spec.extract,
+                    frame_rate,
+                    vfr.then_some(&timestamps),
+                    &*self.progress,
+                    &self.control,
+                )?;
+            }
+
+            // the export of the next segment and the merge of the previous one run on their own
+            // threads while a segment is upscaled
+            let mut export_handle = None;
+            let mut merge_handle = None;
+            let result = (|| -> Result<(), ReveError> {
+                for _ in 0..unprocessed_indexes.len() {
+                    let segment = &unprocessed_indexes[0];
+                    join(export_handle.take())?;
+                    self.control.checkpoint()?;
+                    if let Some(next) = unprocessed_indexes.get(1) {
+                        let input = spec.input.clone();
+                        let frames_dir = workspace.tmp_frames_dir(next.index);
+                        let frames = frames_dir.join("frame%08d.png").display().to_string();
+                        let next = next.clone();
+                        let extract = spec.extract;
+                        let timestamps = workspace.timestamps(next.index).display().to_string();
+                        let progress = self.progress.clone();
+                        let control = self.control.clone();
+
+                        export_handle = Some(thread::spawn(move || {
+                            fs::create_dir(&frames_dir)?;
+                            export_frames(
+                                &input,
+                                &frames,
+                                &next,
+                                extract,
+                                frame_rate,
+                                vfr.then_some(&timestamps),
+                                &*progress,
+                                &control,
+                            )
+                        }));
+                    }
+
+                    let frames_dir = workspace
+                        .tmp_frames_dir(segment.index)
+                        .display()
+                        .to_string();
+                    let upscaled_dir = workspace
+                        .out_frames_dir(segment.index)
+                        .display()
+                        .to_string();
+
+                    fs::create_dir(&upscaled_dir)?;
+
+                    upscale_frames(
+                        upscaler.as_ref(),
+                        &frames_dir,
+                        &upscaled_dir,
+                        spec.scale,
+                        &spec.model,
+                        segment,
+                        &*self.progress,
+                        &self.control,
+                    )?;
+
+                    join(merge_handle.take())?;
+
+                    let profile = profile.clone();
+                    let upscaled = workspace
+                        .out_frames_dir(segment.index)
+                        .join("frame%08d.png")
+                        .display()
+                        .to_string();
+                    let part = workspace
+                        .video_part(segment.index, &spec.format)
+                        .display()
+                        .to_string();
+                    let original_frame_rate = original_frame_rate.clone();
+                    let timestamps = workspace.timestamps(segment.index).display().to_string();
+                    let frames_list = workspace.frames_list(segment.index).display().to_string();
+                    let crf = spec.crf;
+                    let preset = spec.preset.clone();
+                    let encoder_params = spec.encoder_params.clone();
+                    let segment = segment.clone();
+                    let last_duration = index
+                        .as_ref()
+                        .and_then(|index| index.last_frame_duration(&segment, end))
+                        .unwrap_or(1.0 / frame_rate as f64);
+                    let progress = self.progress.clone();
+                    let control = self.control.clone();
+
+                    merge_handle = Some(thread::spawn(move || {
+                        fs::remove_dir_all(&frames_dir)?;
+                        if vfr {
+                            let timestamps = read_timestamps(&timestamps)?;
+                            write_ffconcat(
+                                &upscaled_dir,
+                                &timestamps,
+                                last_duration,
+                                &frames_list,
+                            )?;
+                        }
+                        SegmentMerge {
+                            input_path: &upscaled,
+                            frames_list: vfr.then_some(&frames_list),
+                            output_path: &part,
+                            frame_rate: &original_frame_rate,
+                            profile: &profile,
+                            crf,
+                            preset: &preset,
+                            params: &encoder_params,
+                            segment: &segment,
+                            control: &control,
+                        }
+                        .run(&*progress)?;
+                        fs::remove_dir_all(&upscaled_dir)?;
+                        Ok(())
+                    }));
+
+                    unprocessed_indexes.remove(0);
+                }
+                Ok(())
+            })();
+            // threads still running after a failed step finish before the error is returned
+            let exported = join(export_handle.take());
+            let merged = join(merge_handle.take());
+            result?;
+            exported?;
+            merged?;
+        }
+
+        // Merge video parts
+        let choosen_extension = &spec.format;
+        let mut f_content = format!(
+            "file '{}'",
+            workspace.video_part_entry(0, choosen_extension)
+        );
+
+        for part_number in 1..parts_num {
+            let video_part_path = workspace.video_part_entry(part_number as u32, choosen_extension);
+            f_content = format!("{}\nfile '{}'", f_content, video_part_path);
+        }
+
+        fs::write(&txt_list_path, f_content)?;
+
+        self.info("merging video segments");
+        {
+            let mut count = 0;
+            let p = Path::new(&temp_video_path);
+            loop {
+                thread::sleep(Duration::from_secs(1));
+                if count == 5 {
+                    return Err(ReveError::ffmpeg("merge segments", ""));
+                } else if p.exists() {
+                    if fs::metadata(p)?.len() == 0 {
+                        count += 1;
+                    } else {
+                        break;
+                    }
+                } else {
+                    if dar == "0" || dar == "N/A" {
+                        merge_video_parts(
+                            &txt_list_path.to_string(),
+                            &temp_video_path.to_string(),
+                        )?;
+                    } else {
+                        merge_video_parts_dar(
+                            &txt_list_path.to_string(),
+                            &temp_video_path.to_string(),
+                            &dar,
+                        )?;
+                    }
+                    count += 1;
+                }
+            }
+        }
+
+        //Check if there is invalid bin data in the input file
+        let bin_data = get_bin_data(&spec.input)?;
+        if !bin_data.is_empty() {
+            self.info(format!(
+                "invalid data at index: {}, skipping this one",
+                bin_data
+            ));
+            self.info("copying streams");
+            copy_streams_no_bin_data(&temp_video_path.to_string(), &spec.input, &output_path)?;
+        } else {
+            self.info("copying streams");
+            copy_streams(&temp_video_path.to_string(), &spec.input, &output_path)?;
+        }
+
+        //Check if file has been copied successfully to output path
+        let p = Path::new(&output_path);
+        if !p.exists() || fs::metadata(p)?.len() == 0 {
+            return Err(ReveError::ffmpeg("copy streams", ""));
+        }
+
+        self.progress.event(ProgressEvent::FileFinished {
+            input: spec.input.clone(),
+            output: output_path,
+            seconds: work_now.elapsed().as_secs(),
+        });
+        Ok(())
+    }
+}
+
+/// Waits for a pipeline thread, if one was started, and returns its result.
+fn join(handle: Option<thread::JoinHandle<Result<(), ReveError>>>) -> Result<(), ReveError> {
+    match handle {
+        Some(handle) => handle
+            .join()
+            .unwrap_or_else(|_| Err(Error::other("worker thread panicked").into())),
+        None => Ok(()),
+    }
+}
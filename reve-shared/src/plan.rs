@@ -0,0 +1,235 @@
+use crate::{
+    auto_segment_size, batch_output_path, check_space, count_frames, find_video, fingerprint_file,
+    get_ffprobe_output, plan_segments, walk_files, Args, FrameSource, JobSpec, ReveError,
+    SpaceEstimate, Workspace,
+};
+use rusqlite::{Connection, OpenFlags};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+/// What an upscale of one file would do, as `--dry-run` shows it.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct FilePlan {
+    pub input: String,
+    pub output: String,
+    /// why the file wouldn't be upscaled
+    pub skip: Option<String>,
+    pub frames: u32,
+    pub frame_source: FrameSource,
+    /// frames per segment, the one `--parts auto` picks if it is used
+    pub segment_size: u32,
+    pub segments: u32,
+    pub last_segment_size: u32,
+    pub temp_space: SpaceEstimate,
+}
+
+impl FilePlan {
+    /// Plan of a file that can't be read, `reason` says why.
+    fn unreadable(input: &str, output: &str, reason: String) -> FilePlan {
+        FilePlan {
+            input: input.to_string(),
+            output: output.to_string(),
+            skip: Some(reason),
+            frames: 0,
+            frame_source: FrameSource::Duration,
+            segment_size: 0,
+            segments: 0,
+            last_segment_size: 0,
+            temp_space: SpaceEstimate::default(),
+        }
+    }
+}
+
+/// Plans the upscale of `spec.input` to `output`, from its ffprobe output and frame count.
+/// `max_height` is the `--resolution` limit.
+pub fn plan_file(
+    spec: &JobSpec,
+    probe: &Value,
+    output: &str,
+    max_height: i64,
+) -> Result<FilePlan, ReveError> {
+    let stream = &probe["streams"][0];
+    let width = stream["width"].as_u64().unwrap_or(0) as u32;
+    let height = stream["height"].as_u64().unwrap_or(0) as u32;
+    let source_size = fs::metadata(&spec.input)?.len();
+    let (frames, frame_source) = count_frames(&spec.input)?;
+
+    let mkv = Some(OsStr::new("mkv"));
+    let mut skip = if height as i64 > max_height {
+        Some(format!("taller than {}p", max_height))
+    } else if Path::new(output).exists() {
+        Some(String::from("output already exists"))
+    } else if Path::new(&spec.input).extension() == mkv && Path::new(output).extension() != mkv {
+        Some(String::from("mkv file can only be exported as mkv file"))
+    } else {
+        None
+    };
+
+    let mut spec = spec.clone();
+    if spec.segment_size == 0 {
+        let workspace = Workspace::resolve(spec.workdir.as_deref());
+        let estimate = SpaceEstimate::new(&spec, width, height, frames, source_size);
+        match auto_segment_size(&workspace, output, &estimate, frames, spec.pipe) {
+            Ok(segment_size) => spec.segment_size = segment_size,
+            Err(e) => skip = skip.or(Some(e.to_string())),
+        }
+    }
+    // no segments when --parts auto found no room for one
+    let segments = match spec.segment_size {
+        0 => Vec::new(),
+        segment_size => plan_segments(frames, segment_size),
+    };
+
+    Ok(FilePlan {
+        input: spec.input.clone(),
+        output: output.to_string(),
+        skip,
+        frames,
+        frame_source,
+        segment_size: spec.segment_size,
+        segments: segments.len() as u32,
+        last_segment_size: segments.last().map_or(0, |s| s.size),
+        temp_space: SpaceEstimate::new(&spec, width, height, frames, source_size),
+    })
+}
+
+/// Plans the upscale of every file `args` would upscale, the file or the videos of the folder.
+///
+/// Only ffprobe runs: nothing is exported, encoded or upscaled. The videos of a folder are
+/// looked up in reve.db, opened read-only, to skip those the library already upscaled.
+pub fn plan_files(args: &Args) -> Result<Vec<FilePlan>, ReveError> {
+    plan_files_with_db(args, Path::new("reve.db"))
+}
+
+/// `plan_files` with the library of `db_path`, a missing database is an empty library.
+pub fn plan_files_with_db(args: &Args, db_path: &Path) -> Result<Vec<FilePlan>, ReveError> {
+    let resolution = args.resolution.as_deref().unwrap_or("480");
+    let max_height = resolution
+        .parse::<i64>()
+        .map_err(|_| ReveError::InvalidInput(format!("invalid resolution {}", resolution)))?;
+
+    if !Path::new(args.input()).is_dir() {
+        let spec = JobSpec::from(args);
+        let probe = get_ffprobe_output(&spec.input)?;
+        return Ok(vec![plan_file(
+            &spec,
+            &probe,
+            &spec.output_path(),
+            max_height,
+        )?]);
+    }
+
+    let conn = if db_path.exists() {
+        Some(Connection::open_with_flags(
+            db_path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY,
+        )?)
+    } else {
+        None
+    };
+    let mut plans = Vec::new();
+    // add_to_db doesn't upscale a copy of a file twice
+    let mut fingerprints = HashMap::new();
+    for file in walk_files(args.input()) {
+        let mut spec = JobSpec::from(args);
+        spec.input = file.clone();
+        let output = batch_output_path(args, &file);
+        // a file that can't be probed is reported, the others are still planned
+        let planned = get_ffprobe_output(&file).and_then(|probe| {
+            let plan = plan_file(&spec, &probe, &output, max_height)?;
+            let duration = probe["format"]["duration"]
+                .as_str()
+                .and_then(|duration| duration.parse::<f64>().ok())
+                .unwrap_or(0.0);
+            Ok((plan, fingerprint_file(&file, duration)?))
+        });
+        let (mut plan, fingerprint) = match planned {
+            Ok(planned) => planned,
+            Err(e) => {
+                plans.push(FilePlan::unreadable(&file, &output, e.to_string()));
+                continue;
+            }
+        };
+        if let Some(of) = fingerprints.get(&fingerprint) {
+            plan.skip = Some(format!("copy of {}", of));
+        } else {
+            if plan.skip.is_none() {
+                if let Some(conn) = &conn {
+                    plan.skip = library_skip(conn, &file, &fingerprint)?;
+                }
+            }
+            fingerprints.insert(fingerprint, file);
+        }
+        plans.push(plan);
+    }
+    Ok(plans)
+}
+
+/// Why `add_to_db` wouldn't upscale `file` again, from what the library knows of it.
+fn library_skip(
+    conn: &Connection,
+    file: &str,
+    fingerprint: &str,
+) -> Result<Option<String>, ReveError> {
+    let Some(video) = find_video(conn, file, fingerprint)? else {
+        return Ok(None);
+    };
+    if video.filepath != file && Path::new(&video.filepath).exists() {
+        return Ok(Some(format!("known as {}", video.filepath)));
+    }
+    Ok(match video.status.as_str() {
+        "done" if video.filepath == file => Some(String::from("already upscaled")),
+        "done" => Some(format!("already upscaled as {}", video.filepath)),
+        "skipped" => Some(String::from("skipped in the library")),
+        _ => None,
+    })
+}
+
+/// `--dry-run`: prints the plan of every file and the totals.
+pub fn dry_run(args: &Args) -> Result<(), ReveError> {
+    let plans = plan_files(args)?;
+    let workspace = Workspace::resolve(args.workdir.as_deref());
+    for plan in &plans {
+        println!("{}", plan.input);
+        println!("  output:     {}", plan.output);
+        match &plan.skip {
+            Some(reason) => println!("  skipped:    {}", reason),
+            None => println!("  upscaled:   yes"),
+        }
+        println!("  frames:     {} ({})", plan.frames, plan.frame_source);
+        println!(
+            "  segments:   {} of {} frames (last one {} frames)",
+            plan.segments, plan.segment_size, plan.last_segment_size
+        );
+        println!(
+            "  temp space: {:.1} GB ({:.1} GB of frames, {:.1} GB of video parts)",
+            plan.temp_space.total() as f64 / 1e9,
+            plan.temp_space.frames as f64 / 1e9,
+            plan.temp_space.parts as f64 / 1e9
+        );
+        if plan.skip.is_none() {
+            if let Err(e) = check_space(&workspace, &plan.output, &plan.temp_space, args.pipe) {
+                println!("  space:      {}", e);
+            }
+        }
+    }
+
+    let upscaled: Vec<&FilePlan> = plans.iter().filter(|plan| plan.skip.is_none()).collect();
+    println!(
+        "{} of {} files would be upscaled, {} frames, up to {:.1} GB of temp space",
+        upscaled.len(),
+        plans.len(),
+        upscaled.iter().map(|plan| plan.frames as u64).sum::<u64>(),
+        upscaled
+            .iter()
+            .map(|plan| plan.temp_space.total())
+            .max()
+            .unwrap_or(0) as f64
+            / 1e9
+    );
+    Ok(())
+}
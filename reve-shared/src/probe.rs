@@ -0,0 +1,256 @@
+use crate::{error, ReveError, Seek, Segment};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::process::Command;
+
+/// Video stream properties read with ffprobe.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct MediaInfo {
+    pub width: u32,
+    pub height: u32,
+    /// `avg_frame_rate` as reported by ffprobe (e.g. `24000/1001`)
+    pub avg_frame_rate: String,
+    /// `r_frame_rate` as reported by ffprobe
+    pub r_frame_rate: String,
+    pub duration: f64,
+    /// pts the container starts at, in seconds
+    #[serde(default)]
+    pub start_time: f64,
+    /// true when frame durations vary, so the average frame rate can't describe the timing
+    pub vfr: bool,
+}
+
+impl MediaInfo {
+    /// Average frame rate as a number, 0 if ffprobe didn't report one.
+    pub fn frame_rate(&self) -> f64 {
+        parse_rate(&self.avg_frame_rate)
+    }
+
+    /// pts the container ends at, in seconds.
+    pub fn end(&self) -> f64 {
+        self.start_time + self.duration
+    }
+}
+
+/// Probes the first video stream of `input_path`.
+pub fn probe_media(input_path: &str) -> Result<MediaInfo, ReveError> {
+    let output = error::output(Command::new("ffprobe").args([
+        "-i",
+        input_path,
+        "-v",
+        "error",
+        "-select_streams",
+        "v:0",
+        "-show_entries",
+        "stream=width,height,avg_frame_rate,r_frame_rate:format=duration,start_time",
+        "-of",
+        "json",
+    ]))?;
+    if !output.status.success() {
+        return Err(ReveError::probe(
+            input_path,
+            String::from_utf8_lossy(&output.stderr),
+        ));
+    }
+    let values: Value =
+        serde_json::from_slice(&output.stdout).map_err(|e| ReveError::probe(input_path, e))?;
+    let stream = &values["streams"][0];
+
+    Ok(MediaInfo {
+        width: stream["width"].as_u64().unwrap_or(0) as u32,
+        height: stream["height"].as_u64().unwrap_or(0) as u32,
+        avg_frame_rate: stream["avg_frame_rate"]
+            .as_str()
+            .unwrap_or("0/0")
+            .to_string(),
+        r_frame_rate: stream["r_frame_rate"].as_str().unwrap_or("0/0").to_string(),
+        duration: values["format"]["duration"]
+            .as_str()
+            .and_then(|d| d.parse().ok())
+            .unwrap_or(0.0),
+        start_time: values["format"]["start_time"]
+            .as_str()
+            .and_then(|t| t.parse().ok())
+            .unwrap_or(0.0),
+        vfr: detect_vfr(input_path),
+    })
+}
+
+/// Presentation timestamps of the frames of a video stream, in order, read from its packets
+/// without decoding.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FrameIndex {
+    /// pts of every frame, in seconds
+    pub pts: Vec<f64>,
+    /// whether the frame at the same position is a keyframe
+    pub keyframes: Vec<bool>,
+}
+
+/// Indexes the frames of the first video stream of `input_path`.
+pub fn index_frames(input_path: &str) -> Result<FrameIndex, ReveError> {
+    let output = error::output(Command::new("ffprobe").args([
+        "-i",
+        input_path,
+        "-v",
+        "error",
+        "-select_streams",
+        "v:0",
+        "-show_entries",
+        "packet=pts_time,flags",
+        "-of",
+        "csv=p=0",
+    ]))?;
+    if !output.status.success() {
+        return Err(ReveError::probe(
+            input_path,
+            String::from_utf8_lossy(&output.stderr),
+        ));
+    }
+    FrameIndex::from_packets(&String::from_utf8_lossy(&output.stdout))
+        .map_err(|message| ReveError::probe(input_path, message))
+}
+
+impl FrameIndex {
+    /// Reads the `pts_time,flags` lines of ffprobe's packets. A packet without a pts is an
+    /// error: the frames after it couldn't be placed, and seeking by position would cut the
+    /// segments at the wrong frames.
+    pub fn from_packets(csv: &str) -> Result<FrameIndex, String> {
+        let mut frames: Vec<(f64, bool)> = Vec::new();
+        for line in csv.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let mut fields = line.split(',');
+            let pts = fields.next().unwrap_or("");
+            let pts = pts
+                .parse::<f64>()
+                .ok()
+                .filter(|pts| pts.is_finite())
+                .ok_or_else(|| format!("packet {} has no pts ({})", frames.len(), pts))?;
+            frames.push((pts, fields.next().unwrap_or("").starts_with('K')));
+        }
+        // packets come in decode order
+        frames.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(FrameIndex {
+            pts: frames.iter().map(|f| f.0).collect(),
+            keyframes: frames.iter().map(|f| f.1).collect(),
+        })
+    }
+
+    /// Sets where exact extraction of each of `segments` seeks to. Segments starting past the
+    /// indexed frames are left to be decoded from the start.
+    pub fn locate(&self, segments: &mut [Segment]) {
+        for segment in segments {
+            let start = segment.start as usize;
+            segment.seek = match self.pts.get(start) {
+                Some(&pts) if start == 0 => Some(Seek {
+                    keyframe: pts,
+                    after: pts - 1.0,
+                }),
+                Some(&pts) => {
+                    let keyframe = (0..=start).rev().find(|&i| self.keyframes[i]).unwrap_or(0);
+                    Some(Seek {
+                        keyframe: self.pts[keyframe],
+                        after: (self.pts[start - 1] + pts) / 2.0,
+                    })
+                }
+                None => None,
+            };
+        }
+    }
+
+    /// How long the last frame of `segment` lasts: until the first frame of the next segment,
+    /// or until `end`, the end of the container, for the last segment. None if the segment
+    /// isn't indexed.
+    pub fn last_frame_duration(&self, segment: &Segment, end: f64) -> Option<f64> {
+        let last = (segment.start + segment.size) as usize - 1;
+        let pts = *self.pts.get(last)?;
+        let until = self.pts.get(last + 1).copied().unwrap_or(end);
+        (until > pts).then_some(until - pts)
+    }
+}
+
+/// Looks at the packet timestamps of the first 500 frames and reports whether the frame
+/// durations vary by more than container timestamp rounding. False if they can't be read.
+pub fn detect_vfr(input_path: &str) -> bool {
+    let output = match Command::new("ffprobe")
+        .args([
+            "-i",
+            input_path,
+            "-v",
+            "error",
+            "-select_streams",
+            "v:0",
+            "-read_intervals",
+            "%+#500",
+            "-show_entries",
+            "packet=pts_time",
+            "-of",
+            "csv=p=0",
+        ])
+        .output()
+    {
+        Ok(output) => output,
+        Err(_) => return false,
+    };
+    let mut timestamps: Vec<f64> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.trim().trim_end_matches(',').parse().ok())
+        .collect();
+    is_vfr(&mut timestamps)
+}
+
+/// True if the gaps between the sorted `timestamps` differ from the median gap by more than 10%.
+pub fn is_vfr(timestamps: &mut [f64]) -> bool {
+    timestamps.sort_by(|a, b| a.total_cmp(b));
+    let mut durations: Vec<f64> = timestamps.windows(2).map(|w| w[1] - w[0]).collect();
+    if durations.len() < 2 {
+        return false;
+    }
+    durations.sort_by(|a, b| a.total_cmp(b));
+    let median = durations[durations.len() / 2];
+    // mkv stores milliseconds, so 23.976 fps alternates between 41 and 42 ms
+    let tolerance = (median * 0.1).max(0.0015);
+    durations.iter().any(|d| (d - median).abs() > tolerance)
+}
+
+fn parse_rate(rate: &str) -> f64 {
+    let mut parts = rate.split('/');
+    let frames: f64 = parts.next().and_then(|f| f.parse().ok()).unwrap_or(0.0);
+    let seconds: f64 = parts.next().and_then(|s| s.parse().ok()).unwrap_or(1.0);
+    if seconds == 0.0 {
+        0.0
+    } else {
+        frames / seconds
+    }
+}
+
+/// Writes an ffconcat list playing the frames of `frames_dir` (frame%08d.png, from 1), one per
+/// source timestamp, each lasting until the next timestamp. The last one gets `last_duration`.
+pub fn write_ffconcat(
+    frames_dir: &str,
+    timestamps: &[f64],
+    last_duration: f64,
+    list_path: &str,
+) -> Result<(), ReveError> {
+    let mut content = String::from("ffconcat version 1.0\n");
+    for (i, pts) in timestamps.iter().enumerate() {
+        let duration = match timestamps.get(i + 1) {
+            Some(next) => next - pts,
+            None => last_duration,
+        };
+        let file = std::path::Path::new(frames_dir).join(format!("frame{:08}.png", i + 1));
+        content.push_str(&format!(
+            "file '{}'\nduration {:.6}\n",
+            file.display(),
+            duration
+        ));
+    }
+    std::fs::write(list_path, content)?;
+    Ok(())
+}
+
+/// Reads a timestamps file written during export (one `pts_time` per line).
+pub fn read_timestamps(path: &str) -> Result<Vec<f64>, ReveError> {
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| line.trim().parse().ok())
+        .collect())
+}
@@ -0,0 +1,131 @@
+use crate::{config_dir, ReveConfig, ReveError};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+const PROFILES_FILE: &str = "profiles.json";
+
+/// Named settings picked with `--profile`, with the keys of the config file.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Profile {
+    pub name: String,
+    /// shipped with reve, not read from profiles.json
+    pub builtin: bool,
+    pub settings: ReveConfig,
+}
+
+/// The built-in profiles and those of `profiles.json` in [`config_dir`], sorted by name.
+///
+/// The file maps profile names to settings, a profile of the file replaces the built-in one of
+/// the same name:
+///
+/// ```json
+/// { "anime-540p": { "model": "realesr-animevideov3", "crf": 16, "max-resolution": 540 } }
+/// ```
+pub struct Profiles {
+    profiles: Vec<Profile>,
+}
+
+impl Profiles {
+    pub fn builtin() -> Profiles {
+        let anime = ReveConfig {
+            model: Some(String::from("realesr-animevideov3")),
+            scale: Some(2),
+            encoder: Some(String::from("libx265")),
+            ..ReveConfig::default()
+        };
+        let profiles = vec![
+            Profile {
+                name: String::from("anime-archive"),
+                builtin: true,
+                settings: ReveConfig {
+                    format: Some(String::from("mkv")),
+                    crf: Some(15),
+                    preset: Some(String::from("slow")),
+                    encoder_params: Some(String::from(
+                        "psy-rd=2:aq-strength=1:deblock=0,0:bframes=8",
+                    )),
+                    ..anime.clone()
+                },
+            },
+            Profile {
+                name: String::from("anime-fast"),
+                builtin: true,
+                settings: ReveConfig {
+                    crf: Some(20),
+                    preset: Some(String::from("fast")),
+                    encoder_params: Some(String::from("bframes=8")),
+                    ..anime
+                },
+            },
+            Profile {
+                name: String::from("live-action"),
+                builtin: true,
+                settings: ReveConfig {
+                    model: Some(String::from("realesrgan-x4plus")),
+                    scale: Some(4),
+                    encoder: Some(String::from("libx265")),
+                    crf: Some(18),
+                    preset: Some(String::from("slow")),
+                    encoder_params: Some(String::from("aq-mode=3")),
+                    ..ReveConfig::default()
+                },
+            },
+        ];
+        Profiles { profiles }
+    }
+
+    pub fn path() -> PathBuf {
+        config_dir().join(PROFILES_FILE)
+    }
+
+    /// The built-in profiles, with those of the profiles file when there is one.
+    pub fn load() -> Result<Profiles, ReveError> {
+        Profiles::load_from(&Profiles::path())
+    }
+
+    pub fn load_from(path: &Path) -> Result<Profiles, ReveError> {
+        let mut profiles = Profiles::builtin();
+        let json = match fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(profiles),
+            Err(e) => return Err(e.into()),
+        };
+        let invalid =
+            |e: String| ReveError::InvalidInput(format!("profiles file {}: {}", path.display(), e));
+        let file: BTreeMap<String, ReveConfig> =
+            serde_json::from_str(&json).map_err(|e| invalid(e.to_string()))?;
+        for (name, settings) in file {
+            settings
+                .validate()
+                .map_err(|e| invalid(format!("{}: {}", name, e)))?;
+            profiles.profiles.retain(|profile| profile.name != name);
+            profiles.profiles.push(Profile {
+                name,
+                builtin: false,
+                settings,
+            });
+        }
+        profiles.profiles.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(profiles)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|profile| profile.name == name)
+    }
+
+    pub fn list(&self) -> &[Profile] {
+        &self.profiles
+    }
+
+    /// Names of the profiles, for error messages.
+    pub fn names(&self) -> String {
+        self.profiles
+            .iter()
+            .map(|profile| profile.name.as_str())
+            .collect::<Vec<&str>>()
+            .join("/")
+    }
+}
@@ -0,0 +1,313 @@
+use crate::{file_name, Segment};
+use clearscreen::clear;
+use colored::Colorize;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Step of the pipeline a segment is going through.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    Export,
+    Upscale,
+    Merge,
+}
+
+/// What the pipeline reports while it runs.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    /// a file starts, or resumes with `segments_done` of its segments already in the workspace
+    FileStarted {
+        input: String,
+        output: String,
+        encoder: String,
+        /// 1 based number of the file in the batch
+        file: u32,
+        files: u32,
+        segments: u32,
+        segments_done: u32,
+        last_segment_size: u32,
+        /// frames of the whole batch, and how many of them are already done
+        frames_total: u64,
+        frames_done: u64,
+    },
+    /// frames of a segment done so far in a stage, `fps` is the rate since the stage started
+    Frames {
+        stage: Stage,
+        segment: u32,
+        done: u32,
+        total: u32,
+        fps: f64,
+    },
+    SegmentExported {
+        segment: u32,
+        frames: u32,
+    },
+    FramesUpscaled {
+        segment: u32,
+        frames: u32,
+        fps: f64,
+    },
+    SegmentMerged {
+        segment: u32,
+        frames: u32,
+    },
+    /// status line, like resuming a workspace or copying streams
+    Info {
+        message: String,
+    },
+    FileFinished {
+        input: String,
+        output: String,
+        seconds: u64,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// Receives the events of a running pipeline.
+///
+/// Segments are exported, upscaled and merged on different threads at the same time, so
+/// events of neighbouring segments interleave.
+pub trait ProgressSink: Send + Sync {
+    fn event(&self, event: ProgressEvent);
+}
+
+impl<F: Fn(ProgressEvent) + Send + Sync> ProgressSink for F {
+    fn event(&self, event: ProgressEvent) {
+        self(event)
+    }
+}
+
+/// Drops every event.
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {
+    fn event(&self, _event: ProgressEvent) {}
+}
+
+/// Counts the frames of a segment in one stage and reports them with their rate.
+pub(crate) struct FrameCounter<'a> {
+    sink: &'a dyn ProgressSink,
+    stage: Stage,
+    segment: u32,
+    total: u32,
+    done: u32,
+    started: Instant,
+}
+
+impl<'a> FrameCounter<'a> {
+    pub(crate) fn new(sink: &'a dyn ProgressSink, stage: Stage, segment: &Segment) -> Self {
+        FrameCounter {
+            sink,
+            stage,
+            segment: segment.index,
+            total: segment.size,
+            done: 0,
+            started: Instant::now(),
+        }
+    }
+
+    pub(crate) fn tick(&mut self) {
+        self.done += 1;
+        self.sink.event(ProgressEvent::Frames {
+            stage: self.stage,
+            segment: self.segment,
+            done: self.done,
+            total: self.total,
+            fps: self.fps(),
+        });
+    }
+
+    pub(crate) fn done(&self) -> u32 {
+        self.done
+    }
+
+    pub(crate) fn fps(&self) -> f64 {
+        let seconds = self.started.elapsed().as_secs_f64();
+        if seconds > 0.0 {
+            self.done as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+const SEGMENTS_STYLE: &str = "[info][{elapsed_precise}] [{wide_bar:.green/white}] {pos:>7}/{len:7} processed segments       eta: {eta:<7}";
+const FRAMES_STYLE: &str = "[fram][{elapsed_precise}] [{wide_bar:.green/white}] {pos:>7}/{len:7} total frames             eta: {eta:<7}";
+const EXPORT_STYLE: &str = "[expo][{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos:>7}/{len:7} exporting segment        {per_sec:<12}";
+const UPSCALE_STYLE: &str = "[upsc][{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos:>7}/{len:7} upscaling segment        {per_sec:<12}";
+const MERGE_STYLE: &str = "[merg][{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos:>7}/{len:7} merging segment          {per_sec:<12}";
+
+/// Renders pipeline events as indicatif progress bars, the way `reve` shows them.
+pub struct TerminalProgress {
+    bars: Mutex<Option<TerminalBars>>,
+}
+
+struct TerminalBars {
+    multi: MultiProgress,
+    segments: ProgressBar,
+    frames: ProgressBar,
+    /// frames of the batch done before the segment being upscaled
+    frames_base: u64,
+    stages: HashMap<(Stage, u32), ProgressBar>,
+}
+
+impl TerminalProgress {
+    pub fn new() -> TerminalProgress {
+        TerminalProgress {
+            bars: Mutex::new(None),
+        }
+    }
+}
+
+impl Default for TerminalProgress {
+    fn default() -> Self {
+        TerminalProgress::new()
+    }
+}
+
+impl ProgressSink for TerminalProgress {
+    fn event(&self, event: ProgressEvent) {
+        let mut bars = self.bars.lock().unwrap();
+        match event {
+            ProgressEvent::FileStarted {
+                input,
+                encoder,
+                file,
+                files,
+                segments,
+                segments_done,
+                last_segment_size,
+                frames_total,
+                frames_done,
+                ..
+            } => {
+                let _ = clear();
+                let filename = file_name(&input);
+                println!(
+                    "{}",
+                    format!(
+                        "{}/{}, {}, total segments: {}, last segment size: {}, codec: {} (ctrl+c to exit)",
+                        file,
+                        files,
+                        filename.green(),
+                        segments,
+                        last_segment_size,
+                        encoder
+                    )
+                    .yellow()
+                );
+                let multi = MultiProgress::new();
+                let segments = multi.add(bar(segments as u64, SEGMENTS_STYLE));
+                segments.set_position(segments_done as u64);
+                let frames = multi.add(bar(frames_total, FRAMES_STYLE));
+                frames.set_position(frames_done);
+                *bars = Some(TerminalBars {
+                    multi,
+                    segments,
+                    frames,
+                    frames_base: frames_done,
+                    stages: HashMap::new(),
+                });
+            }
+            ProgressEvent::Frames {
+                stage,
+                segment,
+                done,
+                total,
+                ..
+            } => {
+                if let Some(bars) = bars.as_mut() {
+                    let multi = &bars.multi;
+                    let stage_bar = bars.stages.entry((stage, segment)).or_insert_with(|| {
+                        let style = match stage {
+                            Stage::Export => EXPORT_STYLE,
+                            Stage::Upscale => UPSCALE_STYLE,
+                            Stage::Merge => MERGE_STYLE,
+                        };
+                        multi.add(bar(total as u64, style))
+                    });
+                    stage_bar.set_position(done as u64);
+                    if stage == Stage::Upscale {
+                        bars.frames.set_position(bars.frames_base + done as u64);
+                    }
+                }
+            }
+            ProgressEvent::SegmentExported { segment, .. } => {
+                if let Some(bars) = bars.as_mut() {
+                    bars.finish(Stage::Export, segment);
+                }
+            }
+            ProgressEvent::FramesUpscaled {
+                segment, frames, ..
+            } => {
+                if let Some(bars) = bars.as_mut() {
+                    bars.finish(Stage::Upscale, segment);
+                    bars.frames_base += frames as u64;
+                    bars.frames.set_position(bars.frames_base);
+                }
+            }
+            ProgressEvent::SegmentMerged { segment, .. } => {
+                if let Some(bars) = bars.as_mut() {
+                    bars.finish(Stage::Merge, segment);
+                    bars.segments.inc(1);
+                }
+            }
+            ProgressEvent::Info { message } => match bars.as_ref() {
+                Some(bars) => {
+                    let _ = bars.multi.println(message);
+                }
+                None => println!("{}", message),
+            },
+            ProgressEvent::FileFinished {
+                input,
+                output,
+                seconds,
+            } => {
+                if let Some(bars) = bars.take() {
+                    let _ = bars.multi.clear();
+                }
+                let _ = clear();
+                println!(
+                    "done {:?} to {:?} in {}h:{}m:{}s",
+                    file_name(&input),
+                    file_name(&output),
+                    seconds / 3600,
+                    (seconds / 60) % 60,
+                    seconds % 60
+                );
+            }
+            ProgressEvent::Error { .. } => {
+                if let Some(bars) = bars.take() {
+                    let _ = bars.multi.clear();
+                }
+            }
+        }
+    }
+}
+
+impl TerminalBars {
+    fn finish(&mut self, stage: Stage, segment: u32) {
+        if let Some(stage_bar) = self.stages.remove(&(stage, segment)) {
+            stage_bar.finish_and_clear();
+            self.multi.remove(&stage_bar);
+        }
+    }
+}
+
+fn bar(len: u64, style: &str) -> ProgressBar {
+    let bar = ProgressBar::new(len);
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template(style)
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+    bar
+}
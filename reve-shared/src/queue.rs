@@ -0,0 +1,293 @@
+use crate::{Args, ReveError};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where a queued job is in its life.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Done => "done",
+            JobState::Failed => "failed",
+            JobState::Cancelled => "cancelled",
+        }
+    }
+
+    fn parse(s: &str) -> JobState {
+        match s {
+            "running" => JobState::Running,
+            "done" => JobState::Done,
+            "failed" => JobState::Failed,
+            "cancelled" => JobState::Cancelled,
+            _ => JobState::Queued,
+        }
+    }
+}
+
+impl fmt::Display for JobState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One file to upscale with its own settings, stored in the `jobs` table.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Job {
+    pub id: i64,
+    pub input: String,
+    /// None writes next to the input, named after the codec and format
+    pub output: Option<String>,
+    pub settings: Args,
+    /// higher runs first, jobs with the same priority run in the order they were added
+    pub priority: i64,
+    pub state: JobState,
+    pub attempts: u32,
+    pub error: Option<String>,
+    /// pid of the reve process running the job
+    pub owner: Option<u32>,
+    /// unix timestamps in seconds
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+const JOB_COLUMNS: &str =
+    "id, input, output, settings, priority, state, attempts, error, created_at, updated_at, owner";
+
+/// Adds a job for `input` with a copy of `settings` and returns its id.
+pub fn enqueue(
+    conn: &Connection,
+    input: &str,
+    output: Option<&str>,
+    settings: &Args,
+    priority: i64,
+) -> Result<i64, ReveError> {
+    let mut settings = settings.clone();
+    settings.inputpath = Some(input.to_string());
+    settings.outputpath = output.map(str::to_string);
+    let json = serde_json::to_string(&settings)
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+    let now = now();
+    conn.execute(
+        "INSERT INTO jobs (input, output, settings, priority, state, attempts, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, 'queued', 0, ?5, ?5)",
+        params![input, output, json, priority, now],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Takes the queued job with the highest priority and marks it running, owned by this process.
+pub fn dequeue_next(conn: &mut Connection) -> Result<Option<Job>, ReveError> {
+    dequeue(conn, "")
+}
+
+/// `dequeue_next` among the jobs `ids`, the other queued jobs are left to other runners.
+pub fn dequeue_next_of(conn: &mut Connection, ids: &[i64]) -> Result<Option<Job>, ReveError> {
+    if ids.is_empty() {
+        return Ok(None);
+    }
+    let ids: Vec<String> = ids.iter().map(i64::to_string).collect();
+    dequeue(conn, &format!("AND id IN ({})", ids.join(", ")))
+}
+
+fn dequeue(conn: &mut Connection, filter: &str) -> Result<Option<Job>, ReveError> {
+    let tx = conn.transaction()?;
+    let job = tx
+        .query_row(
+            &format!(
+                "SELECT {} FROM jobs WHERE state = 'queued' {}
+                 ORDER BY priority DESC, id LIMIT 1",
+                JOB_COLUMNS, filter
+            ),
+            params![],
+            job_from_row,
+        )
+        .optional()?;
+    let job = match job {
+        Some(mut job) => {
+            job.state = JobState::Running;
+            job.attempts += 1;
+            job.updated_at = now();
+            job.owner = Some(std::process::id());
+            tx.execute(
+                "UPDATE jobs SET state = 'running', attempts = ?1, updated_at = ?2, owner = ?3
+                 WHERE id = ?4",
+                params![job.attempts, job.updated_at, job.owner, job.id],
+            )?;
+            Some(job)
+        }
+        None => None,
+    };
+    tx.commit()?;
+    Ok(job)
+}
+
+pub fn mark_done(conn: &Connection, id: i64) -> Result<(), ReveError> {
+    conn.execute(
+        "UPDATE jobs SET state = 'done', error = NULL, updated_at = ?1 WHERE id = ?2",
+        params![now(), id],
+    )?;
+    Ok(())
+}
+
+pub fn mark_failed(conn: &Connection, id: i64, error: &str) -> Result<(), ReveError> {
+    conn.execute(
+        "UPDATE jobs SET state = 'failed', error = ?1, updated_at = ?2 WHERE id = ?3",
+        params![error, now(), id],
+    )?;
+    Ok(())
+}
+
+/// Cancels a job that hasn't finished, returns false if there is no such job.
+///
+/// Stopping the process of a running job is up to whoever runs it.
+pub fn mark_cancelled(conn: &Connection, id: i64) -> Result<bool, ReveError> {
+    let changed = conn.execute(
+        "UPDATE jobs SET state = 'cancelled', updated_at = ?1
+         WHERE id = ?2 AND state IN ('queued', 'running')",
+        params![now(), id],
+    )?;
+    Ok(changed > 0)
+}
+
+/// Puts jobs left running by a runner that didn't finish (crash, reboot) back in the queue.
+/// Their workspace still holds the finished segments, so they resume where they stopped.
+///
+/// Jobs whose owner is still running, another `reve queue run`, `reve serve` or the GUI, are
+/// left to it.
+pub fn requeue_interrupted(conn: &Connection) -> Result<usize, ReveError> {
+    let mut stmt = conn.prepare("SELECT id, owner FROM jobs WHERE state = 'running'")?;
+    let running = stmt
+        .query_map(params![], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, Option<u32>>(1)?))
+        })?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+    let mut requeued = 0;
+    for (id, owner) in running {
+        if owner.is_some_and(process_alive) {
+            continue;
+        }
+        if requeue_job(conn, id)? {
+            requeued += 1;
+        }
+    }
+    Ok(requeued)
+}
+
+/// Puts a running job back in the queue, after its runner stopped it. Returns false if there
+/// is no such running job.
+pub fn requeue_job(conn: &Connection, id: i64) -> Result<bool, ReveError> {
+    let changed = conn.execute(
+        "UPDATE jobs SET state = 'queued', owner = NULL, updated_at = ?1
+         WHERE id = ?2 AND state = 'running'",
+        params![now(), id],
+    )?;
+    Ok(changed > 0)
+}
+
+/// True if a process with id `pid` is running. A pid reused after a reboot looks alive.
+#[cfg(unix)]
+pub(crate) fn process_alive(pid: u32) -> bool {
+    // signal 0 only checks that the process exists, EPERM means it belongs to another user
+    let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(windows)]
+pub(crate) fn process_alive(pid: u32) -> bool {
+    std::process::Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/NH", "/FO", "CSV"])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).contains(&format!("\"{}\"", pid)))
+        .unwrap_or(true)
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn process_alive(_pid: u32) -> bool {
+    true
+}
+
+/// All jobs in the order they will run, finished ones last.
+pub fn list_jobs(conn: &Connection) -> Result<Vec<Job>, ReveError> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM jobs
+         ORDER BY CASE state WHEN 'running' THEN 0 WHEN 'queued' THEN 1 ELSE 2 END,
+                  priority DESC, id",
+        JOB_COLUMNS
+    ))?;
+    let jobs = stmt.query_map(params![], job_from_row)?;
+    Ok(jobs.collect::<Result<Vec<Job>, rusqlite::Error>>()?)
+}
+
+pub fn get_job(conn: &Connection, id: i64) -> Result<Option<Job>, ReveError> {
+    Ok(conn
+        .query_row(
+            &format!("SELECT {} FROM jobs WHERE id = ?1", JOB_COLUMNS),
+            params![id],
+            job_from_row,
+        )
+        .optional()?)
+}
+
+/// True if `input` has a job that wasn't cancelled, whatever its outcome.
+pub fn job_exists(conn: &Connection, input: &str) -> Result<bool, ReveError> {
+    let mut stmt = conn.prepare("SELECT 1 FROM jobs WHERE input = ?1 AND state != 'cancelled'")?;
+    Ok(stmt.exists(params![input])?)
+}
+
+/// Changes the priority of a job, returns false if there is no such job.
+pub fn set_job_priority(conn: &Connection, id: i64, priority: i64) -> Result<bool, ReveError> {
+    let changed = conn.execute(
+        "UPDATE jobs SET priority = ?1, updated_at = ?2 WHERE id = ?3",
+        params![priority, now(), id],
+    )?;
+    Ok(changed > 0)
+}
+
+/// Removes a job that isn't running, returns false if there is no such job.
+pub fn remove_job(conn: &Connection, id: i64) -> Result<bool, ReveError> {
+    let changed = conn.execute(
+        "DELETE FROM jobs WHERE id = ?1 AND state != 'running'",
+        params![id],
+    )?;
+    Ok(changed > 0)
+}
+
+fn job_from_row(row: &Row) -> Result<Job, rusqlite::Error> {
+    let settings: String = row.get(3)?;
+    let state: String = row.get(5)?;
+    Ok(Job {
+        id: row.get(0)?,
+        input: row.get(1)?,
+        output: row.get(2)?,
+        settings: serde_json::from_str(&settings).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(3, rusqlite::types::Type::Text, Box::new(e))
+        })?,
+        priority: row.get(4)?,
+        state: JobState::parse(&state),
+        attempts: row.get(6)?,
+        error: row.get(7)?,
+        created_at: row.get(8)?,
+        updated_at: row.get(9)?,
+        owner: row.get(10)?,
+    })
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub index: u32,
+    /// number of the first source frame in the segment
+    #[serde(default)]
+    pub start: u32,
+    pub size: u32,
+    /// where exact extraction seeks to, set by `FrameIndex::locate`
+    #[serde(default)]
+    pub seek: Option<Seek>,
+}
+
+/// Timestamps, in seconds, that let a segment be extracted without decoding the source from
+/// its first frame.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Seek {
+    /// pts of the last keyframe at or before the first frame of the segment
+    pub keyframe: f64,
+    /// halfway between the pts of the frame before the segment and of its first frame
+    pub after: f64,
+}
+
+/// How frames of a segment are picked out of the source.
+#[derive(clap::ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExtractMode {
+    /// seek to the keyframe before the segment and keep frames by pts, every frame lands in
+    /// exactly one segment
+    #[default]
+    Exact,
+    /// seek by timestamp, faster on long files but may repeat or drop frames at boundaries
+    Seek,
+}
+
+/// Splits `frame_count` frames into segments of `segment_size` frames.
+///
+/// The segments are contiguous and don't overlap, so together they hold every source frame
+/// exactly once; only the last one may be shorter.
+pub fn plan_segments(frame_count: u32, segment_size: u32) -> Vec<Segment> {
+    let segment_size = segment_size.max(1);
+    let mut segments = Vec::new();
+    let mut start = 0;
+    while start < frame_count {
+        let size = segment_size.min(frame_count - start);
+        segments.push(Segment {
+            index: segments.len() as u32,
+            start,
+            size,
+            seek: None,
+        });
+        start += size;
+    }
+    segments
+}
+
+impl Segment {
+    /// ffmpeg arguments up to and including the input, for extracting this segment.
+    ///
+    /// Exact extraction of a located segment starts decoding at its keyframe and keeps the
+    /// source timestamps, so the frames can be picked by pts. Without a location every frame
+    /// from the start of the source is decoded.
+    pub fn input_args(&self, mode: ExtractMode, input_path: &str, frame_rate: f32) -> Vec<String> {
+        let mut args = Vec::new();
+        match (mode, self.seek) {
+            (ExtractMode::Exact, Some(seek)) => {
+                args.extend([
+                    String::from("-seek_timestamp"),
+                    String::from("1"),
+                    String::from("-ss"),
+                    seek.keyframe.to_string(),
+                    String::from("-noaccurate_seek"),
+                    String::from("-copyts"),
+                ]);
+            }
+            (ExtractMode::Seek, _) if self.start != 0 => {
+                // half a frame early, so rounding can't skip the first frame
+                args.push(String::from("-ss"));
+                args.push(((self.start as f32 - 0.5) / frame_rate).to_string());
+            }
+            _ => (),
+        }
+        args.push(String::from("-i"));
+        args.push(input_path.to_string());
+        args
+    }
+
+    /// ffmpeg output arguments selecting the frames of this segment, before the output path.
+    ///
+    /// With `showinfo` every extracted frame is logged with its `pts_time`.
+    pub fn output_args(&self, mode: ExtractMode, showinfo: bool) -> Vec<String> {
+        let mut args = Vec::new();
+        let mut filters = Vec::new();
+        match (mode, self.seek) {
+            (ExtractMode::Exact, Some(seek)) => {
+                filters.push(format!("select='gte(t,{})'", seek.after));
+            }
+            (ExtractMode::Exact, None) => {
+                filters.push(format!(
+                    "select='between(n,{},{})'",
+                    self.start,
+                    self.start + self.size - 1
+                ));
+            }
+            (ExtractMode::Seek, _) => (),
+        }
+        if showinfo {
+            filters.push(String::from("showinfo"));
+        }
+        if !filters.is_empty() {
+            args.push(String::from("-vf"));
+            args.push(filters.join(","));
+        }
+        args.extend([
+            String::from("-vsync"),
+            String::from("0"),
+            String::from("-vframes"),
+            self.size.to_string(),
+        ]);
+        args
+    }
+}
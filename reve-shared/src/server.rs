@@ -0,0 +1,466 @@
+use crate::error::StderrTail;
+use crate::{
+    absolute_path, config_dir, dequeue_next, enqueue, get_job, list_jobs, mark_cancelled,
+    mark_done, mark_failed, open_db, requeue_interrupted, walk_files, Args, Job, JobState,
+    Profiles, ReveConfig, ReveError, Workspace, WorkspaceProgress,
+};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{BufRead, BufReader, Error};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use tiny_http::{Header, Method, Request, Response};
+
+/// Body of `POST /jobs`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JobRequest {
+    /// video file, or folder whose videos are all queued
+    pub input: String,
+    /// output video path, next to the input if not set
+    #[serde(default)]
+    pub output: Option<String>,
+    #[serde(default)]
+    pub priority: i64,
+    /// reve command line options, e.g. `["-s", "4", "--pipe"]`
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+/// Body of `GET /progress`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ServerProgress {
+    pub job: Option<Job>,
+    pub progress: Option<WorkspaceProgress>,
+}
+
+struct RunningJob {
+    job: Job,
+    child: Child,
+}
+
+const TOKEN_FILE: &str = "server-token";
+
+/// Upscalers a request can pick by name, a command template must come from the config or a profile.
+const BACKENDS: [&str; 3] = ["realesrgan", "realcugan", "waifu2x"];
+
+/// `reve serve`: a worker running queued jobs one at a time and a small HTTP/JSON API to drive it.
+///
+/// Every request needs `Authorization: Bearer <token>`, with the token the server writes to
+/// [`JobServer::token_path`] when it starts, and a `localhost` or `127.0.0.1` Host, so web pages
+/// open in a browser can't reach it. `POST /jobs` bodies are `application/json`.
+///
+/// ```text
+/// POST /jobs               submit a JobRequest, returns {"ids": [...]}
+/// GET  /jobs               every job, in the order they run
+/// GET  /jobs/<id>          one job
+/// POST /jobs/<id>/cancel   cancel a queued or running job (DELETE /jobs/<id> works too)
+/// GET  /progress           running job and the progress of its current segment
+/// ```
+///
+/// Jobs live in the `jobs` table, like the ones added with `reve queue add`. Each one runs in its
+/// own `runner` process (the reve executable) so a failing job can't take the server down, and
+/// cancelling it kills the runner with the ffmpeg and upscaler processes it started.
+pub struct JobServer {
+    http: tiny_http::Server,
+    db_path: PathBuf,
+    runner: PathBuf,
+    token: String,
+    current: Arc<Mutex<Option<RunningJob>>>,
+}
+
+impl JobServer {
+    /// Listens on `address`, use 127.0.0.1 so only local tools can submit work.
+    pub fn bind(
+        address: &str,
+        db_path: impl AsRef<Path>,
+        runner: impl AsRef<Path>,
+    ) -> Result<JobServer, ReveError> {
+        let http = tiny_http::Server::http(address).map_err(Error::other)?;
+        let server = JobServer {
+            http,
+            db_path: db_path.as_ref().to_path_buf(),
+            runner: runner.as_ref().to_path_buf(),
+            token: new_token(),
+            current: Arc::new(Mutex::new(None)),
+        };
+        // creates reve.db, or migrates it, before the first request
+        server.open_db()?;
+        Ok(server)
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.http.server_addr().to_ip()
+    }
+
+    /// `server-token` in [`config_dir`], read by the tools talking to `reve serve`.
+    pub fn token_path() -> PathBuf {
+        config_dir().join(TOKEN_FILE)
+    }
+
+    /// Token requests must send, a new one each time the server starts.
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Writes the token to `path`, readable by the current user only.
+    pub fn write_token(&self, path: &Path) -> Result<(), ReveError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(path)?;
+        std::io::Write::write_all(&mut file, self.token.as_bytes())?;
+        Ok(())
+    }
+
+    /// Starts the worker and answers requests until the process ends.
+    pub fn run(self) -> Result<(), ReveError> {
+        let worker_conn = self.open_db()?;
+        let runner = self.runner.clone();
+        let current = self.current.clone();
+        thread::spawn(move || work(worker_conn, &runner, &current));
+
+        let conn = self.open_db()?;
+        for mut request in self.http.incoming_requests() {
+            let (status, body) = self.handle(&conn, &mut request);
+            let header =
+                Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+            let response = Response::from_string(body.to_string())
+                .with_status_code(status)
+                .with_header(header);
+            let _ = request.respond(response);
+        }
+        Ok(())
+    }
+
+    fn open_db(&self) -> Result<Connection, ReveError> {
+        open_db(&self.db_path)
+    }
+
+    fn handle(&self, conn: &Connection, request: &mut Request) -> (u16, serde_json::Value) {
+        let url = request.url().split('?').next().unwrap_or("").to_string();
+        let parts: Vec<&str> = url.split('/').filter(|p| !p.is_empty()).collect();
+        let method = request.method().clone();
+
+        if !self.local_host(request) {
+            return (
+                403,
+                json!({ "error": "the Host must be localhost or 127.0.0.1" }),
+            );
+        }
+        let authorization = header(request, "Authorization");
+        if authorization.and_then(|value| value.strip_prefix("Bearer ")) != Some(&self.token) {
+            return (
+                401,
+                json!({ "error": format!("send the token of {}", JobServer::token_path().display()) }),
+            );
+        }
+        if method == Method::Post
+            && parts.as_slice() == ["jobs"]
+            && !header(request, "Content-Type")
+                .is_some_and(|value| value.starts_with("application/json"))
+        {
+            return (415, json!({ "error": "the body must be application/json" }));
+        }
+
+        let result = match (&method, parts.as_slice()) {
+            (Method::Post, ["jobs"]) => self.submit(conn, request),
+            (Method::Get, ["jobs"]) => list_jobs(conn).map(|jobs| (200, json!(jobs))),
+            (Method::Get, ["jobs", id]) => match parse_id(id) {
+                Some(id) => get_job(conn, id).map(|job| match job {
+                    Some(job) => (200, json!(job)),
+                    None => not_found(),
+                }),
+                None => Ok(not_found()),
+            },
+            (Method::Post, ["jobs", id, "cancel"]) | (Method::Delete, ["jobs", id]) => {
+                match parse_id(id) {
+                    Some(id) => self.cancel(conn, id),
+                    None => Ok(not_found()),
+                }
+            }
+            (Method::Get, ["progress"]) => Ok((200, json!(self.progress()))),
+            _ => Ok(not_found()),
+        };
+        result.unwrap_or_else(|e| (500, json!({ "error": e.to_string() })))
+    }
+
+    /// Host header of a request sent to the loopback address, not to a name that resolves to it.
+    fn local_host(&self, request: &Request) -> bool {
+        let port = self.local_addr().map(|address| address.port());
+        match header(request, "Host") {
+            Some(host) => {
+                let (name, host_port) = match host.rsplit_once(':') {
+                    Some((name, host_port)) => (name, host_port.parse().ok()),
+                    None => (host, port),
+                };
+                (name == "localhost" || name == "127.0.0.1") && host_port == port
+            }
+            None => false,
+        }
+    }
+
+    fn submit(
+        &self,
+        conn: &Connection,
+        request: &mut Request,
+    ) -> Result<(u16, serde_json::Value), ReveError> {
+        let mut body = String::new();
+        if let Err(e) = request.as_reader().read_to_string(&mut body) {
+            return Ok(bad_request(&e.to_string()));
+        }
+        let job_request: JobRequest = match serde_json::from_str(&body) {
+            Ok(job_request) => job_request,
+            Err(e) => return Ok(bad_request(&e.to_string())),
+        };
+        let cli_args = [
+            String::from("reve"),
+            format!("--inputpath={}", job_request.input),
+        ]
+        .into_iter()
+        .chain(job_request.args.iter().cloned());
+        let config = ReveConfig::load()?;
+        let profiles = Profiles::load()?;
+        let settings = match Args::try_parse_with_config(cli_args, &config, &profiles) {
+            Ok(settings) if settings.command.is_none() => settings,
+            Ok(_) => return Ok(bad_request("args can't contain a subcommand")),
+            Err(e) => return Ok(bad_request(&e.render().to_string())),
+        };
+        // a template or a workdir runs commands and writes files of the caller's choosing, they
+        // are only taken from the config and the profiles
+        let configs = || {
+            std::iter::once(&config).chain(profiles.list().iter().map(|profile| &profile.settings))
+        };
+        if !BACKENDS.contains(&settings.upscaler.as_str())
+            && !configs().any(|config| config.upscaler.as_ref() == Some(&settings.upscaler))
+        {
+            return Ok(bad_request(&format!(
+                "upscaler must be {} or the one of the config or a profile",
+                BACKENDS.join("/")
+            )));
+        }
+        if let Some(workdir) = &settings.workdir {
+            if !configs().any(|config| config.workdir.as_ref() == Some(workdir)) {
+                return Ok(bad_request(
+                    "workdir must be the one of the config or a profile",
+                ));
+            }
+        }
+        let output = job_request.output.or(settings.outputpath.clone());
+        if let Some(output) = &output {
+            if Path::new(output).exists() {
+                return Ok(bad_request(&format!("{} already exists", output)));
+            }
+        }
+
+        let input = absolute_path(&job_request.input);
+        let mut ids = Vec::new();
+        if Path::new(&input).is_dir() {
+            for file in walk_files(&input) {
+                ids.push(enqueue(conn, &file, None, &settings, job_request.priority)?);
+            }
+        } else {
+            let output = output.as_deref().map(absolute_path);
+            ids.push(enqueue(
+                conn,
+                &input,
+                output.as_deref(),
+                &settings,
+                job_request.priority,
+            )?);
+        }
+        Ok((201, json!({ "ids": ids })))
+    }
+
+    fn cancel(&self, conn: &Connection, id: i64) -> Result<(u16, serde_json::Value), ReveError> {
+        let mut current = self.current.lock().unwrap();
+        if !mark_cancelled(conn, id)? {
+            return Ok(match get_job(conn, id)? {
+                Some(job) => (
+                    409,
+                    json!({ "error": format!("job {} is already {}", id, job.state) }),
+                ),
+                None => not_found(),
+            });
+        }
+        if let Some(running) = current.as_mut().filter(|running| running.job.id == id) {
+            kill_runner(&running.child);
+        }
+        Ok((200, json!(get_job(conn, id)?)))
+    }
+
+    fn progress(&self) -> ServerProgress {
+        let current = self.current.lock().unwrap();
+        match current.as_ref() {
+            Some(running) => ServerProgress {
+                job: Some(running.job.clone()),
+                progress: Some(
+                    Workspace::resolve(running.job.settings.workdir.as_deref()).progress(),
+                ),
+            },
+            None => ServerProgress {
+                job: None,
+                progress: None,
+            },
+        }
+    }
+}
+
+/// Runs queued jobs one after the other, forever.
+fn work(mut conn: Connection, runner: &Path, current: &Mutex<Option<RunningJob>>) {
+    if let Err(e) = requeue_interrupted(&conn) {
+        println!("could not requeue interrupted jobs: {}", e);
+    }
+    loop {
+        // the job is dequeued and its runner started under the lock, so a cancel either finds
+        // the job still queued or finds its runner
+        let mut running = current.lock().unwrap();
+        let job = match dequeue_next(&mut conn) {
+            Ok(Some(job)) => job,
+            Ok(None) => {
+                drop(running);
+                thread::sleep(Duration::from_secs(1));
+                continue;
+            }
+            Err(e) => {
+                drop(running);
+                println!("could not read the job queue: {}", e);
+                thread::sleep(Duration::from_secs(5));
+                continue;
+            }
+        };
+
+        println!("running job {}: {}", job.id, job.input);
+        let mut command = Command::new(runner);
+        command
+            .args(job.settings.to_cli_args())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped());
+        // ffmpeg and the upscaler join the process group of the runner, cancel kills them all
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                let _ = mark_failed(&conn, job.id, &e.to_string());
+                continue;
+            }
+        };
+        let stderr = child.stderr.take().map(|stderr| {
+            thread::spawn(move || {
+                let mut tail = StderrTail::default();
+                for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                    tail.push(&line);
+                }
+                tail
+            })
+        });
+        let id = job.id;
+        *running = Some(RunningJob { job, child });
+        drop(running);
+
+        // poll instead of waiting so cancel can take the lock and kill the child
+        let status = loop {
+            if let Some(running) = current.lock().unwrap().as_mut() {
+                match running.child.try_wait() {
+                    Ok(Some(status)) => break Ok(status),
+                    Ok(None) => (),
+                    Err(e) => break Err(e),
+                }
+            }
+            thread::sleep(Duration::from_millis(200));
+        };
+        *current.lock().unwrap() = None;
+        let stderr = stderr
+            .and_then(|reader| reader.join().ok())
+            .unwrap_or_default()
+            .into_string();
+
+        // a cancelled job was already marked by the request
+        if let Ok(Some(job)) = get_job(&conn, id) {
+            if job.state == JobState::Cancelled {
+                println!("cancelled job {}", id);
+                continue;
+            }
+        }
+        let result = match status {
+            Ok(status) if status.success() => mark_done(&conn, id),
+            Ok(status) if stderr.trim().is_empty() => {
+                mark_failed(&conn, id, &format!("reve exited with {}", status))
+            }
+            Ok(status) => mark_failed(
+                &conn,
+                id,
+                &format!("reve exited with {}:\n{}", status, stderr.trim_end()),
+            ),
+            Err(e) => mark_failed(&conn, id, &e.to_string()),
+        };
+        if let Err(e) = result {
+            println!("could not update job {}: {}", id, e);
+        }
+    }
+}
+
+/// Kills the runner of a job and the processes in its process group.
+#[cfg(unix)]
+fn kill_runner(child: &Child) {
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+/// Kills the runner of a job and the processes it started.
+#[cfg(not(unix))]
+fn kill_runner(child: &Child) {
+    let _ = Command::new("taskkill")
+        .args(["/F", "/T", "/PID", &child.id().to_string()])
+        .output();
+}
+
+/// 128 random bits from the keys std seeds its hash maps with, as hex.
+fn new_token() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    (0..2)
+        .map(|_| {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u128(nanos);
+            hasher.write_u32(std::process::id());
+            format!("{:016x}", hasher.finish())
+        })
+        .collect()
+}
+
+fn header<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
+
+fn parse_id(id: &str) -> Option<i64> {
+    id.parse().ok()
+}
+
+fn not_found() -> (u16, serde_json::Value) {
+    (404, json!({ "error": "not found" }))
+}
+
+fn bad_request(error: &str) -> (u16, serde_json::Value) {
+    (400, json!({ "error": error }))
+}
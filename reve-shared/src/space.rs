@@ -0,0 +1,134 @@
+use crate::{ReveError, SpaceEstimate, Workspace};
+use std::path::Path;
+
+/// A filesystem, as statvfs sees it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Filesystem {
+    /// device of the filesystem, the same for two paths on it
+    pub device: u64,
+    /// bytes an unprivileged user can still write
+    pub available: u64,
+}
+
+/// The filesystem holding `path`, or the folder it would be created in. None where statvfs
+/// isn't available, the space checks are skipped there.
+#[cfg(unix)]
+pub fn filesystem(path: &Path) -> Option<Filesystem> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::MetadataExt;
+
+    let path = crate::absolute_path(path);
+    let existing = Path::new(&path).ancestors().find(|p| p.exists())?;
+    let device = existing.metadata().ok()?.dev();
+    let c_path = CString::new(existing.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(Filesystem {
+        device,
+        available: stat.f_bavail as u64 * stat.f_frsize as u64,
+    })
+}
+
+#[cfg(not(unix))]
+pub fn filesystem(_path: &Path) -> Option<Filesystem> {
+    None
+}
+
+/// Share of the free workspace space `--parts auto` fills, the estimate is rough.
+const AUTO_SEGMENT_SPACE: f64 = 0.8;
+
+/// Checks that the workspace and the folder of `output` have room for an upscale of
+/// `estimate`, before anything is written. Without `pipe` a `--parts` that would fit is
+/// suggested.
+pub fn check_space(
+    workspace: &Workspace,
+    output: &str,
+    estimate: &SpaceEstimate,
+    pipe: bool,
+) -> Result<(), ReveError> {
+    let Some((available, output_size)) = workspace_space(workspace, output, estimate) else {
+        return Ok(());
+    };
+    let needed = estimate.total() + output_size;
+    if needed > available {
+        let parts = estimate.segment_size_for(available - output_size.min(available));
+        return Err(ReveError::NoSpace {
+            path: workspace.root().display().to_string(),
+            needed,
+            available,
+            parts: (!pipe && parts > 0).then_some(parts),
+        });
+    }
+
+    let output_dir = Path::new(output).parent().unwrap_or(Path::new("."));
+    if let Some(output_fs) = filesystem(output_dir).filter(|_| output_size == 0) {
+        if estimate.output > output_fs.available {
+            return Err(ReveError::NoSpace {
+                path: crate::absolute_path(output_dir),
+                needed: estimate.output,
+                available: output_fs.available,
+                parts: None,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// `--parts auto`: the largest segment whose frames fit in the free space of `workspace`, with
+/// a margin, next to the video parts and the output of `estimate`. It is never longer than
+/// `frame_count`. In pipe mode the frames on disk don't depend on the segment size and 1000
+/// is used, as without statvfs.
+pub fn auto_segment_size(
+    workspace: &Workspace,
+    output: &str,
+    estimate: &SpaceEstimate,
+    frame_count: u32,
+    pipe: bool,
+) -> Result<u32, ReveError> {
+    let space = workspace_space(workspace, output, estimate).filter(|_| !pipe);
+    let Some((available, output_size)) = space else {
+        return Ok(1000.min(frame_count.max(1)));
+    };
+    let usable = (available as f64 * AUTO_SEGMENT_SPACE) as u64;
+    let segment_size = estimate.segment_size_for(usable.saturating_sub(output_size));
+    if segment_size == 0 {
+        return Err(ReveError::NoSpace {
+            path: workspace.root().display().to_string(),
+            needed: estimate.parts + output_size + 2 * estimate.frame_pair,
+            available,
+            parts: None,
+        });
+    }
+    Ok(segment_size.min(frame_count.max(1)))
+}
+
+/// Free space of the workspace, counting the frames and video parts an earlier run left in it
+/// as they are removed or reused, and the size of the output if it is written to the same
+/// filesystem (0 otherwise). None where statvfs isn't available.
+fn workspace_space(
+    workspace: &Workspace,
+    output: &str,
+    estimate: &SpaceEstimate,
+) -> Option<(u64, u64)> {
+    let work_fs = filesystem(workspace.root())?;
+    let output_dir = Path::new(output).parent().unwrap_or(Path::new("."));
+    let same_fs = filesystem(output_dir).is_none_or(|fs| fs.device == work_fs.device);
+    let reclaimed = dir_size(&workspace.tmp_frames())
+        + dir_size(&workspace.out_frames())
+        + dir_size(&workspace.video_parts());
+    let output_size = if same_fs { estimate.output } else { 0 };
+    Some((work_fs.available + reclaimed, output_size))
+}
+
+fn dir_size(dir: &Path) -> u64 {
+    walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
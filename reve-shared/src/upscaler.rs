@@ -0,0 +1,246 @@
+use crate::ReveError;
+use regex::Regex;
+use std::process::Command;
+
+/// Default `--model`, a realesrgan one.
+pub const REALESRGAN_MODEL: &str = "realesr-animevideov3";
+
+/// A frame upscaler backend that turns a folder of frames into a folder of upscaled frames.
+pub trait Upscaler: Send + Sync {
+    /// Short name of the backend, used in logs.
+    fn name(&self) -> &str;
+
+    /// Builds the command that upscales every frame of `input_dir` into `output_dir`.
+    fn command(&self, input_dir: &str, output_dir: &str, scale: u8, model: &str) -> Command;
+
+    /// Returns true if `line` (read from the backend's stderr) reports a finished frame.
+    fn is_progress_line(&self, line: &str) -> bool;
+
+    /// Builds a command reading rgb24 rawvideo frames of `width`x`height` on stdin and writing
+    /// the upscaled rgb24 frames on stdout, or `None` if the backend only works on folders.
+    fn stream_command(
+        &self,
+        _width: u32,
+        _height: u32,
+        _scale: u8,
+        _model: &str,
+    ) -> Option<Command> {
+        None
+    }
+
+    /// Returns false for streaming-only backends, which can't run [`Upscaler::command`].
+    fn supports_folders(&self) -> bool {
+        true
+    }
+}
+
+/// realesrgan-ncnn-vulkan, the default backend.
+pub struct RealEsrgan {
+    program: String,
+}
+
+impl RealEsrgan {
+    pub fn new() -> RealEsrgan {
+        RealEsrgan {
+            program: find_program("realesrgan-ncnn-vulkan"),
+        }
+    }
+}
+
+impl Default for RealEsrgan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Upscaler for RealEsrgan {
+    fn name(&self) -> &str {
+        "realesrgan"
+    }
+
+    fn command(&self, input_dir: &str, output_dir: &str, scale: u8, model: &str) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args([
+            "-i",
+            input_dir,
+            "-o",
+            output_dir,
+            "-n",
+            &format!("{}-x{}", model, scale),
+            "-s",
+            &scale.to_string(),
+            "-f",
+            "png",
+            "-v",
+        ]);
+        command
+    }
+
+    fn is_progress_line(&self, line: &str) -> bool {
+        line.contains("done")
+    }
+}
+
+/// realcugan-ncnn-vulkan and waifu2x-ncnn-vulkan, `model` is the folder of models they load
+/// with `-m` (models-se, models-cunet, ...).
+pub struct NcnnVulkan {
+    name: String,
+    program: String,
+}
+
+impl NcnnVulkan {
+    pub fn new(name: &str, program: &str) -> NcnnVulkan {
+        NcnnVulkan {
+            name: name.to_string(),
+            program: find_program(program),
+        }
+    }
+}
+
+impl Upscaler for NcnnVulkan {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn command(&self, input_dir: &str, output_dir: &str, scale: u8, model: &str) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(["-i", input_dir, "-o", output_dir, "-s", &scale.to_string()]);
+        // the default --model is a realesrgan one, the program picks its own then
+        if model != REALESRGAN_MODEL {
+            command.args(["-m", model]);
+        }
+        command.args(["-f", "png", "-v"]);
+        command
+    }
+
+    fn is_progress_line(&self, line: &str) -> bool {
+        line.contains("done")
+    }
+}
+
+/// Where the built-in backends run `program` from: next to the reve executable, as the
+/// release archives ship it, otherwise from PATH.
+fn find_program(program: &str) -> String {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| {
+            let bundled =
+                exe.parent()?
+                    .join(format!("{}{}", program, std::env::consts::EXE_SUFFIX));
+            bundled.is_file().then(|| bundled.display().to_string())
+        })
+        .unwrap_or_else(|| program.to_string())
+}
+
+/// A backend described by a command line template.
+///
+/// The template is split on whitespace and the placeholders `{input_dir}`, `{output_dir}`,
+/// `{scale}` and `{model}` are substituted in every argument. Stderr lines matching
+/// `progress` are counted as finished frames.
+///
+/// A template using `{width}` and `{height}` instead of the folders is a streaming backend:
+/// it reads rgb24 rawvideo frames on stdin and writes the upscaled frames on stdout.
+pub struct CommandTemplate {
+    name: String,
+    program: String,
+    args: Vec<String>,
+    progress: Regex,
+    streaming: bool,
+}
+
+impl CommandTemplate {
+    pub fn parse(name: &str, template: &str, progress: &str) -> Result<CommandTemplate, ReveError> {
+        let mut parts = template.split_whitespace().map(|s| s.to_string());
+        let program = parts
+            .next()
+            .ok_or_else(|| ReveError::InvalidInput(String::from("upscaler template is empty")))?;
+        let args: Vec<String> = parts.collect();
+        let has = |placeholder: &str| args.iter().any(|a| a.contains(placeholder));
+        let streaming = if has("{input_dir}") && has("{output_dir}") {
+            false
+        } else if has("{width}") && has("{height}") {
+            true
+        } else {
+            return Err(ReveError::InvalidInput(String::from(
+                "upscaler template must contain {input_dir} and {output_dir}, or {width} and {height}",
+            )));
+        };
+        let progress = Regex::new(progress).map_err(|e| ReveError::InvalidInput(e.to_string()))?;
+
+        Ok(CommandTemplate {
+            name: name.to_string(),
+            program,
+            args,
+            progress,
+            streaming,
+        })
+    }
+
+    fn build(&self, placeholders: &[(&str, String)]) -> Command {
+        let mut command = Command::new(&self.program);
+        for arg in &self.args {
+            let mut arg = arg.clone();
+            for (placeholder, value) in placeholders {
+                arg = arg.replace(placeholder, value);
+            }
+            command.arg(arg);
+        }
+        command
+    }
+}
+
+impl Upscaler for CommandTemplate {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn command(&self, input_dir: &str, output_dir: &str, scale: u8, model: &str) -> Command {
+        self.build(&[
+            ("{input_dir}", input_dir.to_string()),
+            ("{output_dir}", output_dir.to_string()),
+            ("{scale}", scale.to_string()),
+            ("{model}", model.to_string()),
+        ])
+    }
+
+    fn is_progress_line(&self, line: &str) -> bool {
+        self.progress.is_match(line)
+    }
+
+    fn stream_command(&self, width: u32, height: u32, scale: u8, model: &str) -> Option<Command> {
+        if !self.streaming {
+            return None;
+        }
+        Some(self.build(&[
+            ("{width}", width.to_string()),
+            ("{height}", height.to_string()),
+            ("{scale}", scale.to_string()),
+            ("{model}", model.to_string()),
+        ]))
+    }
+
+    fn supports_folders(&self) -> bool {
+        !self.streaming
+    }
+}
+
+/// Resolves the `--upscaler` value into a backend.
+///
+/// Accepts the built-in names `realesrgan`, `realcugan` and `waifu2x`, or a custom command
+/// template (see [`CommandTemplate`]) whose progress lines match `progress`.
+pub fn get_upscaler(upscaler: &str, progress: &str) -> Result<Box<dyn Upscaler>, ReveError> {
+    match upscaler {
+        "realesrgan" => Ok(Box::new(RealEsrgan::new())),
+        "realcugan" => Ok(Box::new(NcnnVulkan::new(
+            "realcugan",
+            "realcugan-ncnn-vulkan",
+        ))),
+        "waifu2x" => Ok(Box::new(NcnnVulkan::new("waifu2x", "waifu2x-ncnn-vulkan"))),
+        template if template.contains('{') => Ok(Box::new(CommandTemplate::parse(
+            "custom", template, progress,
+        )?)),
+        _ => Err(ReveError::InvalidInput(String::from(
+            "valid: realesrgan/realcugan/waifu2x or a command template with {input_dir} {output_dir}",
+        ))),
+    }
+}
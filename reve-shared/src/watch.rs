@@ -0,0 +1,51 @@
+use crate::walk_files;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
+/// Finds videos in a watched folder once they are completely written.
+///
+/// A file is ready when its size is the same on two polls in a row, so a video still being
+/// copied into the folder is left alone until the copy is done. Each file is returned once.
+#[derive(Default)]
+pub struct FolderWatcher {
+    dir: String,
+    /// name endings of files that are never returned
+    ignored: Vec<String>,
+    sizes: HashMap<String, u64>,
+    seen: HashSet<String>,
+}
+
+impl FolderWatcher {
+    pub fn new(dir: &str) -> FolderWatcher {
+        FolderWatcher {
+            dir: dir.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Never returns files whose name ends with `suffix`, like the upscales written next to
+    /// the videos of the folder.
+    pub fn ignore(mut self, suffix: &str) -> FolderWatcher {
+        self.ignored.push(suffix.to_string());
+        self
+    }
+
+    /// Videos (as filtered by `walk_files`) that stopped growing since the last poll.
+    pub fn poll(&mut self) -> Vec<String> {
+        let mut ready = Vec::new();
+        for file in walk_files(&self.dir) {
+            if self.seen.contains(&file) || self.ignored.iter().any(|s| file.ends_with(s)) {
+                continue;
+            }
+            let size = fs::metadata(&file).map(|m| m.len()).unwrap_or(0);
+            if size > 0 && self.sizes.get(&file) == Some(&size) {
+                self.sizes.remove(&file);
+                self.seen.insert(file.clone());
+                ready.push(file);
+            } else {
+                self.sizes.insert(file, size);
+            }
+        }
+        ready
+    }
+}
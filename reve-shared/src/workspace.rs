@@ -0,0 +1,302 @@
+use crate::queue::process_alive;
+use crate::{JobSpec, ReveError};
+use path_clean::PathClean;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// How far the upscale using a workspace got, read from the files it left so far.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct WorkspaceProgress {
+    /// finished video parts
+    pub parts_done: u32,
+    /// segment being upscaled, if any
+    pub segment: Option<u32>,
+    /// frames of that segment exported from the source
+    pub frames_exported: u32,
+    /// frames of that segment already upscaled
+    pub frames_upscaled: u32,
+}
+
+/// Bytes per pixel of an exported PNG frame, about half of raw RGB.
+const PNG_BYTES_PER_PIXEL: f64 = 1.5;
+
+/// Rough disk usage of an upscale, in bytes.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SpaceEstimate {
+    /// PNG frames in the workspace at once
+    pub frames: u64,
+    /// encoded video parts and the temp.<ext> they are merged into
+    pub parts: u64,
+    /// the finished video
+    pub output: u64,
+    /// a source frame and its upscale
+    pub frame_pair: u64,
+}
+
+impl SpaceEstimate {
+    /// Estimates the upscale of `spec` for a `width`x`height` source of `frame_count` frames
+    /// and `source_size` bytes.
+    ///
+    /// Without `--pipe` the export of the next segment and the merge of the previous one
+    /// overlap with the upscale, so up to two segments of source frames and two of upscaled
+    /// frames are on disk. With it only a window of each is. The encoded video is counted at
+    /// `scale` times the source.
+    pub fn new(
+        spec: &JobSpec,
+        width: u32,
+        height: u32,
+        frame_count: u32,
+        source_size: u64,
+    ) -> SpaceEstimate {
+        let scale = spec.scale as f64;
+        let frame = width as f64 * height as f64 * PNG_BYTES_PER_PIXEL;
+        let frame_pair = (frame + frame * scale * scale) as u64;
+        let frames = if spec.pipe {
+            spec.pipe_window.min(frame_count) as u64 * frame_pair
+        } else {
+            2 * spec.segment_size.min(frame_count) as u64 * frame_pair
+        };
+        let output = (source_size as f64 * scale) as u64;
+        SpaceEstimate {
+            frames,
+            parts: 2 * output,
+            output,
+            frame_pair,
+        }
+    }
+
+    /// Peak size of the workspace.
+    pub fn total(&self) -> u64 {
+        self.frames + self.parts
+    }
+
+    /// Largest `--parts` whose frames fit in `available` bytes next to the video parts, 0 if
+    /// not even one frame does.
+    pub fn segment_size_for(&self, available: u64) -> u32 {
+        if self.frame_pair == 0 {
+            return u32::MAX;
+        }
+        let room = available.saturating_sub(self.parts) / (2 * self.frame_pair);
+        room.min(u32::MAX as u64) as u32
+    }
+}
+
+/// Folder holding every temporary file of an upscale.
+///
+/// ```text
+/// <root>/reve.lock           pid of the reve process using the workspace
+/// <root>/args.temp            settings of the upscale being resumed
+/// <root>/parts.txt            concat list of the video parts
+/// <root>/temp.<ext>           merged video parts, before streams are copied
+/// <root>/tmp_frames/<index>/  exported frames of a segment
+/// <root>/out_frames/<index>/  upscaled frames of a segment
+/// <root>/video_parts/<index>.<ext>
+/// <root>/timestamps/<index>.txt     source pts of every frame of a segment (VFR sources)
+/// <root>/timestamps/<index>.ffconcat
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Workspace {
+    root: PathBuf,
+}
+
+impl Workspace {
+    pub fn new(root: impl AsRef<Path>) -> Workspace {
+        let root = root.as_ref();
+        let root = if root.is_absolute() {
+            root.to_path_buf()
+        } else {
+            env::current_dir().unwrap_or_default().join(root)
+        };
+        Workspace { root: root.clean() }
+    }
+
+    /// Picks the workspace root: `workdir` (from `--workdir` or a config value) if given,
+    /// otherwise /dev/shm/reve when /dev/shm is usable, otherwise `temp` in the current folder.
+    pub fn resolve(workdir: Option<&str>) -> Workspace {
+        if let Some(workdir) = workdir {
+            return Workspace::new(workdir);
+        }
+        let shm = Path::new("/dev/shm");
+        if shm.is_dir() && fs::create_dir_all(shm.join("reve")).is_ok() {
+            return Workspace::new(shm.join("reve"));
+        }
+        Workspace::new("temp")
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn tmp_frames(&self) -> PathBuf {
+        self.root.join("tmp_frames")
+    }
+
+    pub fn out_frames(&self) -> PathBuf {
+        self.root.join("out_frames")
+    }
+
+    pub fn video_parts(&self) -> PathBuf {
+        self.root.join("video_parts")
+    }
+
+    pub fn timestamps_dir(&self) -> PathBuf {
+        self.root.join("timestamps")
+    }
+
+    pub fn lock_file(&self) -> PathBuf {
+        self.root.join("reve.lock")
+    }
+
+    pub fn args_file(&self) -> PathBuf {
+        self.root.join("args.temp")
+    }
+
+    pub fn parts_list(&self) -> PathBuf {
+        self.root.join("parts.txt")
+    }
+
+    pub fn temp_video(&self, extension: &str) -> PathBuf {
+        self.root.join(format!("temp.{}", extension))
+    }
+
+    /// Exported frames of segment `index`.
+    pub fn tmp_frames_dir(&self, index: u32) -> PathBuf {
+        self.tmp_frames().join(index.to_string())
+    }
+
+    /// Upscaled frames of segment `index`.
+    pub fn out_frames_dir(&self, index: u32) -> PathBuf {
+        self.out_frames().join(index.to_string())
+    }
+
+    /// Frames of the current window in pipe mode.
+    pub fn window_dir(&self) -> PathBuf {
+        self.tmp_frames().join("window")
+    }
+
+    /// Source timestamps of the frames of segment `index`.
+    pub fn timestamps(&self, index: u32) -> PathBuf {
+        self.timestamps_dir().join(format!("{}.txt", index))
+    }
+
+    /// ffconcat list replaying the upscaled frames of segment `index` with their source timing.
+    pub fn frames_list(&self, index: u32) -> PathBuf {
+        self.timestamps_dir().join(format!("{}.ffconcat", index))
+    }
+
+    pub fn video_part(&self, index: u32, extension: &str) -> PathBuf {
+        self.video_parts().join(format!("{}.{}", index, extension))
+    }
+
+    /// Entry of `index` in parts.txt, relative to the workspace root.
+    pub fn video_part_entry(&self, index: u32, extension: &str) -> String {
+        Path::new("video_parts")
+            .join(format!("{}.{}", index, extension))
+            .display()
+            .to_string()
+    }
+
+    /// Progress of the upscale running in this workspace.
+    pub fn progress(&self) -> WorkspaceProgress {
+        // a segment's out_frames folder is created when its upscale starts and removed once it
+        // is merged, the previous segment may still be merging so take the highest one
+        let segment = fs::read_dir(self.out_frames()).ok().and_then(|entries| {
+            entries
+                .filter_map(|e| e.ok()?.file_name().to_str()?.parse::<u32>().ok())
+                .max()
+        });
+        WorkspaceProgress {
+            parts_done: count_files(&self.video_parts()),
+            segment,
+            frames_exported: segment.map_or(0, |i| count_files(&self.tmp_frames_dir(i))),
+            frames_upscaled: segment.map_or(0, |i| count_files(&self.out_frames_dir(i))),
+        }
+    }
+
+    /// Takes the workspace for this process until the lock is dropped, so two upscales never
+    /// remove each other's frames. A lock left by a process that died is taken over.
+    pub fn lock(&self) -> Result<WorkspaceLock, ReveError> {
+        fs::create_dir_all(&self.root)?;
+        let path = self.lock_file();
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(mut file) => {
+                    write!(file, "{}", std::process::id())?;
+                    return Ok(WorkspaceLock { path });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    let owner = fs::read_to_string(&path)
+                        .ok()
+                        .and_then(|pid| pid.trim().parse::<u32>().ok());
+                    if let Some(pid) = owner.filter(|pid| process_alive(*pid)) {
+                        return Err(ReveError::WorkspaceBusy {
+                            path: self.root.display().to_string(),
+                            pid,
+                        });
+                    }
+                    fs::remove_file(&path)?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Creates the workspace folders if they don't exist yet.
+    pub fn create(&self) -> Result<(), ReveError> {
+        for dir in [
+            self.tmp_frames(),
+            self.out_frames(),
+            self.video_parts(),
+            self.timestamps_dir(),
+        ] {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(())
+    }
+
+    /// Empties the workspace. With `keep_args` only the frame folders and parts.txt are removed,
+    /// so the settings and finished video parts stay around for resuming.
+    ///
+    /// Only the entries reve manages are touched, the root may be a user folder.
+    pub fn rebuild(&self, keep_args: bool) -> Result<(), ReveError> {
+        let mut dirs = vec![self.tmp_frames(), self.out_frames()];
+        if !keep_args {
+            dirs.push(self.video_parts());
+            dirs.push(self.timestamps_dir());
+            let _ = fs::remove_file(self.args_file());
+        }
+        for dir in dirs {
+            if dir.exists() {
+                fs::remove_dir_all(&dir)?;
+            }
+            fs::create_dir_all(dir)?;
+        }
+        let _ = fs::remove_file(self.parts_list());
+        Ok(())
+    }
+}
+
+/// Lock of a workspace taken with [`Workspace::lock`], released when dropped.
+#[derive(Debug)]
+pub struct WorkspaceLock {
+    path: PathBuf,
+}
+
+impl Drop for WorkspaceLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn count_files(dir: &Path) -> u32 {
+    fs::read_dir(dir)
+        .map(|entries| entries.filter_map(|e| e.ok()).count() as u32)
+        .unwrap_or(0)
+}
@@ -0,0 +1,135 @@
+use reve_shared::*;
+use std::fs;
+
+fn parse(args: &[&str], config: &ReveConfig) -> Args {
+    let input = std::env::temp_dir().display().to_string();
+    let mut cli_args = vec!["reve", "-i", &input];
+    cli_args.extend_from_slice(args);
+    Args::try_parse_with_config(cli_args, config, &Profiles::builtin()).unwrap()
+}
+
+#[test]
+fn flags_override_the_config_and_the_config_overrides_defaults() {
+    let config = ReveConfig {
+        crf: Some(20),
+        scale: Some(4),
+        workdir: Some(String::from("/mnt/fast")),
+        ..ReveConfig::default()
+    };
+
+    let args = parse(&[], &config);
+    assert_eq!(args.crf, 20);
+    assert_eq!(args.scale, 4);
+    assert_eq!(args.workdir.as_deref(), Some("/mnt/fast"));
+    assert_eq!(args.format, "mp4");
+
+    let args = parse(&["-c", "18", "--workdir", "/tmp/reve"], &config);
+    assert_eq!(args.crf, 18);
+    assert_eq!(args.scale, 4);
+    assert_eq!(args.workdir.as_deref(), Some("/tmp/reve"));
+}
+
+#[test]
+fn environment_overrides_the_config() {
+    let config = ReveConfig {
+        preset: Some(String::from("fast")),
+        ..ReveConfig::default()
+    };
+    std::env::set_var("REVE_PRESET", "veryslow");
+    let args = parse(&[], &config);
+    std::env::remove_var("REVE_PRESET");
+    assert_eq!(args.preset, "veryslow");
+}
+
+#[test]
+fn config_file_round_trips_and_rejects_invalid_values() {
+    let dir = std::env::temp_dir().join(format!("reve-config-test-{}", std::process::id()));
+    let path = dir.join("config.json");
+
+    assert_eq!(ReveConfig::load_from(&path).unwrap(), ReveConfig::default());
+
+    let config = ReveConfig {
+        model: Some(String::from("realesrgan-x4plus-anime")),
+        encoder_params: Some(String::from("bframes=4")),
+        ..ReveConfig::default()
+    };
+    config.save_to(&path).unwrap();
+    assert_eq!(ReveConfig::load_from(&path).unwrap(), config);
+
+    // segment-size auto is kept as 0, like --parts auto
+    fs::write(&path, r#"{ "segment-size": "auto" }"#).unwrap();
+    let config = ReveConfig::load_from(&path).unwrap();
+    assert_eq!(config.segment_size, Some(0));
+    assert_eq!(parse(&[], &config).segmentsize, 0);
+    config.save_to(&path).unwrap();
+    assert!(fs::read_to_string(&path)
+        .unwrap()
+        .contains(r#""segment-size": "auto""#));
+    fs::write(&path, r#"{ "segment-size": "half" }"#).unwrap();
+    assert!(ReveConfig::load_from(&path).is_err());
+
+    fs::write(&path, r#"{ "crf": 60 }"#).unwrap();
+    let err = ReveConfig::load_from(&path).unwrap_err();
+    assert!(matches!(err, ReveError::InvalidInput(_)));
+    assert!(err.to_string().contains("crf"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn profile_sits_between_the_flags_and_the_config() {
+    let config = ReveConfig {
+        crf: Some(22),
+        workdir: Some(String::from("/mnt/fast")),
+        ..ReveConfig::default()
+    };
+
+    let args = parse(&["--profile", "live-action"], &config);
+    assert_eq!(args.model, "realesrgan-x4plus");
+    assert_eq!(args.scale, 4);
+    assert_eq!(args.crf, 18);
+    assert_eq!(args.workdir.as_deref(), Some("/mnt/fast"));
+
+    let args = parse(&["--profile", "anime-fast", "-c", "16", "-s", "3"], &config);
+    assert_eq!(args.crf, 16);
+    assert_eq!(args.scale, 3);
+    assert_eq!(args.x265params, "bframes=8");
+
+    let input = std::env::temp_dir().display().to_string();
+    let err = Args::try_parse_with_config(
+        ["reve", "-i", &input, "--profile", "nope"],
+        &config,
+        &Profiles::builtin(),
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("anime-archive"));
+}
+
+#[test]
+fn profiles_file_adds_and_replaces_profiles() {
+    let dir = std::env::temp_dir().join(format!("reve-profiles-test-{}", std::process::id()));
+    let path = dir.join("profiles.json");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        &path,
+        r#"{ "anime-fast": { "crf": 24 }, "team": { "model": "realesrgan-x4plus-anime", "scale": 4 } }"#,
+    )
+    .unwrap();
+
+    let profiles = Profiles::load_from(&path).unwrap();
+    let names: Vec<&str> = profiles.list().iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(
+        names,
+        ["anime-archive", "anime-fast", "live-action", "team"]
+    );
+    let anime_fast = profiles.get("anime-fast").unwrap();
+    assert!(!anime_fast.builtin);
+    assert_eq!(anime_fast.settings.crf, Some(24));
+    assert_eq!(anime_fast.settings.preset, None);
+
+    fs::write(&path, r#"{ "team": { "scale": 5 } }"#).unwrap();
+    let err = Profiles::load_from(&path).err().unwrap();
+    assert!(err.to_string().contains("team: scale"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,67 @@
+use reve_shared::*;
+use std::fs;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn cancelled_job_stops_at_checkpoint() {
+    let control = JobControl::new();
+    assert!(control.checkpoint().is_ok());
+    control.cancel();
+    assert!(control.is_cancelled());
+    assert!(matches!(control.checkpoint(), Err(ReveError::Cancelled)));
+}
+
+#[test]
+fn paused_job_waits_for_resume() {
+    let control = JobControl::new();
+    control.pause();
+    assert!(control.is_paused());
+
+    let (sender, receiver) = mpsc::channel();
+    let waiting = control.clone();
+    thread::spawn(move || sender.send(waiting.checkpoint().is_ok()).unwrap());
+    assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+
+    control.resume();
+    assert!(receiver.recv_timeout(Duration::from_secs(5)).unwrap());
+}
+
+#[cfg(unix)]
+#[test]
+fn cancel_kills_running_upscaler() {
+    let dir = std::env::temp_dir().join("reve-control-test");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("in")).unwrap();
+    fs::create_dir_all(dir.join("out")).unwrap();
+    fs::write(dir.join("in").join("frames.txt"), b"").unwrap();
+    fs::write(dir.join("out").join("frames.txt"), b"").unwrap();
+
+    // never exits on its own
+    let upscaler = get_upscaler(
+        "tail -qf {input_dir}/frames.txt {output_dir}/frames.txt",
+        "done",
+    )
+    .unwrap();
+    let control = JobControl::new();
+    let canceller = control.clone();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(300));
+        canceller.pause();
+        canceller.cancel();
+    });
+
+    let result = upscale_frames(
+        upscaler.as_ref(),
+        &dir.join("in").display().to_string(),
+        &dir.join("out").display().to_string(),
+        2,
+        &String::from("model"),
+        &plan_segments(10, 10)[0],
+        &NoProgress,
+        &control,
+    );
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(matches!(result, Err(ReveError::Cancelled)));
+}
@@ -0,0 +1,32 @@
+use reve_shared::*;
+
+#[test]
+fn unknown_upscaler_is_invalid_input() {
+    match get_upscaler("not-an-upscaler", "done") {
+        Err(ReveError::InvalidInput(_)) => (),
+        Err(e) => panic!("unexpected error {:?}", e),
+        Ok(_) => panic!("parsed an invalid upscaler"),
+    }
+}
+
+#[test]
+fn existing_output_is_an_error() {
+    let dir = std::env::temp_dir().join("reve-error-test");
+    std::fs::create_dir_all(&dir).unwrap();
+    let output = dir.join("out.mp4");
+    std::fs::write(&output, b"").unwrap();
+
+    let result = output_validation(&output.display().to_string());
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(result.unwrap_err().contains("already exists"));
+}
+
+#[test]
+fn probing_a_missing_file_fails() {
+    // MissingTool without ffprobe installed, Probe with it
+    match probe_media("/nonexistent/video.mkv") {
+        Err(ReveError::MissingTool { .. }) | Err(ReveError::Probe { .. }) => (),
+        Err(e) => panic!("unexpected error {:?}", e),
+        Ok(_) => panic!("probed a missing file"),
+    }
+}
@@ -0,0 +1,273 @@
+use indicatif::ProgressBar;
+use reve_shared::*;
+use rusqlite::{params, Connection};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::PathBuf;
+
+fn library_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("reve-library-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn write_video(path: &PathBuf, content: &[u8]) -> String {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+    path.display().to_string()
+}
+
+fn probe(height: i64) -> Value {
+    json!({
+        "format": { "duration": "1425.5", "size": "4", "bit_rate": "1000", "format_name": "matroska,webm" },
+        "streams": [{ "width": 640, "height": height, "codec_name": "h264", "pix_fmt": "yuv420p" }]
+    })
+}
+
+fn record(conn: &Connection, path: &str) -> LibraryRecord {
+    let fingerprint = fingerprint_file(path, 1425.5).unwrap();
+    record_video(conn, path, &fingerprint, &probe(480), 480).unwrap()
+}
+
+fn entries(conn: &Connection) -> Vec<String> {
+    let mut stmt = conn
+        .prepare("SELECT filepath FROM video_info ORDER BY filepath")
+        .unwrap();
+    let rows = stmt.query_map(params![], |row| row.get(0)).unwrap();
+    rows.map(|row| row.unwrap()).collect()
+}
+
+#[test]
+fn fingerprint_follows_content_not_name() {
+    let dir = library_dir("fingerprint");
+    let a = write_video(&dir.join("a.mkv"), b"episode one");
+    let b = write_video(&dir.join("b.mkv"), b"episode one");
+    let c = write_video(&dir.join("c.mkv"), b"episode two");
+
+    let fingerprint = fingerprint_file(&a, 1425.5).unwrap();
+    assert_eq!(fingerprint, fingerprint_file(&b, 1425.5).unwrap());
+    assert_ne!(fingerprint, fingerprint_file(&c, 1425.5).unwrap());
+    assert_ne!(fingerprint, fingerprint_file(&a, 1300.0).unwrap());
+    assert!(fingerprint.ends_with("-11-1425500"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn same_name_in_two_folders_is_two_entries() {
+    let dir = library_dir("names");
+    let conn = Connection::open_in_memory().unwrap();
+    migrate_db(&conn).unwrap();
+    let first = write_video(&dir.join("Show A/Episode 01.mkv"), b"show a episode 1");
+    let second = write_video(&dir.join("Show B/Episode 01.mkv"), b"show b episode 1");
+
+    assert_eq!(record(&conn, &first), LibraryRecord::Added);
+    assert_eq!(record(&conn, &second), LibraryRecord::Added);
+    assert_eq!(record(&conn, &first), LibraryRecord::Known);
+    assert_eq!(entries(&conn), [first, second]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn moved_file_keeps_its_entry_and_copies_are_not_added() {
+    let dir = library_dir("moves");
+    let conn = Connection::open_in_memory().unwrap();
+    migrate_db(&conn).unwrap();
+    let old = write_video(&dir.join("incoming/ep1.mkv"), b"episode one");
+    assert_eq!(record(&conn, &old), LibraryRecord::Added);
+    update_db_status(&conn, &old, "done").unwrap();
+
+    let new = write_video(&dir.join("Show/Episode 01.mkv"), b"episode one");
+    fs::remove_file(&old).unwrap();
+    assert_eq!(
+        record(&conn, &new),
+        LibraryRecord::Moved { from: old.clone() }
+    );
+    assert_eq!(get_db_status(&conn, &new).unwrap().as_deref(), Some("done"));
+
+    let copy = write_video(&dir.join("backup/Episode 01.mkv"), b"episode one");
+    assert_eq!(
+        record(&conn, &copy),
+        LibraryRecord::Copy { of: new.clone() }
+    );
+    assert_eq!(entries(&conn), [new]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn only_new_and_unfinished_videos_are_upscaled() {
+    let dir = library_dir("scan");
+    let db = dir.join("reve.db");
+    let conn = open_db(&db).unwrap();
+    let done = write_video(&dir.join("done.mkv"), b"episode one");
+    let pending = write_video(&dir.join("pending.mkv"), b"episode two");
+    let moved = write_video(&dir.join("incoming/ep3.mkv"), b"episode three");
+    assert_eq!(record(&conn, &done), LibraryRecord::Added);
+    assert_eq!(record(&conn, &pending), LibraryRecord::Added);
+    assert_eq!(record(&conn, &moved), LibraryRecord::Added);
+    update_db_status(&conn, &done, "done").unwrap();
+    update_db_status(&conn, &moved, "processing").unwrap();
+
+    let moved_to = write_video(&dir.join("Show/ep3.mkv"), b"episode three");
+    fs::remove_file(&moved).unwrap();
+    let copy = write_video(&dir.join("backup/done.mkv"), b"episode one");
+    let new = write_video(&dir.join("new.mkv"), b"episode four");
+    let tall = write_video(&dir.join("tall.mkv"), b"episode five");
+
+    let files = vec![
+        done,
+        pending.clone(),
+        moved_to.clone(),
+        copy,
+        new.clone(),
+        tall.clone(),
+    ];
+    let (summary, mut to_process) =
+        add_files_to_db(conn, files, 480, ProgressBar::hidden(), |file| {
+            Ok(probe(if file == tall { 1080 } else { 480 }))
+        })
+        .unwrap();
+    to_process.sort();
+    let mut expected = vec![pending, moved_to, new];
+    expected.sort();
+    assert_eq!(to_process, expected);
+    assert_eq!(
+        summary,
+        AddSummary {
+            added: 2,
+            known: 2,
+            moved: 1,
+            copies: 1,
+            skipped: 1,
+        }
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_new_video_at_a_known_path_replaces_the_entry() {
+    let dir = library_dir("replace");
+    let conn = Connection::open_in_memory().unwrap();
+    migrate_db(&conn).unwrap();
+    let path = write_video(&dir.join("ep1.mkv"), b"episode one");
+    assert_eq!(record(&conn, &path), LibraryRecord::Added);
+    update_db_status(&conn, &path, "done").unwrap();
+
+    // a new release saved over the old one
+    write_video(&dir.join("ep1.mkv"), b"episode one v2");
+    assert_eq!(record(&conn, &path), LibraryRecord::Replaced);
+    assert_eq!(entries(&conn), std::slice::from_ref(&path));
+    assert_eq!(
+        get_db_status(&conn, &path).unwrap().as_deref(),
+        Some("pending")
+    );
+    assert_eq!(record(&conn, &path), LibraryRecord::Known);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn entries_without_fingerprint_get_one_by_path() {
+    let dir = library_dir("backfill");
+    let conn = Connection::open_in_memory().unwrap();
+    migrate_db(&conn).unwrap();
+    let path = write_video(&dir.join("ep1.mkv"), b"episode one");
+    conn.execute(
+        "INSERT INTO video_info (filename, filepath, width, height, duration, pixel_format,
+         display_aspect_ratio, sample_aspect_ratio, format, size, folder_size, bitrate, codec,
+         resolution, status, hash) VALUES ('ep1.mkv', ?1, 640, 480, 1425.5, 'yuv420p', '4:3',
+         '1:1', 'matroska,webm', 11, 11, 1000, 'h264', '480', 'done', 'NaN')",
+        params![path],
+    )
+    .unwrap();
+
+    assert_eq!(record(&conn, &path), LibraryRecord::Known);
+    let fingerprint: Option<String> = conn
+        .query_row("SELECT fingerprint FROM video_info", params![], |row| {
+            row.get(0)
+        })
+        .unwrap();
+    assert_eq!(fingerprint, Some(fingerprint_file(&path, 1425.5).unwrap()));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn videos_are_found_by_fingerprint_or_old_path() {
+    let dir = library_dir("find");
+    let conn = Connection::open_in_memory().unwrap();
+    migrate_db(&conn).unwrap();
+    let path = write_video(&dir.join("ep1.mkv"), b"episode one");
+    let fingerprint = fingerprint_file(&path, 1425.5).unwrap();
+    assert_eq!(find_video(&conn, &path, &fingerprint).unwrap(), None);
+
+    conn.execute(
+        "INSERT INTO video_info (filename, filepath, width, height, duration, pixel_format,
+         display_aspect_ratio, sample_aspect_ratio, format, size, folder_size, bitrate, codec,
+         resolution, status, hash) VALUES ('ep1.mkv', ?1, 640, 480, 1425.5, 'yuv420p', '4:3',
+         '1:1', 'matroska,webm', 11, 11, 1000, 'h264', '480', 'done', 'NaN')",
+        params![path],
+    )
+    .unwrap();
+    let video = find_video(&conn, &path, &fingerprint).unwrap().unwrap();
+    assert_eq!((video.status.as_str(), video.fingerprint), ("done", None));
+
+    let moved = write_video(&dir.join("Show/Episode 01.mkv"), b"episode one");
+    assert_eq!(record(&conn, &path), LibraryRecord::Known);
+    let video = find_video(&conn, &moved, &fingerprint).unwrap().unwrap();
+    assert_eq!(video.filepath, path);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn db_commands_list_reset_and_forget_videos() {
+    let dir = library_dir("db");
+    let conn = Connection::open_in_memory().unwrap();
+    migrate_db(&conn).unwrap();
+    let first = write_video(&dir.join("Show A/Episode 01.mkv"), b"show a episode 1");
+    let second = write_video(&dir.join("Show A/Episode 02.mkv"), b"show a episode 2");
+    let other = write_video(&dir.join("Show B/Episode, \"01\".mkv"), b"show b episode 1");
+    for path in [&first, &second, &other] {
+        record(&conn, path);
+    }
+    update_db_status(&conn, &first, "processing").unwrap();
+    update_db_status(&conn, &second, "done").unwrap();
+
+    let show_a = LibraryFilter {
+        folder: Some(dir.join("Show A").display().to_string()),
+        ..LibraryFilter::default()
+    };
+    assert_eq!(list_videos(&conn, &show_a).unwrap().len(), 2);
+    let processing = LibraryFilter {
+        status: Some(String::from("processing")),
+        ..LibraryFilter::default()
+    };
+    assert_eq!(list_videos(&conn, &processing).unwrap()[0].filepath, first);
+
+    let stats = library_stats(&conn).unwrap();
+    assert_eq!(stats.videos, 3);
+    assert_eq!(stats.by_status["pending"], 1);
+    assert_eq!(stats.by_codec["h264"], 3);
+    assert_eq!(stats.duration, 3.0 * 1425.5);
+
+    assert_eq!(reset_db_status(&conn, "processing", "pending").unwrap(), 1);
+    assert_eq!(
+        get_db_status(&conn, &first).unwrap().as_deref(),
+        Some("pending")
+    );
+
+    let csv = videos_to_csv(&list_videos(&conn, &LibraryFilter::default()).unwrap());
+    assert!(csv.starts_with("id,filepath,"));
+    assert!(csv.contains("Episode, \"\"01\"\".mkv\","));
+
+    let forgotten = forget_videos(&conn, &dir.join("Show A").display().to_string()).unwrap();
+    assert_eq!(forgotten.len(), 2);
+    assert_eq!(entries(&conn), [other]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,72 @@
+use reve_shared::*;
+use rusqlite::{params, Connection};
+
+fn user_version(conn: &Connection) -> u32 {
+    conn.query_row("PRAGMA user_version", params![], |row| row.get(0))
+        .unwrap()
+}
+
+fn column_type(conn: &Connection, table: &str, column: &str) -> String {
+    conn.query_row(
+        "SELECT type FROM pragma_table_info(?1) WHERE name = ?2",
+        params![table, column],
+        |row| row.get(0),
+    )
+    .unwrap()
+}
+
+#[test]
+fn new_database_gets_every_migration_once() {
+    let conn = Connection::open_in_memory().unwrap();
+    migrate_db(&conn).unwrap();
+    assert_eq!(user_version(&conn), SCHEMA_VERSION);
+    assert_eq!(column_type(&conn, "video_info", "duration"), "REAL");
+    assert_eq!(column_type(&conn, "jobs", "settings"), "TEXT");
+
+    migrate_db(&conn).unwrap();
+    assert_eq!(user_version(&conn), SCHEMA_VERSION);
+}
+
+#[test]
+fn unversioned_video_info_with_text_columns_is_converted() {
+    let conn = Connection::open_in_memory().unwrap();
+    // the table as the old create_db_table made it
+    conn.execute_batch(
+        "CREATE TABLE video_info (
+            id INTEGER PRIMARY KEY, filename TEXT NOT NULL, filepath TEXT NOT NULL,
+            width INTEGER NOT NULL, height INTEGER NOT NULL, duration TEXT NOT NULL,
+            pixel_format TEXT NOT NULL, display_aspect_ratio TEXT NOT NULL,
+            sample_aspect_ratio TEXT NOT NULL, format TEXT NOT NULL, size TEXT NOT NULL,
+            folder_size INTEGER NOT NULL, bitrate TEXT NOT NULL, codec TEXT NOT NULL,
+            resolution TEXT NOT NULL, status TEXT NOT NULL, hash TEXT NOT NULL
+        );
+        INSERT INTO video_info VALUES (7, 'a.mkv', '/videos/a.mkv', 640, 480, '1425.5',
+            'yuv420p', '4:3', '1:1', 'matroska,webm', '734003200', 900000000, '4119000',
+            'h264', '480', 'done', 'abc');",
+    )
+    .unwrap();
+
+    migrate_db(&conn).unwrap();
+
+    assert_eq!(column_type(&conn, "video_info", "size"), "BIGINT");
+    let (duration, size, bitrate): (f64, i64, i64) = conn
+        .query_row(
+            "SELECT duration, size, bitrate FROM video_info WHERE id = 7",
+            params![],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .unwrap();
+    assert_eq!((duration, size, bitrate), (1425.5, 734003200, 4119000));
+    assert_eq!(
+        get_db_status(&conn, "/videos/a.mkv").unwrap().as_deref(),
+        Some("done")
+    );
+}
+
+#[test]
+fn database_of_a_newer_reve_is_refused() {
+    let conn = Connection::open_in_memory().unwrap();
+    conn.execute_batch(&format!("PRAGMA user_version = {}", SCHEMA_VERSION + 1))
+        .unwrap();
+    assert!(matches!(migrate_db(&conn), Err(ReveError::InvalidInput(_))));
+}
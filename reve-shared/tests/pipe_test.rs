@@ -0,0 +1,171 @@
+use reve_shared::*;
+use std::fs;
+use std::io::{Cursor, ErrorKind, Read};
+use std::path::Path;
+use std::process::Command;
+
+fn write_png(path: &Path, color: png::ColorType, depth: png::BitDepth, data: &[u8]) {
+    let file = fs::File::create(path).unwrap();
+    let mut encoder = png::Encoder::new(file, 2, 1);
+    encoder.set_color(color);
+    encoder.set_depth(depth);
+    if color == png::ColorType::Indexed {
+        encoder.set_palette(vec![10, 20, 30, 40, 50, 60]);
+    }
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(data).unwrap();
+}
+
+#[test]
+fn frames_are_read_whole_across_short_reads() {
+    // the pipe hands out the frame in pieces
+    let mut reader = Cursor::new(vec![1, 2]).chain(Cursor::new(vec![3, 4, 5, 6]));
+    let mut frame = [0u8; 3];
+    assert!(read_frame(&mut reader, &mut frame).unwrap());
+    assert_eq!(frame, [1, 2, 3]);
+    assert!(read_frame(&mut reader, &mut frame).unwrap());
+    assert_eq!(frame, [4, 5, 6]);
+    // a clean end of stream is the end of the segment
+    assert!(!read_frame(&mut reader, &mut frame).unwrap());
+}
+
+#[test]
+fn a_truncated_frame_is_an_error() {
+    let mut frame = [0u8; 3];
+    let error = read_frame(&mut Cursor::new(vec![1, 2]), &mut frame).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn upscaled_pngs_are_read_as_rgb24() {
+    let dir = std::env::temp_dir().join("reve-pipe-png-test");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    use png::BitDepth::{Eight, Sixteen};
+    use png::ColorType::*;
+    let cases: [(png::ColorType, png::BitDepth, Vec<u8>); 6] = [
+        (Rgb, Eight, vec![1, 2, 3, 4, 5, 6]),
+        (Rgba, Eight, vec![1, 2, 3, 255, 4, 5, 6, 0]),
+        (Grayscale, Eight, vec![7, 8]),
+        (GrayscaleAlpha, Eight, vec![7, 255, 8, 0]),
+        (Rgb, Sixteen, vec![1, 0, 2, 0, 3, 0, 4, 0, 5, 0, 6, 0]),
+        (Indexed, Eight, vec![0, 1]),
+    ];
+    let expected: [&[u8]; 6] = [
+        &[1, 2, 3, 4, 5, 6],
+        &[1, 2, 3, 4, 5, 6],
+        &[7, 7, 7, 8, 8, 8],
+        &[7, 7, 7, 8, 8, 8],
+        &[1, 2, 3, 4, 5, 6],
+        &[10, 20, 30, 40, 50, 60],
+    ];
+    for (i, ((color, depth, data), expected)) in cases.iter().zip(expected).enumerate() {
+        let path = dir.join(format!("{}.png", i));
+        write_png(&path, *color, *depth, data);
+        assert_eq!(
+            read_png(&path).unwrap(),
+            expected,
+            "{:?} {:?}",
+            color,
+            depth
+        );
+    }
+
+    fs::write(dir.join("broken.png"), "not a png").unwrap();
+    let error = read_png(&dir.join("broken.png")).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// A 64x48 video of `frames` frames at 25 fps.
+fn test_video(dir: &Path, frames: u32) -> String {
+    let video = dir.join("source.mkv");
+    let status = Command::new("ffmpeg")
+        .args([
+            "-v",
+            "error",
+            "-y",
+            "-f",
+            "lavfi",
+            "-i",
+            "testsrc=s=64x48:r=25",
+        ])
+        .args(["-frames:v", &frames.to_string(), "-c:v", "ffv1"])
+        .arg(&video)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    video.display().to_string()
+}
+
+fn run_segment(dir: &Path, upscaler: &dyn Upscaler, window: usize) -> Result<(), ReveError> {
+    let input = test_video(dir, 10);
+    let output = dir.join("part.mkv").display().to_string();
+    let window_dir = dir.join("window").display().to_string();
+    let segment = &plan_segments(10, 10)[0];
+    let profile = get_encoder_profile("libx264").unwrap();
+    let control = JobControl::new();
+    PipeSegment {
+        input_path: &input,
+        output_path: &output,
+        segment,
+        extract: ExtractMode::Exact,
+        width: 64,
+        height: 48,
+        frame_rate: "25",
+        scale: 1,
+        model: REALESRGAN_MODEL,
+        profile: &profile,
+        crf: 20,
+        preset: "ultrafast",
+        params: "",
+        window_dir: &window_dir,
+        window,
+        control: &control,
+    }
+    .run(upscaler, &NoProgress)
+}
+
+#[test]
+#[ignore = "needs ffmpeg and cp"]
+fn folder_upscalers_get_the_frames_a_window_at_a_time() {
+    let dir = std::env::temp_dir().join("reve-pipe-window-test");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let upscaler = CommandTemplate::parse("cp", "cp -r {input_dir}/. {output_dir}", "").unwrap();
+
+    run_segment(&dir, &upscaler, 4).unwrap();
+    let (frames, _) = count_frames(&dir.join("part.mkv").display().to_string()).unwrap();
+    assert_eq!(frames, 10);
+    // the window is removed with the segment
+    assert!(!dir.join("window").exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+#[ignore = "needs ffmpeg and sh"]
+fn a_failing_streaming_upscaler_stops_the_segment() {
+    let dir = std::env::temp_dir().join("reve-pipe-stream-test");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    // `sh -c cat 64 48` passes the frames through
+    let upscaler = CommandTemplate::parse("cat", "sh -c cat {width} {height}", "").unwrap();
+    run_segment(&dir, &upscaler, 4).unwrap();
+
+    // half a frame then a failure
+    let upscaler = CommandTemplate::parse(
+        "broken",
+        "sh -c head${IFS}-c4608;exit${IFS}3 {width} {height}",
+        "",
+    )
+    .unwrap();
+    match run_segment(&dir, &upscaler, 4) {
+        Err(ReveError::Upscaler { .. }) => (),
+        other => panic!("{:?}", other),
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
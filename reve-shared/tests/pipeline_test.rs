@@ -0,0 +1,96 @@
+use clap::Parser;
+use reve_shared::*;
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn args_convert_into_a_job_spec() {
+    let args = Args::parse_from([
+        "reve",
+        "-i",
+        ".",
+        "-s",
+        "4",
+        "--crf=18",
+        "--encoder=libx264",
+        "--parts=500",
+        "--x265params=bframes=4",
+        "--pipe",
+    ]);
+    let spec = JobSpec::from(&args);
+    assert_eq!(
+        spec,
+        JobSpec::new(".")
+            .scale(4)
+            .crf(18)
+            .encoder("libx264")
+            .segment_size(500)
+            .encoder_params("bframes=4")
+            .pipe(true, 16)
+    );
+}
+
+#[test]
+fn parts_auto_is_a_segment_size_of_zero() {
+    let args = Args::parse_from(["reve", "-i", ".", "--parts", "auto"]);
+    assert_eq!(JobSpec::from(&args).segment_size, 0);
+    assert!(args.to_cli_args().contains(&String::from("--parts=auto")));
+    assert!(Args::try_parse_from(["reve", "-i", ".", "--parts", "0"]).is_err());
+}
+
+#[test]
+fn only_the_same_segments_resume_a_workspace() {
+    let previous = JobSpec::new("/videos/a.mkv").scale(4).segment_size(500);
+    assert!(previous.clone().resumes(&previous));
+    assert!(!previous.clone().segment_size(1000).resumes(&previous));
+    assert!(!previous
+        .clone()
+        .extract(ExtractMode::Seek)
+        .resumes(&previous));
+    // --parts auto goes on with the segments already cut
+    assert!(previous.clone().segment_size(0).resumes(&previous));
+    assert!(!previous.clone().scale(2).resumes(&previous));
+}
+
+#[test]
+fn output_defaults_to_the_input_folder() {
+    let spec = JobSpec::new("/videos/show/episode 01.mkv").format("mkv");
+    assert_eq!(spec.output_path(), "/videos/show/episode 01.libx265.mkv");
+    assert_eq!(spec.output("/out/01.mkv").output_path(), "/out/01.mkv");
+}
+
+#[test]
+fn progress_events_serialize_with_their_kind() {
+    let event = ProgressEvent::Frames {
+        stage: Stage::Upscale,
+        segment: 3,
+        done: 10,
+        total: 1000,
+        fps: 2.5,
+    };
+    let json = serde_json::to_value(&event).unwrap();
+    assert_eq!(json["event"], "frames");
+    assert_eq!(json["stage"], "upscale");
+    assert_eq!(json["done"], 10);
+}
+
+#[test]
+fn closures_receive_progress_events() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let sink = {
+        let events = events.clone();
+        move |event: ProgressEvent| events.lock().unwrap().push(event)
+    };
+    let pipeline = Pipeline::new(
+        JobSpec::new("/nonexistent/video.mkv").workdir(
+            &std::env::temp_dir()
+                .join("reve-progress-test")
+                .display()
+                .to_string(),
+        ),
+    )
+    .progress(Arc::new(sink));
+
+    assert!(pipeline.run().is_err());
+    let events = events.lock().unwrap();
+    assert!(matches!(events.last(), Some(ProgressEvent::Error { .. })));
+}
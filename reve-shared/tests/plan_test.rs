@@ -0,0 +1,125 @@
+use reve_shared::*;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+fn args(input: &str, extra: &[&str]) -> Args {
+    let mut cli_args = vec!["reve", "-i", input];
+    cli_args.extend_from_slice(extra);
+    Args::try_parse_with_config(cli_args, &ReveConfig::default(), &Profiles::builtin()).unwrap()
+}
+
+#[test]
+fn temp_space_counts_two_segments_without_pipe() {
+    let spec = JobSpec::new("/videos/a.mkv").scale(2).segment_size(1000);
+    let estimate = SpaceEstimate::new(&spec, 640, 480, 30000, 100_000_000);
+    // 2 x 1000 frames of 640x480 and of 1280x960 at 1.5 bytes per pixel
+    assert_eq!(estimate.frames, 2 * 1000 * (640 * 480 + 1280 * 960) * 3 / 2);
+    assert_eq!(estimate.parts, 400_000_000);
+    assert_eq!(estimate.total(), estimate.frames + estimate.parts);
+
+    // a short video never has a full segment
+    let short = SpaceEstimate::new(&spec, 640, 480, 10, 100_000_000);
+    assert_eq!(short.frames, estimate.frames / 100);
+
+    let piped = SpaceEstimate::new(&spec.pipe(true, 16), 640, 480, 30000, 100_000_000);
+    assert_eq!(piped.frames, 16 * (640 * 480 + 1280 * 960) * 3 / 2);
+}
+
+#[test]
+fn folder_outputs_are_named_after_the_encoder() {
+    let dir = std::env::temp_dir().display().to_string();
+    let file = Path::new(&dir).join("Show/Episode 01.mkv");
+    let args = args(&dir, &["-e", "libx264", "-f", "mkv"]);
+    assert_eq!(
+        batch_output_path(&args, &file.display().to_string()),
+        Path::new(&dir)
+            .join("Show/Episode 01.libx264.mkv")
+            .display()
+            .to_string()
+    );
+}
+
+#[test]
+fn files_that_cant_be_probed_are_reported_not_fatal() {
+    let dir = std::env::temp_dir().join("reve_plan_unreadable_test");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("broken.mkv"), "").unwrap();
+    fs::write(dir.join("truncated.mp4"), "not a video").unwrap();
+
+    let input = dir.display().to_string();
+    let db = dir.join("missing.db");
+    let plans = plan_files_with_db(&args(&input, &[]), &db).unwrap();
+    assert_eq!(plans.len(), 2);
+    for plan in &plans {
+        assert!(plan.skip.is_some(), "{:?}", plan);
+        assert_eq!(plan.frames, 0);
+    }
+    // the missing library isn't created
+    assert!(!db.exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+#[ignore = "needs ffmpeg and ffprobe"]
+fn dry_run_plans_a_folder_without_upscaling() {
+    let dir = std::env::temp_dir().join("reve_plan_test");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("backup")).unwrap();
+    let video = dir.join("a.mkv");
+    let status = Command::new("ffmpeg")
+        .args(["-v", "error", "-f", "lavfi", "-i", "testsrc=s=64x48:r=25"])
+        .args(["-frames:v", "60", "-c:v", "ffv1"])
+        .arg(&video)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    fs::copy(&video, dir.join("backup/a.mkv")).unwrap();
+
+    let input = dir.display().to_string();
+    let plans = plan_files(&args(&input, &["-P", "25", "-f", "mkv"])).unwrap();
+    assert_eq!(plans.len(), 2);
+    let plan = plans.iter().find(|plan| plan.skip.is_none()).unwrap();
+    assert_eq!(plan.frames, 60);
+    assert_eq!((plan.segments, plan.last_segment_size), (3, 10));
+    assert!(plan.output.ends_with("a.libx265.mkv"));
+    assert!(plans.iter().any(|plan| plan
+        .skip
+        .as_deref()
+        .is_some_and(|s| s.starts_with("copy of"))));
+
+    let plans = plan_files(&args(&input, &["-r", "40", "-f", "mkv"])).unwrap();
+    assert_eq!(plans[0].skip.as_deref(), Some("taller than 40p"));
+
+    // a video the library upscaled already is skipped, the database is only read
+    let db = std::env::temp_dir().join("reve_plan_test.db");
+    let _ = fs::remove_file(&db);
+    let conn = open_db(&db).unwrap();
+    let file = video.display().to_string();
+    let probe = get_ffprobe_output(&file).unwrap();
+    let duration = probe["format"]["duration"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    let fingerprint = fingerprint_file(&file, duration).unwrap();
+    record_video(&conn, &file, &fingerprint, &probe, 480).unwrap();
+    update_db_status(&conn, &file, "done").unwrap();
+    drop(conn);
+    let modified = fs::metadata(&db).unwrap().modified().unwrap();
+    let plans = plan_files_with_db(&args(&input, &["-f", "mkv"]), &db).unwrap();
+    assert!(plans.iter().all(|plan| plan.skip.is_some()));
+    // the copy walked first is skipped for the library, the other as its copy
+    assert!(plans.iter().any(|plan| plan
+        .skip
+        .as_deref()
+        .is_some_and(|s| s == "already upscaled" || s == format!("known as {}", file))));
+    assert_eq!(fs::metadata(&db).unwrap().modified().unwrap(), modified);
+    fs::remove_file(&db).unwrap();
+    // nothing but the two videos was written
+    assert_eq!(walk_count(&input), 2);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,54 @@
+use reve_shared::*;
+
+#[test]
+fn constant_frame_rate_is_not_vfr() {
+    // 23.976 fps stored with millisecond timestamps
+    let mut timestamps: Vec<f64> = (0..200)
+        .map(|n| (n as f64 * 1001.0 / 24000.0 * 1000.0).round() / 1000.0)
+        .collect();
+    assert!(!is_vfr(&mut timestamps));
+}
+
+#[test]
+fn varying_frame_durations_are_vfr() {
+    let mut timestamps = vec![0.0, 0.033, 0.066, 0.1, 0.2, 0.233, 0.5, 0.533];
+    assert!(is_vfr(&mut timestamps));
+}
+
+#[test]
+fn nan_timestamps_do_not_panic() {
+    let mut timestamps = vec![0.0, f64::NAN, 0.033, 0.066];
+    is_vfr(&mut timestamps);
+}
+
+#[test]
+fn last_frame_of_a_segment_lasts_until_the_next_segment() {
+    let index = FrameIndex {
+        pts: vec![0.0, 0.04, 0.1, 0.3, 0.34],
+        keyframes: vec![true; 5],
+    };
+    let segments = plan_segments(5, 3);
+    let duration = index.last_frame_duration(&segments[0], 0.5).unwrap();
+    assert!((duration - 0.2).abs() < 1e-9);
+    // the last segment ends with the container
+    let duration = index.last_frame_duration(&segments[1], 0.5).unwrap();
+    assert!((duration - 0.16).abs() < 1e-9);
+    assert_eq!(
+        index.last_frame_duration(&plan_segments(8, 8)[0], 0.5),
+        None
+    );
+}
+
+#[test]
+fn packets_are_indexed_in_presentation_order() {
+    // B-frames come after the frame they are shown before
+    let index = FrameIndex::from_packets("0.000000,K__\n0.120000,___\n0.040000,___\n\n").unwrap();
+    assert_eq!(index.pts, [0.0, 0.04, 0.12]);
+    assert_eq!(index.keyframes, [true, false, false]);
+}
+
+#[test]
+fn packets_without_pts_are_not_indexed() {
+    let error = FrameIndex::from_packets("0.000000,K__\nN/A,___\n0.080000,___\n").unwrap_err();
+    assert!(error.contains("packet 1"), "{}", error);
+}
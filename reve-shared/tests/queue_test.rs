@@ -0,0 +1,129 @@
+use clap::Parser;
+use reve_shared::*;
+use rusqlite::{params, Connection};
+use std::process::{Command, Stdio};
+
+fn settings(args: &[&str]) -> Args {
+    let input = std::env::temp_dir().display().to_string();
+    let mut cli_args = vec!["reve", "-i", &input];
+    cli_args.extend_from_slice(args);
+    Args::parse_from(cli_args)
+}
+
+#[test]
+fn jobs_run_by_priority_with_their_own_settings() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    migrate_db(&conn).unwrap();
+
+    let low = enqueue(&conn, "/videos/a.mkv", None, &settings(&["-s", "2"]), 0).unwrap();
+    let high = enqueue(
+        &conn,
+        "/videos/b.mkv",
+        Some("/out/b.mkv"),
+        &settings(&["-s", "4", "-c", "20"]),
+        5,
+    )
+    .unwrap();
+
+    let job = dequeue_next(&mut conn).unwrap().unwrap();
+    assert_eq!(job.id, high);
+    assert_eq!(job.state, JobState::Running);
+    assert_eq!(job.attempts, 1);
+    assert_eq!(job.settings.scale, 4);
+    assert_eq!(job.settings.crf, 20);
+    assert_eq!(job.settings.inputpath.as_deref(), Some("/videos/b.mkv"));
+    assert_eq!(job.settings.outputpath.as_deref(), Some("/out/b.mkv"));
+    mark_done(&conn, job.id).unwrap();
+
+    let job = dequeue_next(&mut conn).unwrap().unwrap();
+    assert_eq!(job.id, low);
+    assert_eq!(job.settings.scale, 2);
+    mark_failed(&conn, job.id, "reve exited with 1").unwrap();
+
+    assert!(dequeue_next(&mut conn).unwrap().is_none());
+    let failed = get_job(&conn, low).unwrap().unwrap();
+    assert_eq!(failed.state, JobState::Failed);
+    assert_eq!(failed.error.as_deref(), Some("reve exited with 1"));
+}
+
+#[test]
+fn reorder_remove_and_requeue() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    migrate_db(&conn).unwrap();
+    let first = enqueue(&conn, "/videos/a.mkv", None, &settings(&[]), 0).unwrap();
+    let second = enqueue(&conn, "/videos/b.mkv", None, &settings(&[]), 0).unwrap();
+    let third = enqueue(&conn, "/videos/c.mkv", None, &settings(&[]), 0).unwrap();
+
+    assert!(set_job_priority(&conn, third, 10).unwrap());
+    assert!(remove_job(&conn, first).unwrap());
+    assert!(!remove_job(&conn, first).unwrap());
+    let ids: Vec<i64> = list_jobs(&conn).unwrap().iter().map(|j| j.id).collect();
+    assert_eq!(ids, vec![third, second]);
+
+    // the job of a runner that is still going, this test, is left to it
+    let job = dequeue_next(&mut conn).unwrap().unwrap();
+    assert_eq!(job.owner, Some(std::process::id()));
+    assert!(!remove_job(&conn, job.id).unwrap());
+    assert_eq!(requeue_interrupted(&conn).unwrap(), 0);
+
+    // a runner that died leaves its job running, the next one picks it up again
+    let mut runner = Command::new(std::env::current_exe().unwrap())
+        .arg("--list")
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    let dead = runner.id();
+    runner.wait().unwrap();
+    conn.execute(
+        "UPDATE jobs SET owner = ?1 WHERE id = ?2",
+        params![dead, job.id],
+    )
+    .unwrap();
+    assert_eq!(requeue_interrupted(&conn).unwrap(), 1);
+    let job = dequeue_next(&mut conn).unwrap().unwrap();
+    assert_eq!(job.id, third);
+    assert_eq!(job.attempts, 2);
+}
+
+#[test]
+fn a_runner_can_take_only_its_own_jobs() {
+    let mut conn = Connection::open_in_memory().unwrap();
+    migrate_db(&conn).unwrap();
+    let other = enqueue(&conn, "/videos/a.mkv", None, &settings(&[]), 10).unwrap();
+    let own = enqueue(&conn, "/videos/b.mkv", None, &settings(&[]), 0).unwrap();
+
+    assert_eq!(dequeue_next_of(&mut conn, &[own]).unwrap().unwrap().id, own);
+    assert!(dequeue_next_of(&mut conn, &[own]).unwrap().is_none());
+    assert!(dequeue_next_of(&mut conn, &[]).unwrap().is_none());
+    assert_eq!(
+        get_job(&conn, other).unwrap().unwrap().state,
+        JobState::Queued
+    );
+}
+
+#[test]
+fn settings_round_trip_through_the_command_line() {
+    let args = settings(&[
+        "-s",
+        "3",
+        "--pipe",
+        "--extract",
+        "seek",
+        "--x265params=bframes=4",
+    ]);
+    let parsed = Args::parse_from(std::iter::once(String::from("reve")).chain(args.to_cli_args()));
+    assert_eq!(
+        serde_json::to_string(&parsed).unwrap(),
+        serde_json::to_string(&args).unwrap()
+    );
+}
+
+#[test]
+fn input_is_only_needed_without_a_subcommand() {
+    let args = Args::try_parse_from(["reve", "queue", "list"]).unwrap();
+    assert_eq!(args.inputpath, None);
+    assert!(matches!(
+        run_args(Args::try_parse_from(["reve"]).unwrap()),
+        Err(ReveError::InvalidInput(_))
+    ));
+}
@@ -0,0 +1,140 @@
+use reve_shared::*;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn plan_covers_every_frame_once() {
+    for (frame_count, segment_size) in [
+        (0, 10),
+        (1, 10),
+        (10, 10),
+        (1000, 1000),
+        (2399, 1000),
+        (24000, 7),
+    ] {
+        let segments = plan_segments(frame_count, segment_size);
+        let frames: Vec<u32> = segments
+            .iter()
+            .flat_map(|s| s.start..s.start + s.size)
+            .collect();
+        assert_eq!(frames, (0..frame_count).collect::<Vec<u32>>());
+        assert!(segments
+            .iter()
+            .all(|s| s.size > 0 && s.size <= segment_size));
+        for (i, segment) in segments.iter().enumerate() {
+            assert_eq!(segment.index, i as u32);
+        }
+    }
+}
+
+#[test]
+fn located_segments_seek_to_their_keyframe() {
+    // keyframes every 4 frames at 10 fps, starting at 1.0s
+    let index = FrameIndex {
+        pts: (0..9).map(|n| 1.0 + n as f64 / 10.0).collect(),
+        keyframes: (0..9).map(|n| n % 4 == 0).collect(),
+    };
+    let mut segments = plan_segments(12, 3);
+    index.locate(&mut segments);
+
+    let seeks: Vec<_> = segments.iter().map(|s| s.seek).collect();
+    assert_eq!(seeks[0].unwrap().keyframe, 1.0);
+    assert!(seeks[0].unwrap().after < 1.0);
+    // frame 3 is after the keyframe at frame 0, frame 6 after the one at frame 4
+    assert_eq!(seeks[1].unwrap().keyframe, 1.0);
+    assert!((seeks[2].unwrap().keyframe - 1.4).abs() < 1e-9);
+    assert!((seeks[2].unwrap().after - 1.55).abs() < 1e-9);
+    // frame 9 is past the index
+    assert_eq!(seeks[3], None);
+
+    let segment = &segments[2];
+    let input = segment.input_args(ExtractMode::Exact, "a.mkv", 10.0);
+    let at = input.iter().position(|arg| arg == "-ss").unwrap();
+    assert_eq!(
+        input[at + 1].parse::<f64>().unwrap(),
+        seeks[2].unwrap().keyframe
+    );
+    assert!(input.contains(&String::from("-copyts")));
+    let output = segment.output_args(ExtractMode::Exact, false).join(" ");
+    assert!(output.contains("select='gte(t,"));
+    assert!(output.contains("-vframes 3"));
+
+    // seek mode lands half a frame before the segment
+    let input = segment.input_args(ExtractMode::Seek, "a.mkv", 10.0);
+    assert_eq!(input[..2], [String::from("-ss"), String::from("0.55")]);
+}
+
+#[test]
+#[ignore = "needs ffmpeg and ffprobe"]
+fn exported_segments_have_every_frame_once() {
+    let dir = std::env::temp_dir().join("reve_segment_test");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    // every frame of the counter video is filled with its own frame number
+    let frame_count = 250;
+    let video = dir.join("counter.mkv").display().to_string();
+    let status = Command::new("ffmpeg")
+        .args([
+            "-v",
+            "error",
+            "-f",
+            "lavfi",
+            "-i",
+            "color=c=black:s=32x32:r=30000/1001,format=gray,geq=lum='N'",
+            "-frames:v",
+            &frame_count.to_string(),
+            "-c:v",
+            "ffv1",
+            "-g",
+            "50",
+            &video,
+        ])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // segments of 64 frames start between the keyframes
+    let mut segments = plan_segments(frame_count, 64);
+    let index = index_frames(&video).unwrap();
+    assert_eq!(index.pts.len() as u32, frame_count);
+    index.locate(&mut segments);
+    assert!(segments.iter().all(|s| s.seek.is_some()));
+
+    let mut frames = Vec::new();
+    for segment in segments {
+        let segment_dir = dir.join(segment.index.to_string());
+        fs::create_dir_all(&segment_dir).unwrap();
+        export_frames(
+            &video,
+            &segment_dir.join("frame%08d.png").display().to_string(),
+            &segment,
+            ExtractMode::Exact,
+            30000.0 / 1001.0,
+            None,
+            &NoProgress,
+            &JobControl::new(),
+        )
+        .unwrap();
+
+        let mut files: Vec<_> = fs::read_dir(&segment_dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        files.sort();
+        assert_eq!(files.len() as u32, segment.size);
+        frames.extend(files.iter().map(|f| first_pixel(f)));
+    }
+
+    assert_eq!(frames, (0..frame_count).collect::<Vec<u32>>());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+fn first_pixel(path: &Path) -> u32 {
+    let decoder = png::Decoder::new(fs::File::open(path).unwrap());
+    let mut reader = decoder.read_info().unwrap();
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut buffer).unwrap();
+    buffer[0] as u32
+}
@@ -0,0 +1,261 @@
+#![cfg(unix)]
+
+use reve_shared::*;
+use serde_json::Value;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+struct Server {
+    address: SocketAddr,
+    token: String,
+}
+
+/// Starts a server on a free loopback port whose jobs run `script` instead of reve.
+fn start(name: &str, script: &str) -> (Server, PathBuf) {
+    let dir = std::env::temp_dir().join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let runner = dir.join("runner.sh");
+    fs::write(&runner, format!("#!/bin/sh\n{}\n", script)).unwrap();
+    fs::set_permissions(&runner, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(dir.join("a.mkv"), "").unwrap();
+    fs::write(dir.join("b.mkv"), "").unwrap();
+
+    let server = JobServer::bind("127.0.0.1:0", dir.join("reve.db"), &runner).unwrap();
+    let address = server.local_addr().unwrap();
+    let token = server.token().to_string();
+    server.write_token(&dir.join("server-token")).unwrap();
+    thread::spawn(move || server.run());
+    (Server { address, token }, dir)
+}
+
+fn request(server: &Server, method: &str, path: &str, body: &str) -> (u16, Value) {
+    let headers = format!(
+        "Host: localhost:{}\r\nAuthorization: Bearer {}\r\nContent-Type: application/json\r\n",
+        server.address.port(),
+        server.token
+    );
+    send(server, method, path, &headers, body)
+}
+
+/// Sends a request with only the given headers.
+fn send(server: &Server, method: &str, path: &str, headers: &str, body: &str) -> (u16, Value) {
+    let mut stream = TcpStream::connect(server.address).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\n{}Connection: close\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        headers,
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let status = response[9..12].parse().unwrap();
+    let body = response.split("\r\n\r\n").nth(1).unwrap_or("");
+    (status, serde_json::from_str(body).unwrap_or(Value::Null))
+}
+
+fn submit(server: &Server, input: &Path, args: &str) -> i64 {
+    let body = format!(r#"{{"input": "{}", "args": {}}}"#, input.display(), args);
+    let (status, response) = request(server, "POST", "/jobs", &body);
+    assert_eq!(status, 201, "{}", response);
+    response["ids"][0].as_i64().unwrap()
+}
+
+fn wait_for_state(server: &Server, id: i64, state: &str) -> Value {
+    let start = Instant::now();
+    loop {
+        let (_, job) = request(server, "GET", &format!("/jobs/{}", id), "");
+        if job["state"] == state {
+            return job;
+        }
+        assert!(
+            start.elapsed() < Duration::from_secs(20),
+            "job {} stayed {}",
+            id,
+            job["state"]
+        );
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+fn submitted_jobs_run_with_their_settings() {
+    let (server, dir) = start("reve_server_run", "exit 0");
+
+    let id = submit(&server, &dir.join("a.mkv"), r#"["-s", "4", "-c", "20"]"#);
+    let job = wait_for_state(&server, id, "done");
+    assert_eq!(job["settings"]["scale"], 4);
+    assert_eq!(job["settings"]["crf"], 20);
+    assert_eq!(job["attempts"], 1);
+
+    let (status, jobs) = request(&server, "GET", "/jobs", "");
+    assert_eq!(status, 200);
+    assert_eq!(jobs.as_array().unwrap().len(), 1);
+
+    // settings are validated like on the command line
+    let body = format!(
+        r#"{{"input": "{}", "args": ["-s", "9"]}}"#,
+        dir.join("a.mkv").display()
+    );
+    assert_eq!(request(&server, "POST", "/jobs", &body).0, 400);
+    assert_eq!(request(&server, "GET", "/jobs/999", "").0, 404);
+}
+
+#[test]
+fn running_and_queued_jobs_can_be_cancelled() {
+    let (server, dir) = start("reve_server_cancel", "exec sleep 30");
+
+    let running = submit(&server, &dir.join("a.mkv"), "[]");
+    let queued = submit(&server, &dir.join("b.mkv"), "[]");
+    wait_for_state(&server, running, "running");
+
+    let (status, progress) = request(&server, "GET", "/progress", "");
+    assert_eq!(status, 200);
+    assert_eq!(progress["job"]["id"], running);
+
+    let (status, job) = request(&server, "POST", &format!("/jobs/{}/cancel", queued), "");
+    assert_eq!(status, 200);
+    assert_eq!(job["state"], "cancelled");
+    let (status, job) = request(&server, "DELETE", &format!("/jobs/{}", running), "");
+    assert_eq!(status, 200);
+    assert_eq!(job["state"], "cancelled");
+    assert_eq!(
+        request(&server, "POST", &format!("/jobs/{}/cancel", running), "").0,
+        409
+    );
+
+    // the killed job frees the worker without being marked failed
+    let start = Instant::now();
+    while request(&server, "GET", "/progress", "").1["job"] != Value::Null {
+        assert!(start.elapsed() < Duration::from_secs(20));
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert_eq!(
+        wait_for_state(&server, running, "cancelled")["state"],
+        "cancelled"
+    );
+    assert_eq!(
+        wait_for_state(&server, queued, "cancelled")["state"],
+        "cancelled"
+    );
+}
+
+#[test]
+fn cancel_kills_the_processes_the_runner_started() {
+    let dir = std::env::temp_dir().join("reve_server_kill");
+    let pid_file = dir.join("child.pid");
+    let script = format!("sleep 30 &\necho $! > {}\nwait", pid_file.display());
+    let (server, dir) = start("reve_server_kill", &script);
+
+    let id = submit(&server, &dir.join("a.mkv"), "[]");
+    wait_for_state(&server, id, "running");
+    let start = Instant::now();
+    let pid = loop {
+        if let Some(pid) = fs::read_to_string(&pid_file)
+            .ok()
+            .filter(|pid| pid.ends_with('\n'))
+        {
+            break pid.trim().to_string();
+        }
+        assert!(start.elapsed() < Duration::from_secs(20));
+        thread::sleep(Duration::from_millis(100));
+    };
+
+    assert_eq!(
+        request(&server, "POST", &format!("/jobs/{}/cancel", id), "").0,
+        200
+    );
+    let alive = || {
+        Command::new("kill")
+            .args(["-0", &pid])
+            .stderr(Stdio::null())
+            .status()
+            .unwrap()
+            .success()
+    };
+    let start = Instant::now();
+    while alive() {
+        assert!(
+            start.elapsed() < Duration::from_secs(20),
+            "sleep {} outlived its runner",
+            pid
+        );
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+fn failed_jobs_keep_what_the_runner_said() {
+    let (server, dir) = start("reve_server_fail", "echo 'no video stream' >&2\nexit 3");
+
+    let id = submit(&server, &dir.join("a.mkv"), "[]");
+    let job = wait_for_state(&server, id, "failed");
+    let error = job["error"].as_str().unwrap();
+    assert!(error.starts_with("reve exited with"), "{}", error);
+    assert!(error.ends_with("no video stream"), "{}", error);
+}
+
+#[test]
+fn requests_need_the_token_a_local_host_and_json() {
+    let (server, dir) = start("reve_server_auth", "exit 0");
+    assert_eq!(
+        fs::read_to_string(dir.join("server-token")).unwrap(),
+        server.token
+    );
+    let mode = fs::metadata(dir.join("server-token"))
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let port = server.address.port();
+    let body = format!(r#"{{"input": "{}"}}"#, dir.join("a.mkv").display());
+    let host = format!("Host: 127.0.0.1:{}\r\n", port);
+    let token = format!("Authorization: Bearer {}\r\n", server.token);
+    let json = "Content-Type: application/json\r\n";
+
+    let headers = format!("{}{}", host, json);
+    assert_eq!(send(&server, "GET", "/jobs", &headers, "").0, 401);
+    let headers = format!("{}Authorization: Bearer nope\r\n", host);
+    assert_eq!(send(&server, "GET", "/jobs", &headers, "").0, 401);
+    // a page of evil.example whose name resolves to 127.0.0.1
+    let headers = format!("Host: evil.example:{}\r\n{}{}", port, token, json);
+    assert_eq!(send(&server, "POST", "/jobs", &headers, &body).0, 403);
+    let headers = format!("Host: localhost:1\r\n{}", token);
+    assert_eq!(send(&server, "GET", "/jobs", &headers, "").0, 403);
+    // a form post can't set the content type
+    let headers = format!("{}{}Content-Type: text/plain\r\n", host, token);
+    assert_eq!(send(&server, "POST", "/jobs", &headers, &body).0, 415);
+
+    let headers = format!("{}{}", host, token);
+    assert_eq!(send(&server, "GET", "/jobs", &headers, "").0, 200);
+    let headers = format!("{}{}{}", host, token, json);
+    assert_eq!(send(&server, "POST", "/jobs", &headers, &body).0, 201);
+}
+
+#[test]
+fn requests_cant_pick_commands_or_folders() {
+    let (server, dir) = start("reve_server_args", "exit 0");
+    let input = dir.join("a.mkv");
+
+    for args in [
+        r#"["--upscaler", "sh -c 'rm -rf ~' {input_dir} {output_dir}"]"#,
+        r#"["--workdir", "/home"]"#,
+    ] {
+        let body = format!(r#"{{"input": "{}", "args": {}}}"#, input.display(), args);
+        let (status, response) = request(&server, "POST", "/jobs", &body);
+        assert_eq!(status, 400, "{}", args);
+        assert!(response["error"].is_string());
+    }
+    submit(&server, &input, r#"["--upscaler", "realcugan"]"#);
+}
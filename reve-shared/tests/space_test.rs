@@ -0,0 +1,107 @@
+use reve_shared::*;
+
+fn estimate(segment_size: u32) -> SpaceEstimate {
+    let spec = JobSpec::new("/videos/a.mkv")
+        .scale(4)
+        .segment_size(segment_size);
+    SpaceEstimate::new(&spec, 640, 480, u32::MAX, 100_000_000)
+}
+
+#[test]
+fn segment_size_fits_the_frames_next_to_the_parts() {
+    let estimate = estimate(1000);
+    assert_eq!(estimate.segment_size_for(estimate.total()), 1000);
+    assert_eq!(
+        estimate.segment_size_for(estimate.parts + estimate.frames / 4),
+        250
+    );
+    assert_eq!(estimate.segment_size_for(estimate.parts), 0);
+}
+
+#[test]
+#[cfg_attr(not(unix), ignore = "needs statvfs")]
+fn a_full_workspace_refuses_to_start_and_suggests_parts() {
+    let dir = std::env::temp_dir().join("reve-space-test");
+    let workspace = Workspace::new(&dir);
+    let output = dir.join("a.libx265.mkv").display().to_string();
+    let fs = filesystem(&dir).unwrap();
+
+    assert!(check_space(&workspace, &output, &estimate(1), false).is_ok());
+
+    // a segment size whose frames need twice the free space
+    let huge = estimate(1);
+    let segment_size = (fs.available / huge.frame_pair) as u32;
+    match check_space(&workspace, &output, &estimate(segment_size), false) {
+        Err(ReveError::NoSpace {
+            needed,
+            available,
+            parts: Some(parts),
+            ..
+        }) => {
+            assert!(needed > available);
+            assert!(parts < segment_size / 2 + 1);
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+    let piped = JobSpec::new("/videos/a.mkv")
+        .scale(4)
+        .pipe(true, segment_size);
+    let piped = SpaceEstimate::new(&piped, 640, 480, u32::MAX, 100_000_000);
+    assert!(matches!(
+        check_space(&workspace, &output, &piped, true),
+        Err(ReveError::NoSpace { parts: None, .. })
+    ));
+}
+
+#[test]
+fn no_space_tells_what_to_change() {
+    let error = ReveError::NoSpace {
+        path: String::from("/dev/shm/reve"),
+        needed: 18_400_000_000,
+        available: 15_900_000_000,
+        parts: Some(400),
+    };
+    assert_eq!(
+        error.to_string(),
+        "not enough space in /dev/shm/reve: about 18.4 GB needed, 15.9 GB free, use --parts 400 or another --workdir"
+    );
+}
+
+#[test]
+#[cfg_attr(not(unix), ignore = "needs statvfs")]
+fn parts_auto_fills_part_of_the_free_space() {
+    let dir = std::env::temp_dir().join("reve-space-auto-test");
+    let workspace = Workspace::new(&dir);
+    let output = dir.join("a.libx265.mkv").display().to_string();
+    let fs = filesystem(&dir).unwrap();
+    let estimate = estimate(0);
+
+    let segment_size = auto_segment_size(&workspace, &output, &estimate, u32::MAX, false).unwrap();
+    let fits = estimate.segment_size_for(fs.available);
+    assert!(segment_size > 0 && segment_size < fits);
+    assert!(check_space(
+        &workspace,
+        &output,
+        &SpaceEstimate::new(
+            &JobSpec::new("/videos/a.mkv")
+                .scale(4)
+                .segment_size(segment_size),
+            640,
+            480,
+            u32::MAX,
+            100_000_000
+        ),
+        false
+    )
+    .is_ok());
+
+    // never longer than the video, and the default in pipe mode
+    assert_eq!(
+        auto_segment_size(&workspace, &output, &estimate, 1, false).unwrap(),
+        1
+    );
+    assert_eq!(
+        auto_segment_size(&workspace, &output, &estimate, u32::MAX, true).unwrap(),
+        1000
+    );
+}
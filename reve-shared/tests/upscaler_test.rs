@@ -0,0 +1,95 @@
+use reve_shared::*;
+
+fn args(upscaler: &str, model: &str) -> Vec<String> {
+    get_upscaler(upscaler, "done")
+        .unwrap()
+        .command("in", "out", 2, model)
+        .get_args()
+        .map(|arg| arg.to_string_lossy().to_string())
+        .collect()
+}
+
+#[test]
+fn ncnn_backends_load_the_model_folder() {
+    for upscaler in ["realcugan", "waifu2x"] {
+        let with_model = args(upscaler, "models-se");
+        let at = with_model.iter().position(|arg| arg == "-m").unwrap();
+        assert_eq!(with_model[at + 1], "models-se");
+        // the default model only exists for realesrgan
+        assert!(!args(upscaler, REALESRGAN_MODEL).contains(&String::from("-m")));
+    }
+}
+
+fn strings<'a>(args: impl Iterator<Item = &'a std::ffi::OsStr>) -> Vec<String> {
+    args.map(|arg| arg.to_string_lossy().to_string()).collect()
+}
+
+#[test]
+fn built_in_backends_look_up_their_program_the_same_way() {
+    // none of them is next to the test executable, so all of them come from PATH
+    for (upscaler, program) in [
+        ("realesrgan", "realesrgan-ncnn-vulkan"),
+        ("realcugan", "realcugan-ncnn-vulkan"),
+        ("waifu2x", "waifu2x-ncnn-vulkan"),
+    ] {
+        let command =
+            get_upscaler(upscaler, "done")
+                .unwrap()
+                .command("in", "out", 2, REALESRGAN_MODEL);
+        assert_eq!(command.get_program(), program);
+    }
+}
+
+#[test]
+fn templates_substitute_their_placeholders() {
+    let upscaler = CommandTemplate::parse(
+        "custom",
+        "upscale -i {input_dir} -o {output_dir}  -n {model}-x{scale}",
+        r"^\d+ done$",
+    )
+    .unwrap();
+    assert!(upscaler.supports_folders());
+    assert!(upscaler.stream_command(64, 48, 2, "anime").is_none());
+
+    let command = upscaler.command("frames in", "out", 2, "anime");
+    assert_eq!(command.get_program(), "upscale");
+    // split before substituting, a folder with a space stays one argument
+    assert_eq!(
+        strings(command.get_args()),
+        ["-i", "frames in", "-o", "out", "-n", "anime-x2"]
+    );
+    assert!(upscaler.is_progress_line("12 done"));
+    assert!(!upscaler.is_progress_line("12 done, 3 left"));
+}
+
+#[test]
+fn templates_with_a_frame_size_stream() {
+    let upscaler =
+        CommandTemplate::parse("stream", "upscale --size {width}x{height} -s {scale}", "").unwrap();
+    assert!(!upscaler.supports_folders());
+
+    let command = upscaler.stream_command(64, 48, 4, "anime").unwrap();
+    assert_eq!(command.get_program(), "upscale");
+    assert_eq!(strings(command.get_args()), ["--size", "64x48", "-s", "4"]);
+}
+
+#[test]
+fn malformed_templates_are_rejected() {
+    for (template, progress) in [
+        ("", ""),
+        ("   ", ""),
+        // the program alone doesn't count
+        ("{input_dir} {output_dir}", ""),
+        ("upscale {input_dir}", ""),
+        ("upscale {width}", ""),
+        ("upscale {input_dir} {output_dir}", "(unclosed"),
+    ] {
+        match CommandTemplate::parse("custom", template, progress) {
+            Err(ReveError::InvalidInput(_)) => (),
+            Err(e) => panic!("{:?}: {}", template, e),
+            Ok(_) => panic!("{:?} was accepted", template),
+        }
+    }
+    // a name that is neither a built-in nor a template
+    assert!(get_upscaler("esrgan", "done").is_err());
+}
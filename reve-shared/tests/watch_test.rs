@@ -0,0 +1,51 @@
+use reve_shared::*;
+use std::fs;
+use std::io::Write;
+
+#[test]
+fn files_are_ready_once_they_stop_growing() {
+    let dir = std::env::temp_dir().join("reve_watch_test");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let inbox = dir.display().to_string();
+    let episode = dir.join("episode.mkv");
+    let copying = dir.join("copying.mp4");
+    fs::write(&episode, "complete").unwrap();
+    fs::write(dir.join("notes.txt"), "not a video").unwrap();
+
+    let mut watcher = FolderWatcher::new(&inbox);
+    assert!(watcher.poll().is_empty());
+
+    fs::write(&copying, "part").unwrap();
+    assert_eq!(watcher.poll(), vec![episode.display().to_string()]);
+
+    // still being written
+    fs::OpenOptions::new()
+        .append(true)
+        .open(&copying)
+        .unwrap()
+        .write_all(b" two")
+        .unwrap();
+    assert!(watcher.poll().is_empty());
+    assert_eq!(watcher.poll(), vec![copying.display().to_string()]);
+
+    // every file is handed out once
+    assert!(watcher.poll().is_empty());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn upscales_written_into_the_folder_are_ignored() {
+    let dir = std::env::temp_dir().join("reve_watch_ignore_test");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let episode = dir.join("episode.mkv");
+    fs::write(&episode, "complete").unwrap();
+    fs::write(dir.join("episode.libx265.mkv"), "upscaled").unwrap();
+
+    let mut watcher = FolderWatcher::new(&dir.display().to_string()).ignore(".libx265.mkv");
+    assert!(watcher.poll().is_empty());
+    assert_eq!(watcher.poll(), vec![episode.display().to_string()]);
+    assert!(watcher.poll().is_empty());
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,48 @@
+use reve_shared::*;
+use std::fs;
+use std::process::{Command, Stdio};
+
+#[test]
+fn a_workspace_is_used_by_one_upscale_at_a_time() {
+    let dir = std::env::temp_dir().join("reve-workspace-lock-test");
+    let _ = fs::remove_dir_all(&dir);
+    let workspace = Workspace::new(&dir);
+
+    let lock = workspace.lock().unwrap();
+    match workspace.lock() {
+        Err(ReveError::WorkspaceBusy { pid, .. }) => assert_eq!(pid, std::process::id()),
+        other => panic!("locked twice: {:?}", other),
+    }
+    // the pipeline gives up before touching the files of the other upscale
+    let input = dir.join("missing.mkv").display().to_string();
+    let spec = JobSpec::new(&input).workdir(&dir.display().to_string());
+    assert!(matches!(
+        Pipeline::new(spec).run(),
+        Err(ReveError::WorkspaceBusy { .. })
+    ));
+
+    drop(lock);
+    assert!(!workspace.lock_file().exists());
+    drop(workspace.lock().unwrap());
+}
+
+#[test]
+fn the_lock_of_a_dead_process_is_taken_over() {
+    let dir = std::env::temp_dir().join("reve-workspace-stale-test");
+    let _ = fs::remove_dir_all(&dir);
+    let workspace = Workspace::new(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let mut child = Command::new("cargo")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    child.wait().unwrap();
+    fs::write(workspace.lock_file(), child.id().to_string()).unwrap();
+
+    let _lock = workspace.lock().unwrap();
+    assert_eq!(
+        fs::read_to_string(workspace.lock_file()).unwrap(),
+        std::process::id().to_string()
+    );
+}